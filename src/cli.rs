@@ -1,177 +1,1078 @@
 use crate::{pro, icegui};
-use core::panic;
+use crate::config::Config;
 use getopts::Options;
 use libc::{self, pid_t};
 use std::fs;
-use std::io;
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
 use std::process::exit;
 use std::time::Duration;
 
-pub fn make_opts() -> Options {
-  let mut opts = Options::new();
-  opts.optflag("", "gui", "Open GUI");
-  opts.optopt("r", "refresh_rate", "Stats refresh rate", "[NUM]");
+// name, operands, description
+const COMMANDS: [(&str, &str, &str); 15] = [
+  ("list", "", "Print the processes once"),
+  ("top", "", "Keep printing system stats and processes"),
+  ("record", "", "Record snapshots to a file for replay"),
+  ("replay", "FILE...", "Replay recorded snapshots in the TUI"),
+  ("diff", "[FILE...]", "Compare two snapshots, recorded or live"),
+  ("serve", "", "Export metrics for Prometheus over HTTP"),
+  ("daemon", "", "Accept JSON requests on a Unix socket"),
+  ("watch", "", "Renice, kill or pin processes as policies say"),
+  ("tree", "", "Print the process tree"),
+  ("kill", "[PID...]", "Send a signal to processes"),
+  ("renice", "[PID...]", "Change the priority of processes"),
+  ("affinity", "[PID...]", "Pin processes to a set of cpus"),
+  ("info", "PID", "Print everything we know about one process"),
+  ("tui", "", "Open the terminal UI, the default without arguments"),
+  ("gui", "", "Open the GUI"),
+];
+
+fn source_opts(opts: &mut Options) {
+  opts.optopt("", "root", "Read /proc and /etc under this directory", "[DIR]");
+  opts.optopt("", "proc_root", "Read procfs from this directory", "[DIR]");
+}
+
+// Options that pick and order processes, see `Selection`
+const SELECTION_OPTS: [&str; 7] = [
+  "nprocs",
+  "sort_by",
+  "descending",
+  "filter_by",
+  "pattern",
+  "exact_match",
+  "where",
+];
+
+fn selection_opts(opts: &mut Options) {
   opts.optopt("n", "nprocs", "Max number of processes to show", "[NUM]");
-  opts.optopt("k", "kill", "Kill process with signal", "[SIG]");
-  opts.optopt("i", "iters", "Number of iterations", "[NUM]");
-  opts.optflag("h", "help", "Print help message");
-  opts.optopt("p", "priority", "Priority of process", "[PRIO]");
-  opts.optopt("o", "output", "output file (logs)", "[FILE]");
-  opts.optopt("", "pid", "pid of process", "[PID]");
   opts.optopt(
     "s",
     "sort_by",
     "How to sort the processes",
-    "[name|pid|memory|priority|user|state|threads|vmsize|utime|stime]",
+    &format!("[{}]", pro::Field::names()),
   );
-  opts.optopt("f", "filter_by", "Filter by", "[name|user|ppid|state]");
+  opts.optflag("d", "descending", "Sort in descending order");
+  filter_opts(opts);
+}
+
+fn filter_opts(opts: &mut Options) {
+  opts.optopt("f", "filter_by", "Filter by", &format!("[any|{}]", pro::Field::names()));
   opts.optopt("", "pattern", "Pattern to filter by", "[PATTERN]");
   opts.optflag("e", "exact_match", "The pattern should be an exact match");
-  opts.optflag("d", "descending", "Sort in descending order");
+  opts.optopt(
+    "w",
+    "where",
+    "Filter expression, e.g. 'user=root and (mem>500M or cpu>50)'",
+    "[EXPR]",
+  );
+}
+
+fn tree_opts(opts: &mut Options) {
+  opts.optopt("", "from", "Print the tree below this process (not --root, which names the source dir)", "[PID]");
+  opts.optopt(
+    "",
+    "fields",
+    "Extra fields to print for each process, e.g. user,memory",
+    &format!("[{}]", pro::Field::names()),
+  );
+}
+
+fn config_opts(opts: &mut Options) {
+  opts.optopt(
+    "",
+    "config",
+    "Config file, $XDG_CONFIG_HOME/procstat/config.toml by default",
+    "[FILE]",
+  );
+}
+
+fn top_opts(opts: &mut Options) {
+  opts.optopt("r", "refresh_rate", "Stats refresh rate", "[NUM]");
+  opts.optopt("i", "iters", "Number of iterations", "[NUM]");
+  opts.optopt("o", "output", "output file (logs)", "[FILE]");
+  opts.optopt("", "rules", "Alert rules to evaluate on every refresh", "[FILE]");
+}
+
+/// The options of the flat command line from before subcommands, still accepted without a command.
+pub fn make_opts() -> Options {
+  let mut opts = Options::new();
+  opts.optflag("", "gui", "Open GUI");
+  opts.optopt("k", "kill", "Send signal, e.g. 9, KILL or SIGTERM", "[SIG]");
+  opts.optflag("h", "help", "Print help message");
+  opts.optopt("p", "priority", "Priority of process", "[PRIO]");
+  opts.optopt("", "pid", "pid of process", "[PID]");
   opts.optopt("c", "cpu_affinity", "List of cpus", "[CPU]");
   opts.optflag("a", "all", "Execute on all output processes");
   opts.optflag("t", "tree", "print process tree");
+  opts.optflag("l", "full_command", "Show the full command line instead of the name");
+  opts.optopt(
+    "",
+    "format",
+    "Output format, json and csv print one snapshot unless --iters is given",
+    "[table|json|csv|ndjson]",
+  );
+  top_opts(&mut opts);
+  selection_opts(&mut opts);
+  tree_opts(&mut opts);
+  source_opts(&mut opts);
+  config_opts(&mut opts);
   opts
 }
 
-pub fn read_opts() -> getopts::Matches {
-  let args: Vec<String> = std::env::args().collect();
-  let opts = make_opts();
-  let matches = match opts.parse(&args[1..]) {
-    Ok(m) => m,
-    Err(f) => {
-      panic!("{}", f);
-    }
-  };
+fn command_opts() -> Options {
+  let mut opts = Options::new();
+  opts.optflag("h", "help", "Print help message");
+  source_opts(&mut opts);
+  opts
+}
+
+// Parses the options of `command`, printing its help and exiting on -h
+fn parse_command(program: &str, command: &str, opts: &Options, args: &[String]) -> pro::Result<getopts::Matches> {
+  let matches = opts
+    .parse(args)
+    .map_err(|f| pro::Error::InvalidArgument(format!("{}: {}", command, f)))?;
+  let (_, operands, description) = COMMANDS
+    .iter()
+    .find(|(name, _, _)| *name == command)
+    .copied()
+    .unwrap_or_default();
 
   if matches.opt_present("h") {
-    pro::print_usage(&args[0], opts);
+    let brief = format!(
+      "Usage: {} {} [options] {}\n\n{}",
+      program, command, operands, description
+    );
+    print!("{}", opts.usage(&brief));
     exit(0);
   }
+  if operands.is_empty() {
+    if let Some(arg) = matches.free.first() {
+      return Err(pro::Error::InvalidArgument(format!(
+        "{}: unexpected argument '{}'",
+        command, arg
+      )));
+    }
+  }
+  Ok(matches)
+}
 
-  matches
+fn print_help(program: &str) {
+  println!("Usage: {} [COMMAND] [options]\n\nCommands:", program);
+  for (name, operands, description) in COMMANDS {
+    println!("  {:<20}{}", format!("{} {}", name, operands), description);
+  }
+  println!(
+    "\nRun '{} COMMAND -h' for the options of a command. Without arguments the TUI is opened.\n\
+     The flat options of older versions (e.g. '-t' or '--pid 42 -k 9') still work but are deprecated.",
+    program
+  );
 }
 
-pub fn run() -> io::Result<()> {
-  let matches = read_opts();
-  if matches.opt_present("gui") {
-    let _ = icegui::run();
-    return Ok(());
+/// Exit status for each kind of failure, so wrapper scripts can tell them apart.
+pub fn exit_code(error: &pro::Error) -> i32 {
+  match error {
+    pro::Error::Io(_) => 1,
+    pro::Error::InvalidArgument(_) | pro::Error::UnknownField(_) => 2,
+    pro::Error::Parse { .. } => 3,
+    pro::Error::PermissionDenied(_) => 4,
+    pro::Error::ProcessVanished(_) => 5,
   }
+}
 
-  let pid_p = matches.opt_present("pid");
-  let all_p = matches.opt_present("a");
-  let refresh_rate = matches
-    .opt_get_default::<u64>("r", 1)
-    .expect("Invalid refresh rate value");
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+  Table,
+  Json,
+  Csv,
+  Ndjson,
+}
 
-  let nprocs = matches
-    .opt_get_default::<usize>("n", usize::MAX)
-    .expect("Invalid nprocs value");
+impl std::str::FromStr for Format {
+  type Err = pro::Error;
 
-  let iterations = matches
-    .opt_get_default::<u32>("i", 0)
-    .expect("Invalid iterations value");
+  fn from_str(s: &str) -> pro::Result<Self> {
+    match s {
+      "table" => Ok(Format::Table),
+      "json" => Ok(Format::Json),
+      "csv" => Ok(Format::Csv),
+      "ndjson" => Ok(Format::Ndjson),
+      _ => Err(pro::Error::InvalidArgument(format!(
+        "invalid value for --format: '{}', expected table, json, csv or ndjson",
+        s
+      ))),
+    }
+  }
+}
 
-  let sort_by = matches
-    .opt_get_default::<String>("s", "pid".to_string())
-    .expect("Invalid sort_by value");
+fn opt_or<T: std::str::FromStr>(matches: &getopts::Matches, name: &str, default: T) -> pro::Result<T> {
+  matches.opt_get_default(name, default).map_err(|_| {
+    pro::Error::InvalidArgument(format!(
+      "invalid value for --{}: '{}'",
+      name,
+      matches.opt_str(name).unwrap_or_default()
+    ))
+  })
+}
 
-  let filter_by = matches
-    .opt_get_default::<String>("f", "".to_string())
-    .expect("Invalid filter_by value");
+pub fn run() {
+  if let Err(e) = try_run() {
+    eprintln!("error: {}", e);
+    exit(exit_code(&e));
+  }
+}
 
-  let pattern = matches
-    .opt_get_default::<String>("pattern", "".to_string())
-    .expect("Invalid pattern value");
+fn try_run() -> pro::Result<()> {
+  let args: Vec<String> = std::env::args().collect();
+  let program = &args[0];
+  let rest = args.get(2..).unwrap_or_default();
+
+  match args.get(1).map(String::as_str).unwrap_or("tui") {
+    "help" | "-h" | "--help" => {
+      print_help(program);
+      Ok(())
+    }
+    "list" => list_command(program, rest),
+    "top" => top_command(program, rest),
+    "record" => record_command(program, rest),
+    "replay" => replay_command(program, rest),
+    "diff" => diff_command(program, rest),
+    "serve" => serve_command(program, rest),
+    "daemon" => daemon_command(program, rest),
+    "watch" => watch_command(program, rest),
+    "tree" => tree_command(program, rest),
+    "kill" => kill_command(program, rest),
+    "renice" => renice_command(program, rest),
+    "affinity" => affinity_command(program, rest),
+    "info" => info_command(program, rest),
+    "tui" => {
+      let mut opts = command_opts();
+      config_opts(&mut opts);
+      opts.optopt("", "rules", "Alert rules to evaluate on every refresh", "[FILE]");
+      let matches = parse_command(program, "tui", &opts, rest)?;
+      let rules = match matches.opt_str("rules") {
+        Some(path) => pro::alert::load_rules(std::path::Path::new(&path))?,
+        None => Vec::new(),
+      };
+      let (config, config_path) = read_config(&matches)?;
+      Ok(crate::tui::run(read_source(&matches), rules, config, config_path)?)
+    }
+    "gui" => {
+      let mut opts = command_opts();
+      config_opts(&mut opts);
+      let matches = parse_command(program, "gui", &opts, rest)?;
+      let _ = icegui::run(read_source(&matches), read_config(&matches)?.0);
+      Ok(())
+    }
+    arg if arg.starts_with('-') => run_deprecated(program, &args[1..]),
+    command => Err(pro::Error::InvalidArgument(format!(
+      "unknown command '{}', see '{} help'",
+      command, program
+    ))),
+  }
+}
+
+fn read_source(matches: &getopts::Matches) -> pro::ProcSource {
+  let mut source = pro::ProcSource::new(matches.opt_str("root").unwrap_or("/".to_string()));
+  if let Some(proc_root) = matches.opt_str("proc_root") {
+    source = source.with_proc_root(proc_root);
+  }
+  source
+}
+
+fn read_config(matches: &getopts::Matches) -> pro::Result<(Config, std::path::PathBuf)> {
+  Config::load(matches.opt_str("config").as_deref().map(std::path::Path::new))
+}
+
+/// Which processes to show or act on, and in which order.
+struct Selection {
+  nprocs: usize,
+  sort_by: pro::Field,
+  descending: bool,
+  filter: Option<pro::Filter>,
+}
+
+impl Selection {
+  fn from_matches(matches: &getopts::Matches) -> pro::Result<Self> {
+    Self::with_defaults(matches, &Config::default())
+  }
+
+  // Falls back to the sort order and filter of `config`, for commands that only look
+  fn with_defaults(matches: &getopts::Matches, config: &Config) -> pro::Result<Self> {
+    Ok(Selection {
+      nprocs: opt_or(matches, "nprocs", usize::MAX)?,
+      sort_by: match matches.opt_str("sort_by") {
+        Some(sort_by) => sort_by.parse()?,
+        None => config.sort_by,
+      },
+      descending: matches.opt_present("descending") || config.descending,
+      filter: match Self::filter_from_matches(matches)? {
+        Some(filter) => Some(filter),
+        None => config.filter(),
+      },
+    })
+  }
+
+  // Needs only the options of `filter_opts`
+  fn filter_from_matches(matches: &getopts::Matches) -> pro::Result<Option<pro::Filter>> {
+    let mut filter = match matches.opt_str("filter_by") {
+      Some(filter_by) => Some(pro::Filter::new(
+        &filter_by,
+        &matches.opt_str("pattern").unwrap_or_default(),
+        matches.opt_present("exact_match"),
+      )?),
+      None if matches.opt_present("pattern") => {
+        return Err(pro::Error::InvalidArgument("--pattern needs --filter_by".to_string()))
+      }
+      None => None,
+    };
+    if let Some(expr) = matches.opt_str("where") {
+      let query = expr.parse()?;
+      match &mut filter {
+        Some(filter) => filter.query = Some(query),
+        None => filter = Some(pro::Filter::with_query(query)),
+      }
+    }
+    Ok(filter)
+  }
+
+  fn list(&self, source: &pro::ProcSource) -> pro::Result<Vec<pro::ProcessInfo>> {
+    // CPU% is a delta, so the list comes from a second sample, like `info`
+    let mut counters = pro::Counters::default();
+    counters.read_processes(source)?;
+    std::thread::sleep(Duration::from_millis(500));
+    pro::list_processes(
+      counters.read_processes(source)?,
+      0,
+      self.nprocs,
+      self.sort_by,
+      !self.descending,
+      self.filter.as_ref(),
+    )
+  }
+}
+
+fn read_format(matches: &getopts::Matches) -> pro::Result<Format> {
+  opt_or(matches, "format", Format::Table)
+}
+
+fn list_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  selection_opts(&mut opts);
+  opts.optflag("l", "full_command", "Show the full command line instead of the name");
+  opts.optopt("", "format", "Output format", "[table|json|csv|ndjson]");
+  config_opts(&mut opts);
+  let matches = parse_command(program, "list", &opts, args)?;
+
+  let (config, _) = read_config(&matches)?;
+  let processes = Selection::with_defaults(&matches, &config)?.list(&read_source(&matches))?;
+  let output = match read_format(&matches)? {
+    Format::Table => pro::format_process_table(&processes, matches.opt_present("l")),
+    Format::Json => serde_json::to_string_pretty(&processes).map_err(io::Error::from)? + "\n",
+    Format::Csv => format_csv(&processes),
+    Format::Ndjson => {
+      let mut output = String::new();
+      for process in &processes {
+        output.push_str(&serde_json::to_string(process).map_err(io::Error::from)?);
+        output.push('\n');
+      }
+      output
+    }
+  };
+  io::stdout().write_all(output.as_bytes())?;
+  Ok(())
+}
+
+fn format_csv(processes: &[pro::ProcessInfo]) -> String {
+  let mut csv = pro::ProcessInfo::CSV_HEADER.to_string();
+  csv.push('\n');
+  for process in processes {
+    csv.push_str(&process.to_csv());
+    csv.push('\n');
+  }
+  csv
+}
+
+struct TopOptions {
+  refresh_rate: u64,
+  iterations: u32,
+  output: String,
+  format: Format,
+  full_command: bool,
+  rules: Option<std::path::PathBuf>,
+}
 
-  let descending = matches.opt_present("d");
+impl TopOptions {
+  fn from_matches(matches: &getopts::Matches, config: &Config) -> pro::Result<Self> {
+    let format = read_format(matches)?;
+    // snapshot formats print once by default, table and ndjson keep streaming
+    let default_iterations = if matches!(format, Format::Json | Format::Csv) {
+      1
+    } else {
+      0
+    };
+    Ok(TopOptions {
+      refresh_rate: opt_or(matches, "refresh_rate", config.refresh_rate)?,
+      iterations: opt_or(matches, "iters", default_iterations)?,
+      output: match matches.opt_str("output") {
+        Some(output) => output,
+        None => config.log.display().to_string(),
+      },
+      format,
+      full_command: matches.opt_present("full_command"),
+      rules: matches.opt_str("rules").map(std::path::PathBuf::from),
+    })
+  }
+}
 
-  let output_file = matches
-    .opt_get_default::<String>("o", "/tmp/procstat.log".to_string())
-    .expect("Invalid output file value");
+fn top_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  top_opts(&mut opts);
+  selection_opts(&mut opts);
+  opts.optflag("l", "full_command", "Show the full command line instead of the name");
+  opts.optopt(
+    "",
+    "format",
+    "Output format, json and csv print one snapshot unless --iters is given",
+    "[table|json|csv|ndjson]",
+  );
+  config_opts(&mut opts);
+  let matches = parse_command(program, "top", &opts, args)?;
+  let (config, _) = read_config(&matches)?;
+  run_top(
+    &read_source(&matches),
+    &Selection::with_defaults(&matches, &config)?,
+    &TopOptions::from_matches(&matches, &config)?,
+  )
+}
 
+fn run_top(source: &pro::ProcSource, selection: &Selection, top: &TopOptions) -> pro::Result<()> {
   let mut current_iteration = 0;
   let mut log_file = fs::OpenOptions::new()
     .create(true)
     .truncate(true)
     .write(true)
-    .open(output_file)
-    .expect("Failed to open log file");
-  let exact_match = matches.opt_present("e");
-
-  if pid_p || all_p {
-    let mut pids: Vec<pid_t> = Vec::new();
-    let pid = matches.opt_get_default::<pid_t>("pid", 0).expect("Invalid pid value");
-    if all_p {
-      pids = pro::list_processes(
-        pro::read_processes().unwrap(),
-        0,
-        nprocs,
-        &sort_by,
-        !descending,
-        &filter_by,
-        &pattern,
-        exact_match,
-      )
-      .unwrap()
-      .iter()
-      .map(|p| p.pid)
-      .collect();
-    } else {
-      pids.push(pid);
-    }
-    if matches.opt_present("k") {
-      let kill_signal = matches
-        .opt_get_default::<i32>("k", libc::SIGKILL)
-        .expect("Invalid signal value");
-      pro::execute_on_with_arg(pids, kill_signal, pro::kill_process, None);
-    } else if matches.opt_present("p") {
-      let priority = matches.opt_get_default::<i32>("p", 0).expect("Invalid priority value");
-      pro::execute_on_with_arg(pids, priority, pro::set_priority, None);
-    } else if matches.opt_present("c") {
-      let cpu_list: Vec<usize> = matches
-        .opt_get_default::<String>("c", "".to_string())
-        .iter()
-        .map(|arg| arg.parse::<usize>().expect("Invalid CPU value"))
-        .collect();
-      pro::execute_on_with_args(pids, &cpu_list, pro::bind_to_cpu_set, None);
+    .open(&top.output)?;
+  let mut alerts = match &top.rules {
+    Some(path) => Some(pro::alert::Alerts::new(pro::alert::load_rules(path)?)),
+    None => None,
+  };
+  let mut collector = pro::sampler::Collector::new(source.clone());
+  // usage is a delta, so snapshot formats warm up rather than print a first sample without CPU
+  if matches!(top.format, Format::Json | Format::Csv) {
+    collector.collect()?;
+    std::thread::sleep(Duration::from_millis(500));
+  }
+
+  while top.iterations == 0 || current_iteration != top.iterations {
+    if current_iteration > 0 {
+      std::thread::sleep(Duration::from_secs(top.refresh_rate));
     }
-    return Ok(());
+    current_iteration += 1;
+
+    // rules look at every process, the output only at the selected ones
+    let mut snapshot = collector.collect()?.snapshot;
+    let mut firing = String::new();
+    if let Some(alerts) = &mut alerts {
+      for event in alerts.update(&pro::alert::Sample::from(&snapshot)) {
+        if let Err(e) = alerts.notify(&event) {
+          eprintln!("error: {}: {}", event.rule, e);
+        }
+        if top.format != Format::Table {
+          eprintln!("{}", event.message);
+        }
+      }
+      for alert in alerts.firing() {
+        firing.push_str(&format!("ALERT {}\n", alert.message));
+      }
+    }
+    snapshot.processes = pro::list_processes(
+      std::mem::take(&mut snapshot.processes),
+      0,
+      selection.nprocs,
+      selection.sort_by,
+      !selection.descending,
+      selection.filter.as_ref(),
+    )?;
+    let output = match top.format {
+      Format::Table => firing + &pro::format_stats(&snapshot, top.full_command),
+      Format::Json => serde_json::to_string_pretty(&snapshot).map_err(io::Error::from)?,
+      Format::Ndjson => serde_json::to_string(&snapshot).map_err(io::Error::from)?,
+      Format::Csv => format_csv(&snapshot.processes),
+    };
+
+    let mut stdout = io::stdout();
+    match top.format {
+      // Clear screen and display all at once
+      Format::Table => write!(stdout, "{esc}[2J{esc}[1;1H{}", output, esc = 27 as char)?,
+      Format::Csv => write!(stdout, "{}", output)?,
+      // scripts often stop reading early, so a closed pipe is an error rather than a panic
+      _ => writeln!(stdout, "{}", output)?,
+    }
+    stdout.flush()?;
+    writeln!(log_file, "{}", output)?;
   }
 
-  if matches.opt_present("t") {
-    pro::build_tree(
-      &pro::list_processes(
-        pro::read_processes().unwrap(),
-        0,
-        nprocs,
-        &sort_by,
-        !descending,
-        &filter_by,
-        &pattern,
-        exact_match,
-      )
-      .unwrap(),
+  Ok(())
+}
+
+fn record_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  selection_opts(&mut opts);
+  opts.optopt("r", "refresh_rate", "Seconds between snapshots", "[NUM]");
+  opts.optopt(
+    "i",
+    "iters",
+    "Number of snapshots, 0 records until interrupted",
+    "[NUM]",
+  );
+  opts.optopt("o", "output", "Recording file", "[FILE]");
+  opts.optopt(
+    "",
+    "max_size",
+    "Rotate the recording past this size, e.g. 50M",
+    "[SIZE]",
+  );
+  opts.optopt("", "keep", "Number of rotated recordings to keep", "[NUM]");
+  config_opts(&mut opts);
+  let matches = parse_command(program, "record", &opts, args)?;
+
+  let source = read_source(&matches);
+  let (config, _) = read_config(&matches)?;
+  let selection = Selection::with_defaults(&matches, &config)?;
+  let refresh_rate = opt_or(&matches, "refresh_rate", config.refresh_rate)?;
+  let iterations: u32 = opt_or(&matches, "iters", 0)?;
+  let max_bytes = match matches.opt_str("max_size") {
+    Some(size) => pro::query::parse_size(&size)
+      .map(|bytes| bytes as u64)
+      .ok_or_else(|| pro::Error::InvalidArgument(format!("invalid value for --max_size: '{}'", size)))?,
+    None => 100 << 20,
+  };
+  let mut recorder = pro::record::Recorder::new(
+    matches.opt_str("output").unwrap_or("/tmp/procstat.ndjson".to_string()),
+    max_bytes,
+    opt_or(&matches, "keep", 5)?,
+  )?;
+
+  let mut collector = pro::sampler::Collector::new(source);
+  let mut current_iteration = 0;
+  while iterations == 0 || current_iteration != iterations {
+    if current_iteration > 0 {
+      std::thread::sleep(Duration::from_secs(refresh_rate));
+    }
+    current_iteration += 1;
+    let mut snapshot = collector.collect()?.snapshot;
+    snapshot.processes = pro::list_processes(
+      std::mem::take(&mut snapshot.processes),
       0,
+      selection.nprocs,
+      selection.sort_by,
+      !selection.descending,
+      selection.filter.as_ref(),
+    )?;
+    recorder.write(&snapshot)?;
+  }
+  Ok(())
+}
+
+fn replay_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = Options::new();
+  opts.optflag("h", "help", "Print help message");
+  opts.optopt("r", "refresh_rate", "Seconds between snapshots when playing", "[NUM]");
+  config_opts(&mut opts);
+  let matches = parse_command(program, "replay", &opts, args)?;
+  if matches.free.is_empty() {
+    return Err(pro::Error::InvalidArgument("replay: missing FILE".to_string()));
+  }
+
+  let snapshots = pro::record::load(&matches.free)?;
+  if snapshots.is_empty() {
+    return Err(pro::Error::InvalidArgument(
+      "replay: the recording is empty".to_string(),
+    ));
+  }
+  let (config, _) = read_config(&matches)?;
+  let refresh_rate = Duration::try_from_secs_f64(opt_or(&matches, "refresh_rate", config.refresh_rate as f64)?)
+    .ok()
+    .filter(|rate| !rate.is_zero())
+    .ok_or_else(|| pro::Error::InvalidArgument("--refresh_rate must be a positive number".to_string()))?;
+  Ok(crate::tui::replay(snapshots, refresh_rate, config)?)
+}
+
+fn diff_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  filter_opts(&mut opts);
+  opts.optopt("", "interval", "Seconds between the two live snapshots", "[NUM]");
+  opts.optopt(
+    "",
+    "memory",
+    "Report memory changes of at least SIZE, e.g. 50M",
+    "[SIZE]",
+  );
+  opts.optopt("", "threads", "Report thread count changes of at least NUM", "[NUM]");
+  opts.optopt("", "priority", "Report priority changes of at least NUM", "[NUM]");
+  opts.optflag(
+    "",
+    "all_states",
+    "Also report changes between running, sleeping and idle",
+  );
+  opts.optopt("", "top", "Number of processes to list by CPU usage", "[NUM]");
+  opts.optopt("", "format", "Output format", "[table|json]");
+  let matches = parse_command(program, "diff", &opts, args)?;
+
+  let defaults = pro::diff::Thresholds::default();
+  let thresholds = pro::diff::Thresholds {
+    memory_kb: match matches.opt_str("memory") {
+      Some(size) => pro::query::parse_size(&size)
+        .map(|bytes| bytes as u64 / 1024)
+        .ok_or_else(|| pro::Error::InvalidArgument(format!("invalid value for --memory: '{}'", size)))?,
+      None => defaults.memory_kb,
+    },
+    thread_count: opt_or(&matches, "threads", defaults.thread_count)?,
+    priority: opt_or(&matches, "priority", defaults.priority)?,
+    all_states: matches.opt_present("all_states"),
+  };
+  let format = read_format(&matches)?;
+  if !matches!(format, Format::Table | Format::Json) {
+    return Err(pro::Error::InvalidArgument(
+      "diff can only be printed as table or json".to_string(),
+    ));
+  }
+
+  let (mut before, mut after) = if matches.free.is_empty() {
+    let source = read_source(&matches);
+    let mut counters = pro::Counters::default();
+    let before = counters.take_snapshot(&source, usize::MAX, pro::Field::Pid, false, None)?;
+    std::thread::sleep(Duration::from_secs(opt_or(&matches, "interval", 5)?));
+    (
+      before,
+      counters.take_snapshot(&source, usize::MAX, pro::Field::Pid, false, None)?,
     )
-    .print(0);
+  } else {
+    let mut snapshots = pro::record::load(&matches.free)?;
+    if snapshots.len() < 2 {
+      return Err(pro::Error::InvalidArgument(
+        "diff: the recording needs at least two snapshots".to_string(),
+      ));
+    }
+    let after = snapshots.pop().unwrap();
+    (snapshots.swap_remove(0), after)
+  };
+  if let Some(filter) = Selection::filter_from_matches(&matches)? {
+    // matching in either snapshot keeps a process in both, it shouldn't exit by changing
+    let matching: std::collections::HashSet<_> = before
+      .processes
+      .iter()
+      .chain(&after.processes)
+      .filter(|p| filter.matches(p))
+      .map(|p| (p.pid, p.start_time))
+      .collect();
+    before.processes.retain(|p| matching.contains(&(p.pid, p.start_time)));
+    after.processes.retain(|p| matching.contains(&(p.pid, p.start_time)));
+  }
 
-    return Ok(());
+  let diff = pro::diff::diff(&before, &after, &thresholds, opt_or(&matches, "top", 5)?);
+  let mut stdout = io::stdout();
+  match format {
+    Format::Json => writeln!(
+      stdout,
+      "{}",
+      serde_json::to_string_pretty(&diff).map_err(io::Error::from)?
+    )?,
+    _ => write!(stdout, "{}", diff)?,
   }
+  Ok(())
+}
+
+fn serve_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  filter_opts(&mut opts);
+  opts.optopt(
+    "",
+    "listen",
+    "Address to listen on, 127.0.0.1:9920 by default",
+    "[ADDR]",
+  );
+  opts.optflag(
+    "",
+    "processes",
+    "Export per-process metrics of every process, the filter options export only those of the matches",
+  );
+  let matches = parse_command(program, "serve", &opts, args)?;
+
+  let processes = match Selection::filter_from_matches(&matches)? {
+    Some(filter) => Some(filter),
+    None if matches.opt_present("processes") => Some(pro::Filter::new("any", "", false)?),
+    None => None,
+  };
+  crate::serve::run(
+    &matches.opt_str("listen").unwrap_or("127.0.0.1:9920".to_string()),
+    read_source(&matches),
+    processes,
+  )
+}
+
+fn daemon_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  opts.optopt(
+    "",
+    "socket",
+    "Socket path, /run/procstat.sock for root, $XDG_RUNTIME_DIR/procstat.sock or /tmp/procstat.sock \
+     otherwise by default",
+    "[PATH]",
+  );
+  let matches = parse_command(program, "daemon", &opts, args)?;
+  let socket = match matches.opt_str("socket") {
+    Some(socket) => std::path::PathBuf::from(socket),
+    None => crate::daemon::default_socket(),
+  };
+  crate::daemon::run(&socket, read_source(&matches))
+}
+
+fn watch_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  opts.optopt("", "policies", "Policies to apply", "[FILE]");
+  opts.optflag("", "dry_run", "Only log what the policies would do");
+  opts.optopt(
+    "",
+    "audit_log",
+    "Where to log every action, /var/log/procstat-audit.log for root and \
+     $XDG_STATE_HOME/procstat/audit.log otherwise by default",
+    "[FILE]",
+  );
+  opts.optopt("r", "refresh_rate", "Seconds between checks", "[NUM]");
+  opts.optopt("i", "iters", "Number of checks, 0 watches until interrupted", "[NUM]");
+  let matches = parse_command(program, "watch", &opts, args)?;
+
+  let source = read_source(&matches);
+  let policies = match matches.opt_str("policies") {
+    Some(path) => pro::policy::load_policies(std::path::Path::new(&path))?,
+    None => return Err(pro::Error::InvalidArgument("watch: missing --policies".to_string())),
+  };
+  let refresh_rate = opt_or(&matches, "refresh_rate", 1)?;
+  let iterations: u32 = opt_or(&matches, "iters", 0)?;
+  let audit_log = match matches.opt_str("audit_log") {
+    Some(path) => path.into(),
+    None => {
+      let path = pro::policy::default_audit_log();
+      if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+      }
+      path
+    }
+  };
+  let mut watchdog = pro::policy::Watchdog::new(policies, matches.opt_present("dry_run"), Some(audit_log));
 
+  let mut collector = pro::sampler::Collector::new(source);
+  let mut current_iteration = 0;
   while iterations == 0 || current_iteration != iterations {
-    let output = pro::show_stats(nprocs, &sort_by, descending, &filter_by, &pattern, exact_match);
+    if current_iteration > 0 {
+      std::thread::sleep(Duration::from_secs(refresh_rate));
+    }
     current_iteration += 1;
-    // Clear screen and display all at once
-    print!("{esc}[2J{esc}[1;1H{}", output, esc = 27 as char);
-    std::io::stdout().flush().unwrap();
+    let snapshot = collector.collect()?.snapshot;
+    for record in watchdog.update(&snapshot.processes, snapshot.timestamp)? {
+      println!("{}", record);
+    }
+  }
+  Ok(())
+}
 
-    std::thread::sleep(Duration::from_secs(refresh_rate));
-    writeln!(log_file, "{}", output).expect("Failed to write to log file");
+struct TreeOptions {
+  root: pid_t,
+  fields: Vec<pro::Field>,
+  format: Format,
+}
+
+impl TreeOptions {
+  fn from_matches(matches: &getopts::Matches) -> pro::Result<Self> {
+    Ok(TreeOptions {
+      root: opt_or(matches, "from", 0)?,
+      fields: match matches.opt_str("fields") {
+        Some(fields) => fields
+          .split(',')
+          .map(|f| f.trim().parse())
+          .collect::<pro::Result<Vec<pro::Field>>>()?,
+        None => Vec::new(),
+      },
+      format: read_format(matches)?,
+    })
+  }
+}
+
+fn tree_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  tree_opts(&mut opts);
+  selection_opts(&mut opts);
+  opts.optopt("", "format", "Output format", "[table|json]");
+  let matches = parse_command(program, "tree", &opts, args)?;
+  run_tree(
+    &read_source(&matches),
+    &Selection::from_matches(&matches)?,
+    &TreeOptions::from_matches(&matches)?,
+  )
+}
+
+fn run_tree(source: &pro::ProcSource, selection: &Selection, tree: &TreeOptions) -> pro::Result<()> {
+  // filter afterwards, so the ancestors of matches stay in, and cut to nprocs only once the tree is
+  // built, so neither the root nor the matches can fall outside it
+  let processes = pro::list_processes(
+    pro::read_processes(source)?,
+    0,
+    usize::MAX,
+    selection.sort_by,
+    !selection.descending,
+    None,
+  )?;
+  if tree.root != 0 && !processes.iter().any(|p| p.pid == tree.root) {
+    return Err(pro::Error::ProcessVanished(tree.root));
+  }
+  let (processes, matched) = match &selection.filter {
+    Some(filter) => {
+      let (processes, matched) = pro::filter_tree(processes, filter);
+      (processes, Some(matched))
+    }
+    None => (processes, None),
+  };
+  let mut root = pro::build_tree(&processes, tree.root);
+  root.truncate(selection.nprocs);
+
+  match tree.format {
+    Format::Table => {
+      // only highlight matches on a terminal, pipes get plain text
+      let highlight = matched.as_ref().filter(|_| io::stdout().is_terminal());
+      write!(io::stdout(), "{}", root.format(&processes, &tree.fields, highlight))?;
+    }
+    Format::Json => writeln!(io::stdout(), "{}", root.to_json(&processes, matched.as_ref()))?,
+    Format::Csv | Format::Ndjson => {
+      return Err(pro::Error::InvalidArgument(
+        "the tree can only be printed as table or json".to_string(),
+      ))
+    }
+  }
+  Ok(())
+}
+
+enum Action {
+  Kill(i32),
+  Renice(i32),
+  Affinity(Vec<usize>),
+}
+
+fn run_action(pids: Vec<pid_t>, action: &Action) -> pro::Result<()> {
+  match action {
+    Action::Kill(signal) => pro::execute_on_with_arg(pids, *signal, pro::kill_process, None),
+    Action::Renice(priority) => pro::execute_on_with_arg(pids, *priority, pro::set_priority, None),
+    Action::Affinity(cpus) => pro::execute_on_with_args(pids, cpus, pro::bind_to_cpu_set, None),
   }
+}
+
+// Runs `action` on the PIDs given as operands, or on the selected processes with --all
+fn action_command(program: &str, command: &str, mut opts: Options, args: &[String]) -> pro::Result<()> {
+  opts.optflag("a", "all", "Act on all processes picked by the options below");
+  selection_opts(&mut opts);
+  let matches = parse_command(program, command, &opts, args)?;
+  let action = match command {
+    "kill" => Action::Kill(match matches.opt_str("signal") {
+      Some(signal) => pro::parse_signal(&signal)?,
+      None => libc::SIGTERM,
+    }),
+    "renice" => match matches.opt_str("priority") {
+      Some(_) => Action::Renice(opt_or(&matches, "priority", 0)?),
+      None => {
+        return Err(pro::Error::InvalidArgument(
+          "renice: --priority is required".to_string(),
+        ))
+      }
+    },
+    _ => match matches.opt_str("cpus") {
+      Some(cpus) => Action::Affinity(pro::parse_cpus(&cpus)?),
+      None => return Err(pro::Error::InvalidArgument("affinity: --cpus is required".to_string())),
+    },
+  };
+
+  let pids = if matches.opt_present("all") {
+    if let Some(arg) = matches.free.first() {
+      return Err(pro::Error::InvalidArgument(format!(
+        "{}: PIDs and --all can't be combined, got '{}'",
+        command, arg
+      )));
+    }
+    let source = read_source(&matches);
+    Selection::from_matches(&matches)?
+      .list(&source)?
+      .iter()
+      .map(|p| p.pid)
+      .collect()
+  } else {
+    if let Some(opt) = SELECTION_OPTS.iter().find(|opt| matches.opt_present(opt)) {
+      return Err(pro::Error::InvalidArgument(format!(
+        "{}: --{} only applies with --all",
+        command, opt
+      )));
+    }
+    if matches.free.is_empty() {
+      return Err(pro::Error::InvalidArgument(format!("{}: give PIDs or --all", command)));
+    }
+    matches
+      .free
+      .iter()
+      .map(|pid| {
+        pid
+          .parse::<pid_t>()
+          .map_err(|_| pro::Error::InvalidArgument(format!("invalid PID '{}'", pid)))
+      })
+      .collect::<pro::Result<Vec<pid_t>>>()?
+  };
+  run_action(pids, &action)
+}
+
+fn kill_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  opts.optopt(
+    "",
+    "signal",
+    "Signal to send, e.g. 9, KILL or SIGTERM (default TERM)",
+    "[SIG]",
+  );
+  action_command(program, "kill", opts, args)
+}
+
+fn renice_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  opts.optopt("p", "priority", "New priority (nice value)", "[PRIO]");
+  action_command(program, "renice", opts, args)
+}
+
+fn affinity_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  opts.optopt("c", "cpus", "Comma separated list of cpus", "[CPU]");
+  action_command(program, "affinity", opts, args)
+}
+
+fn info_command(program: &str, args: &[String]) -> pro::Result<()> {
+  let mut opts = command_opts();
+  opts.optopt("", "format", "Output format", "[table|json]");
+  let matches = parse_command(program, "info", &opts, args)?;
+  let pid = match matches.free.as_slice() {
+    [pid] => pid
+      .parse::<pid_t>()
+      .map_err(|_| pro::Error::InvalidArgument(format!("invalid PID '{}'", pid)))?,
+    _ => {
+      return Err(pro::Error::InvalidArgument(
+        "info: expected exactly one PID".to_string(),
+      ))
+    }
+  };
+  let source = read_source(&matches);
 
+  // reports why we can't read the process; the second sample gives us its CPU%
+  pro::read_process_info(&source, pid)?;
+  let mut counters = pro::Counters::default();
+  counters.read_processes(&source)?;
+  std::thread::sleep(Duration::from_millis(500));
+  let process = counters
+    .read_processes(&source)?
+    .into_iter()
+    .find(|p| p.pid == pid)
+    .ok_or(pro::Error::ProcessVanished(pid))?;
+
+  let mut stdout = io::stdout();
+  match read_format(&matches)? {
+    Format::Table => {
+      for field in pro::Field::ALL {
+        writeln!(stdout, "{:<10} {}", field.name(), field.format(&process))?;
+      }
+    }
+    Format::Json => writeln!(
+      stdout,
+      "{}",
+      serde_json::to_string_pretty(&process).map_err(io::Error::from)?
+    )?,
+    Format::Csv | Format::Ndjson => {
+      return Err(pro::Error::InvalidArgument(
+        "info can only be printed as table or json".to_string(),
+      ))
+    }
+  }
   Ok(())
 }
 
+// The flat options of older versions, mapped onto the commands above
+fn run_deprecated(program: &str, args: &[String]) -> pro::Result<()> {
+  let opts = make_opts();
+  let matches = opts
+    .parse(args)
+    .map_err(|f| pro::Error::InvalidArgument(f.to_string()))?;
+  if matches.opt_present("h") {
+    print_help(program);
+    print!("\n{}", opts.usage("Deprecated options:"));
+    return Ok(());
+  }
+
+  let modes: Vec<&str> = [
+    ("gui", "gui"),
+    ("tree", "tree"),
+    ("kill", "kill"),
+    ("priority", "renice"),
+    ("cpu_affinity", "affinity"),
+  ]
+  .iter()
+  .filter(|(opt, _)| matches.opt_present(opt))
+  .map(|&(_, command)| command)
+  .collect();
+  if modes.len() > 1 {
+    return Err(pro::Error::InvalidArgument(format!(
+      "{} can't be combined, use one command at a time",
+      modes
+        .iter()
+        .map(|c| format!("'{}'", c))
+        .collect::<Vec<_>>()
+        .join(" and ")
+    )));
+  }
+  let command = modes.first().copied().unwrap_or("top");
+  let targeted = matches.opt_present("pid") || matches.opt_present("all");
+  if matches.opt_present("pid") && matches.opt_present("all") {
+    return Err(pro::Error::InvalidArgument(
+      "--pid and --all can't be combined".to_string(),
+    ));
+  }
+  match command {
+    "kill" | "renice" | "affinity" if !targeted => {
+      return Err(pro::Error::InvalidArgument(format!("{} needs --pid or --all", command)))
+    }
+    "gui" | "tree" | "top" if targeted => {
+      return Err(pro::Error::InvalidArgument(
+        "--pid and --all need one of --kill, --priority or --cpu_affinity".to_string(),
+      ))
+    }
+    _ => {}
+  }
+  eprintln!(
+    "warning: options without a command are deprecated, use '{} {}' instead (see '{} help')",
+    program, command, program
+  );
+
+  let source = read_source(&matches);
+  let selection = Selection::from_matches(&matches)?;
+  let action = match command {
+    "gui" => {
+      let _ = icegui::run(source, read_config(&matches)?.0);
+      return Ok(());
+    }
+    "tree" => return run_tree(&source, &selection, &TreeOptions::from_matches(&matches)?),
+    "top" => {
+      let (config, _) = read_config(&matches)?;
+      let selection = Selection::with_defaults(&matches, &config)?;
+      return run_top(&source, &selection, &TopOptions::from_matches(&matches, &config)?);
+    }
+    "kill" => Action::Kill(pro::parse_signal(&matches.opt_str("kill").unwrap_or_default())?),
+    "renice" => Action::Renice(opt_or(&matches, "priority", 0)?),
+    _ => Action::Affinity(pro::parse_cpus(&matches.opt_str("cpu_affinity").unwrap_or_default())?),
+  };
+  let pids = if matches.opt_present("all") {
+    selection.list(&source)?.iter().map(|p| p.pid).collect()
+  } else {
+    vec![opt_or::<pid_t>(&matches, "pid", 0)?]
+  };
+  run_action(pids, &action)
+}
@@ -0,0 +1,437 @@
+//! Defaults of the frontends, read from `$XDG_CONFIG_HOME/procstat/config.toml` or the file given
+//! with `--config`. Command line options override them. Every setting is optional:
+//!
+//! ```toml
+//! refresh_rate = 1
+//! history = 100
+//! log = "/tmp/procstat.log"
+//! sort_by = "cpu"
+//! descending = true
+//! filter = "user!=root"
+//! columns = ["pid", "user", "cpu", "memory", "io_read", "start", "name"]
+//!
+//! [widths]
+//! name = 40
+//!
+//! [gui]
+//! refresh_rate = 2
+//!
+//! [colors]
+//! selection = "#303030"
+//! error = "lightred"
+//!
+//! [keys]
+//! kill = "x"
+//! ```
+
+use crate::pro::{self, toml_from_str, Error, Field, Result};
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// Seconds between refreshes of top, record and the TUI.
+  pub refresh_rate: u64,
+  /// Samples kept for the charts.
+  pub history: usize,
+  /// Where top logs its output.
+  pub log: PathBuf,
+  pub sort_by: Field,
+  pub descending: bool,
+  /// A filter expression, see `pro::query`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub filter: Option<String>,
+  /// The columns of the TUI process table, in order.
+  pub columns: Vec<Field>,
+  /// Widths of the TUI columns that differ from the default.
+  pub widths: BTreeMap<Field, u16>,
+  pub gui: Gui,
+  pub colors: Colors,
+  pub keys: Keys,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      refresh_rate: 1,
+      history: 100,
+      log: PathBuf::from("/tmp/procstat.log"),
+      sort_by: Field::Pid,
+      descending: false,
+      filter: None,
+      columns: Field::COLUMNS.to_vec(),
+      widths: BTreeMap::new(),
+      gui: Gui::default(),
+      colors: Colors::default(),
+      keys: Keys::default(),
+    }
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gui {
+  /// Seconds between refreshes of the GUI.
+  pub refresh_rate: u64,
+}
+
+impl Default for Gui {
+  fn default() -> Self {
+    Gui { refresh_rate: 2 }
+  }
+}
+
+/// The TUI theme. Colors are names like "darkgray" or "light red", "#rrggbb", or a palette index.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+  /// Background of the selected row.
+  #[serde(with = "color")]
+  pub selection: Color,
+  #[serde(with = "color")]
+  pub tabs: Color,
+  #[serde(with = "color")]
+  pub active_tab: Color,
+  /// RAM and cpu gauges.
+  #[serde(with = "color")]
+  pub gauge: Color,
+  #[serde(with = "color")]
+  pub swap: Color,
+  /// Cpu and load history.
+  #[serde(with = "color")]
+  pub chart: Color,
+  #[serde(with = "color")]
+  pub memory_chart: Color,
+  /// Disk reads and received bytes.
+  #[serde(with = "color")]
+  pub read: Color,
+  /// Disk writes and transmitted bytes.
+  #[serde(with = "color")]
+  pub write: Color,
+  #[serde(with = "color")]
+  pub text: Color,
+  /// Status messages.
+  #[serde(with = "color")]
+  pub ok: Color,
+  /// Failures and alerts.
+  #[serde(with = "color")]
+  pub error: Color,
+}
+
+impl Default for Colors {
+  fn default() -> Self {
+    Colors {
+      selection: Color::DarkGray,
+      tabs: Color::White,
+      active_tab: Color::Yellow,
+      gauge: Color::Green,
+      swap: Color::Yellow,
+      chart: Color::Cyan,
+      memory_chart: Color::Green,
+      read: Color::Green,
+      write: Color::Red,
+      text: Color::White,
+      ok: Color::Green,
+      error: Color::Red,
+    }
+  }
+}
+
+mod color {
+  use ratatui::style::Color;
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(color)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name
+      .parse()
+      .map_err(|_| serde::de::Error::custom(format!("invalid color '{}'", name)))
+  }
+}
+
+/// The keys of the TUI actions. Arrows, Enter and Esc are fixed, as are the field keys after `sort`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keys {
+  pub quit: char,
+  pub help: char,
+  pub search: char,
+  pub sort: char,
+  pub ascending: char,
+  pub command: char,
+  pub tree: char,
+  pub collapse: char,
+  pub expand: char,
+  pub threads: char,
+  pub bottom: char,
+  pub kill: char,
+  pub nice: char,
+  pub unnice: char,
+  /// Writes the current sort order and columns back to the config file.
+  pub save: char,
+  /// Opens the column setup, like F2.
+  pub setup: char,
+  pub pause: char,
+  pub step_back: char,
+  pub step: char,
+  pub seek_back: char,
+  pub seek: char,
+}
+
+impl Default for Keys {
+  fn default() -> Self {
+    Keys {
+      quit: 'q',
+      help: '?',
+      search: '/',
+      sort: 's',
+      ascending: 'a',
+      command: 'c',
+      tree: 't',
+      collapse: '-',
+      expand: '+',
+      threads: 'H',
+      bottom: 'G',
+      kill: 'k',
+      nice: 'n',
+      unnice: 'N',
+      save: 'W',
+      setup: 'S',
+      pause: ' ',
+      step_back: ',',
+      step: '.',
+      seek_back: '<',
+      seek: '>',
+    }
+  }
+}
+
+impl Keys {
+  fn all(&self) -> [(&'static str, char); 21] {
+    [
+      ("quit", self.quit),
+      ("help", self.help),
+      ("search", self.search),
+      ("sort", self.sort),
+      ("ascending", self.ascending),
+      ("command", self.command),
+      ("tree", self.tree),
+      ("collapse", self.collapse),
+      ("expand", self.expand),
+      ("threads", self.threads),
+      ("bottom", self.bottom),
+      ("kill", self.kill),
+      ("nice", self.nice),
+      ("unnice", self.unnice),
+      ("save", self.save),
+      ("setup", self.setup),
+      ("pause", self.pause),
+      ("step_back", self.step_back),
+      ("step", self.step),
+      ("seek_back", self.seek_back),
+      ("seek", self.seek),
+    ]
+  }
+}
+
+impl Config {
+  /// Reads the config at `path`, or at `default_path()` if not given. Only a missing default file
+  /// is not an error, it means the defaults. Returns the path to save to as well.
+  pub fn load(path: Option<&Path>) -> Result<(Config, PathBuf)> {
+    let (path, required) = match path {
+      Some(path) => (path.to_path_buf(), true),
+      None => (default_path(), false),
+    };
+    let text = match fs::read_to_string(&path) {
+      Ok(text) => text,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok((Config::default(), path)),
+      Err(e) => return Err(io_error(e, &path)),
+    };
+    let config: Config = toml_from_str(&text, &path)?;
+    config
+      .validate()
+      .map_err(|message| Error::parse(&path, None, message))?;
+    Ok((config, path))
+  }
+
+  fn validate(&self) -> std::result::Result<(), String> {
+    if self.refresh_rate == 0 || self.gui.refresh_rate == 0 {
+      return Err("refresh_rate must be at least 1".to_string());
+    }
+    if self.history < 2 {
+      return Err("history must be at least 2".to_string());
+    }
+    if self.columns.is_empty() {
+      return Err("columns can't be empty".to_string());
+    }
+    if let Some(filter) = &self.filter {
+      filter
+        .parse::<pro::query::Query>()
+        .map_err(|e| format!("filter: {}", e))?;
+    }
+    let keys = self.keys.all();
+    for (i, (action, key)) in keys.iter().enumerate() {
+      if let Some((other, _)) = keys[..i].iter().find(|(_, other)| other == key) {
+        return Err(format!("keys: '{}' is bound to both {} and {}", key, other, action));
+      }
+    }
+    Ok(())
+  }
+
+  /// Writes the layout of the TUI, i.e. `sort_by`, `descending`, `columns` and `widths`, to `path`.
+  /// The rest of the file is kept as written, comments included; a missing file and its directory
+  /// are created.
+  pub fn save_layout(&self, path: &Path) -> Result<()> {
+    #[derive(Serialize)]
+    struct Layout<'a> {
+      sort_by: Field,
+      descending: bool,
+      columns: &'a [Field],
+      widths: &'a BTreeMap<Field, u16>,
+    }
+
+    let text = match fs::read_to_string(path) {
+      Ok(text) => text,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+      Err(e) => return Err(io_error(e, path)),
+    };
+    let mut document: toml_edit::DocumentMut = text.parse().map_err(|e: toml_edit::TomlError| {
+      let line = e.span().map(|span| text[..span.start].matches('\n').count() + 1);
+      Error::parse(path, line, e.message())
+    })?;
+    let layout = toml_edit::ser::to_document(&Layout {
+      sort_by: self.sort_by,
+      descending: self.descending,
+      columns: &self.columns,
+      widths: &self.widths,
+    })
+    .map_err(|e| Error::InvalidArgument(e.to_string()))?;
+    for (key, item) in layout.iter() {
+      // the serializer writes maps inline, [widths] reads better as a table of its own
+      match item.as_inline_table() {
+        Some(table) if table.is_empty() => drop(document.remove(key)),
+        Some(table) => document[key] = toml_edit::Item::Table(table.clone().into_table()),
+        None => document[key] = item.clone(),
+      }
+    }
+
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir).map_err(|e| io_error(e, dir))?;
+    }
+    fs::write(path, document.to_string()).map_err(|e| io_error(e, path))
+  }
+
+  /// The default `filter`, parsed.
+  pub fn filter(&self) -> Option<pro::Filter> {
+    let query = self.filter.as_ref()?.parse().ok()?;
+    Some(pro::Filter::with_query(query))
+  }
+}
+
+// Like `pro::record::load`, naming the file in I/O errors
+fn io_error(error: std::io::Error, path: &Path) -> Error {
+  match Error::from_read(error, path, None) {
+    Error::Io(e) => std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)).into(),
+    e => e,
+  }
+}
+
+/// `$XDG_CONFIG_HOME/procstat/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> PathBuf {
+  let dir = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+    Some(dir) => PathBuf::from(dir),
+    None => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config"),
+  };
+  dir.join("procstat").join("config.toml")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(text: &str) -> Result<Config> {
+    let config: Config = toml_from_str(text, Path::new("config.toml"))?;
+    config
+      .validate()
+      .map_err(|message| Error::parse("config.toml", None, message))?;
+    Ok(config)
+  }
+
+  #[test]
+  fn overrides_defaults() {
+    let config = parse(
+      r##"
+        history = 300
+        sort_by = "mem"
+        columns = ["pid", "cpu", "name"]
+        filter = "user=app"
+        [colors]
+        selection = "#303030"
+        [keys]
+        kill = "x"
+      "##,
+    )
+    .unwrap();
+    assert_eq!((config.refresh_rate, config.history), (1, 300));
+    assert_eq!(config.sort_by, Field::Memory);
+    assert_eq!(config.columns, vec![Field::Pid, Field::Cpu, Field::Name]);
+    assert!(config.filter().is_some());
+    assert_eq!(config.colors.selection, Color::Rgb(0x30, 0x30, 0x30));
+    assert_eq!(config.colors.error, Color::Red);
+    assert_eq!((config.keys.kill, config.keys.quit), ('x', 'q'));
+  }
+
+  #[test]
+  fn rejects_bad_settings() {
+    let error = |text: &str| parse(text).err().unwrap().to_string();
+    assert!(error("sort_by = \"bogus\"").contains("bogus"));
+    assert!(error("refresh = 2").contains("refresh"));
+    assert!(error("[colors]\ntext = \"plaid\"").contains("invalid color 'plaid'"));
+    assert!(error("filter = \"bogus>1\"").contains("filter"));
+    assert!(error("[keys]\nkill = \"q\"").contains("'q' is bound to both quit and kill"));
+    assert!(error("[keys]\nkill = \"xy\"").starts_with("config.toml:2:"));
+  }
+
+  #[test]
+  fn saves_only_the_layout() {
+    let dir = std::env::temp_dir().join(format!("procstat-config-{}", std::process::id()));
+    let path = dir.join("nested").join("config.toml");
+    let mut config = Config {
+      sort_by: Field::Cpu,
+      columns: vec![Field::Name, Field::Pid],
+      ..Default::default()
+    };
+    config.widths.insert(Field::Name, 30);
+    config.save_layout(&path).unwrap();
+
+    let (loaded, loaded_path) = Config::load(Some(&path)).unwrap();
+    assert_eq!(loaded_path, path);
+    assert_eq!(loaded.sort_by, Field::Cpu);
+    assert_eq!(loaded.columns, config.columns);
+    assert_eq!(loaded.widths, config.widths);
+    assert_eq!(loaded.keys.pause, ' ');
+
+    // the other settings keep their comments and aren't filled in with defaults
+    let text = "# mine\nhistory = 50 # samples\nsort_by = \"pid\"\n\n[widths]\nname = 30\n\n[colors]\nok = \"42\"\n";
+    fs::write(&path, text).unwrap();
+    config.widths.clear();
+    config.save_layout(&path).unwrap();
+    let saved = fs::read_to_string(&path).unwrap();
+    assert!(saved.starts_with("# mine\nhistory = 50 # samples\nsort_by = \"cpu\"\n"));
+    assert!(saved.contains("[colors]\nok = \"42\"\n") && !saved.contains("widths") && !saved.contains("[keys]"));
+    let (loaded, _) = Config::load(Some(&path)).unwrap();
+    assert_eq!((loaded.history, loaded.colors.ok), (50, Color::Indexed(42)));
+    assert_eq!(loaded.columns, config.columns);
+    fs::remove_dir_all(dir).unwrap();
+
+    assert!(Config::load(Some(&path)).is_err());
+  }
+}
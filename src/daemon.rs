@@ -0,0 +1,428 @@
+//! A control daemon on a Unix socket, for scripts that want to keep a connection open rather than
+//! run the binary every second. Every line sent is a JSON request and gets one JSON line back:
+//!
+//! ```text
+//! {"id": 1, "method": "list", "params": {"where": "mem>500M", "sort_by": "cpu", "descending": true}}
+//! {"id": 1, "result": [{"user": "app", "pid": 42, ...}]}
+//! {"id": 2, "method": "kill", "params": {"pid": 42, "signal": "TERM"}}
+//! {"id": 2, "error": {"kind": "permission_denied", "message": "permission denied: /proc/42"}}
+//! ```
+//!
+//! Methods are `list`, `info` (pid), `system`, `kill` (pid, signal), `renice` (pid, priority) and
+//! `affinity` (pid, cpus). The socket is open to every user, the peer credentials decide what they
+//! may do: root acts on any process, anybody else only on their own and can't raise priorities. With
+//! `--root` the reads come from that tree, but the checks and actions are on the live system.
+
+use crate::pro;
+use libc::pid_t;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+
+// Longest request line, so a client can't make the daemon buffer without end
+const MAX_REQUEST: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+struct Request {
+  #[serde(default)]
+  id: Value,
+  method: String,
+  #[serde(default)]
+  params: Value,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ListParams {
+  nprocs: Option<usize>,
+  sort_by: Option<String>,
+  descending: bool,
+  filter_by: Option<String>,
+  pattern: String,
+  exact_match: bool,
+  #[serde(rename = "where")]
+  query: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PidParams {
+  pid: pid_t,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KillParams {
+  pid: pid_t,
+  /// A name like "TERM" or a number, TERM if missing.
+  signal: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReniceParams {
+  pid: pid_t,
+  priority: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AffinityParams {
+  pid: pid_t,
+  cpus: Vec<usize>,
+}
+
+/// `/run/procstat.sock` for root, so other users can't plant anything at the path, otherwise
+/// `$XDG_RUNTIME_DIR/procstat.sock` falling back to the temp directory.
+pub fn default_socket() -> PathBuf {
+  if unsafe { libc::geteuid() } == 0 {
+    return PathBuf::from("/run/procstat.sock");
+  }
+  std::env::var_os("XDG_RUNTIME_DIR")
+    .map(PathBuf::from)
+    .unwrap_or_else(std::env::temp_dir)
+    .join("procstat.sock")
+}
+
+pub fn run(path: &Path, source: pro::ProcSource) -> pro::Result<()> {
+  if path.exists() {
+    if UnixStream::connect(path).is_ok() {
+      return Err(pro::Error::InvalidArgument(format!(
+        "{} is in use, is another daemon running?",
+        path.display()
+      )));
+    }
+    // left behind by a daemon that didn't exit cleanly
+    fs::remove_file(path)?;
+  }
+  // bind creates the socket with the umask applied, which sets its mode without a chmod that a
+  // symlink swapped in after the bind could redirect
+  let umask = unsafe { libc::umask(0o111) };
+  let listener = UnixListener::bind(path);
+  unsafe { libc::umask(umask) };
+  let listener = listener?;
+  eprintln!("Listening on {}", path.display());
+
+  let source = Arc::new(source);
+  for stream in listener.incoming() {
+    let stream = match stream {
+      Ok(stream) => stream,
+      Err(e) => {
+        eprintln!("error: {}", e);
+        continue;
+      }
+    };
+    let source = Arc::clone(&source);
+    std::thread::spawn(move || {
+      if let Err(e) = serve_client(stream, &source) {
+        eprintln!("error: {}", e);
+      }
+    });
+  }
+  Ok(())
+}
+
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+  let mut credentials: libc::ucred = unsafe { std::mem::zeroed() };
+  let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+  let result = unsafe {
+    libc::getsockopt(
+      stream.as_raw_fd(),
+      libc::SOL_SOCKET,
+      libc::SO_PEERCRED,
+      &mut credentials as *mut libc::ucred as *mut libc::c_void,
+      &mut length,
+    )
+  };
+  if result == 0 {
+    Ok(credentials.uid)
+  } else {
+    Err(io::Error::last_os_error())
+  }
+}
+
+fn serve_client(stream: UnixStream, source: &pro::ProcSource) -> io::Result<()> {
+  let uid = peer_uid(&stream)?;
+  // cpu usage is relative to the client's previous request
+  let mut counters = pro::Counters::default();
+  let mut writer = &stream;
+  let mut reader = BufReader::new(&stream);
+  loop {
+    let mut line = String::new();
+    let length = (&mut reader).take(MAX_REQUEST as u64).read_line(&mut line)?;
+    if length == 0 {
+      return Ok(());
+    }
+    // the rest of the line can't be told from the next request, so hang up after the error
+    if length == MAX_REQUEST && !line.ends_with('\n') {
+      let message = format!("requests are limited to {} bytes", MAX_REQUEST);
+      writeln!(
+        writer,
+        "{}",
+        json!({"id": null, "error": {"kind": "invalid_argument", "message": message}})
+      )?;
+      return Ok(());
+    }
+    if line.trim().is_empty() {
+      continue;
+    }
+    let response = handle(&line, uid, source, &mut counters);
+    writeln!(writer, "{}", response)?;
+  }
+}
+
+fn error_kind(error: &pro::Error) -> &'static str {
+  match error {
+    pro::Error::Io(_) => "io",
+    pro::Error::InvalidArgument(_) => "invalid_argument",
+    pro::Error::UnknownField(_) => "unknown_field",
+    pro::Error::Parse { .. } => "parse",
+    pro::Error::PermissionDenied(_) => "permission_denied",
+    pro::Error::ProcessVanished(_) => "process_vanished",
+  }
+}
+
+// The response to one request line from a client running as `uid`
+fn handle(line: &str, uid: u32, source: &pro::ProcSource, counters: &mut pro::Counters) -> Value {
+  let request: Request = match serde_json::from_str(line) {
+    Ok(request) => request,
+    Err(e) => return json!({"id": null, "error": {"kind": "parse", "message": e.to_string()}}),
+  };
+  match dispatch(&request, uid, source, counters) {
+    Ok(result) => json!({"id": request.id, "result": result}),
+    Err(e) => json!({"id": request.id, "error": {"kind": error_kind(&e), "message": e.to_string()}}),
+  }
+}
+
+fn params<T: serde::de::DeserializeOwned>(request: &Request) -> pro::Result<T> {
+  let params = match &request.params {
+    Value::Null => json!({}),
+    params => params.clone(),
+  };
+  serde_json::from_value(params).map_err(|e| pro::Error::InvalidArgument(format!("{}: {}", request.method, e)))
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> pro::Result<Value> {
+  Ok(serde_json::to_value(value).map_err(io::Error::from)?)
+}
+
+fn dispatch(request: &Request, uid: u32, source: &pro::ProcSource, counters: &mut pro::Counters) -> pro::Result<Value> {
+  match request.method.as_str() {
+    "list" => {
+      let params: ListParams = params(request)?;
+      let mut filter = match &params.filter_by {
+        Some(filter_by) => Some(pro::Filter::new(filter_by, &params.pattern, params.exact_match)?),
+        None => None,
+      };
+      if let Some(query) = &params.query {
+        let query = query.parse()?;
+        match &mut filter {
+          Some(filter) => filter.query = Some(query),
+          None => filter = Some(pro::Filter::with_query(query)),
+        }
+      }
+      let sort_by = match &params.sort_by {
+        Some(sort_by) => sort_by.parse()?,
+        None => pro::Field::Pid,
+      };
+      let processes = pro::list_processes(
+        counters.read_processes(source)?,
+        0,
+        params.nprocs.unwrap_or(usize::MAX),
+        sort_by,
+        !params.descending,
+        filter.as_ref(),
+      )?;
+      to_value(&processes)
+    }
+    "info" => {
+      let PidParams { pid } = params(request)?;
+      let process = counters
+        .read_processes(source)?
+        .into_iter()
+        .find(|p| p.pid == pid)
+        .ok_or(pro::Error::ProcessVanished(pid))?;
+      to_value(&process)
+    }
+    "system" => {
+      params::<serde::de::IgnoredAny>(request)?;
+      to_value(&counters.take_snapshot(source, 0, pro::Field::Pid, false, None)?)
+    }
+    "kill" => {
+      let KillParams { pid, signal } = params(request)?;
+      let signal = match signal {
+        None => libc::SIGTERM,
+        Some(Value::String(name)) => pro::parse_signal(&name)?,
+        Some(number) => pro::parse_signal(&number.to_string())?,
+      };
+      let process = authorize(uid, pid)?;
+      act(|tx| process.send_signal(signal, Some(tx)))
+    }
+    "renice" => {
+      let ReniceParams { pid, priority } = params(request)?;
+      let process = authorize(uid, pid)?;
+      // like the kernel, only root may raise priorities
+      if uid != 0 && priority < pro::get_priority(pid) {
+        return Err(pro::Error::PermissionDenied(live_path(pid)));
+      }
+      act(|tx| {
+        pro::set_priority(pid, priority, Some(tx))?;
+        process.check_running()
+      })
+    }
+    "affinity" => {
+      let AffinityParams { pid, cpus } = params(request)?;
+      let process = authorize(uid, pid)?;
+      act(|tx| {
+        pro::bind_to_cpu_set(pid, &cpus, Some(tx))?;
+        process.check_running()
+      })
+    }
+    method => Err(pro::Error::InvalidArgument(format!("unknown method '{}'", method))),
+  }
+}
+
+// Root may act on anything, everybody else on their own processes. Actions hit live pids, so this
+// looks at the live /proc even when --root points the reads elsewhere. The handle ties the check
+// to the process: its signals can't reach a process that reused the pid, and other actions check
+// that it still runs afterwards.
+fn authorize(uid: u32, pid: pid_t) -> pro::Result<pro::ProcessHandle> {
+  let process = pro::ProcessHandle::open(pid)?;
+  if uid == 0 || process.uid()? == uid {
+    Ok(process)
+  } else {
+    Err(pro::Error::PermissionDenied(live_path(pid)))
+  }
+}
+
+fn live_path(pid: pid_t) -> PathBuf {
+  pro::ProcSource::default().proc(pid.to_string())
+}
+
+// Runs an action of `pro`, returning the message it reports on success
+fn act(action: impl FnOnce(&mpsc::Sender<pro::OutputMessage>) -> pro::Result<()>) -> pro::Result<Value> {
+  let (tx, rx) = mpsc::channel();
+  action(&tx)?;
+  let message = rx.try_iter().map(|m| m.message).collect::<Vec<_>>().join("\n");
+  Ok(json!({ "message": message }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pro::fixture;
+
+  #[test]
+  fn lists_processes() {
+    let response = handle(
+      r#"{"id": 7, "method": "list", "params": {"where": "user=app or user=postgres", "sort_by": "pid"}}"#,
+      1000,
+      &fixture("basic"),
+      &mut pro::Counters::default(),
+    );
+    assert_eq!(response["id"], 7);
+    let pids: Vec<_> = response["result"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|p| p["pid"].clone())
+      .collect();
+    assert_eq!(pids, vec![json!(42), json!(100)]);
+
+    let response = handle(
+      r#"{"method": "info", "params": {"pid": 42}}"#,
+      1000,
+      &fixture("basic"),
+      &mut pro::Counters::default(),
+    );
+    assert_eq!(
+      response["result"]["command"],
+      "/usr/lib/jvm/bin/java -Xmx2g -jar /srv/app/app.jar"
+    );
+  }
+
+  #[test]
+  fn reports_errors_by_kind() {
+    let kind =
+      |line: &str| handle(line, 999, &fixture("basic"), &mut pro::Counters::default())["error"]["kind"].clone();
+    assert_eq!(kind("not json"), "parse");
+    assert_eq!(kind(r#"{"id": 1, "method": "reboot"}"#), "invalid_argument");
+    assert_eq!(
+      kind(r#"{"method": "list", "params": {"nprocs": "all"}}"#),
+      "invalid_argument"
+    );
+    assert_eq!(
+      kind(r#"{"method": "list", "params": {"where": "bogus>1"}}"#),
+      "unknown_field"
+    );
+    assert_eq!(
+      kind(r#"{"method": "kill", "params": {"pid": 42, "signal": "NOPE"}}"#),
+      "invalid_argument"
+    );
+    assert_eq!(
+      kind(r#"{"method": "info", "params": {"pid": 4242}}"#),
+      "process_vanished"
+    );
+  }
+
+  #[test]
+  fn only_root_acts_on_other_users_processes() {
+    // checked against the live /proc, where pid 1 belongs to root, whatever the fixture says
+    let own = std::process::id() as pid_t;
+    let uid = unsafe { libc::getuid() };
+    assert!(authorize(0, 1).is_ok());
+    assert_eq!(authorize(uid, own).unwrap().pid(), own);
+    assert!(matches!(authorize(uid + 1, own), Err(pro::Error::PermissionDenied(_))));
+    let response = handle(
+      r#"{"method": "kill", "params": {"pid": 1}}"#,
+      998,
+      &fixture("basic"),
+      &mut pro::Counters::default(),
+    );
+    assert_eq!(response["error"]["kind"], "permission_denied");
+    let response = handle(
+      r#"{"method": "renice", "params": {"pid": 1, "priority": 5}}"#,
+      999,
+      &fixture("basic"),
+      &mut pro::Counters::default(),
+    );
+    assert_eq!(response["error"]["kind"], "permission_denied");
+  }
+
+  #[test]
+  fn hangs_up_on_overlong_requests() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let daemon = std::thread::spawn(move || serve_client(server, &fixture("basic")));
+    let mut writer = &client;
+    writeln!(writer, r#"{{"id": 1, "method": "system"}}"#).unwrap();
+    writer.write_all(&vec![b' '; MAX_REQUEST + 1]).unwrap();
+    let lines: Vec<Value> = BufReader::new(&client)
+      .lines()
+      .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+      .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["id"], 1);
+    assert_eq!(lines[1]["error"]["kind"], "invalid_argument");
+    daemon.join().unwrap().unwrap();
+  }
+
+  #[test]
+  fn rejects_cpus_out_of_range() {
+    let response = handle(
+      &format!(
+        r#"{{"method": "affinity", "params": {{"pid": {}, "cpus": [5000]}}}}"#,
+        std::process::id()
+      ),
+      unsafe { libc::getuid() },
+      &fixture("basic"),
+      &mut pro::Counters::default(),
+    );
+    assert_eq!(response["error"]["kind"], "invalid_argument");
+  }
+}
@@ -1,92 +1,559 @@
+use crate::config::Config;
 use crate::pro;
-use iced::widget::{button, column, container, row, text, text_input, Scrollable, Space};
-use iced::{Alignment, Application, Command, Element, Length, Settings};
+use crate::tabs::Tab;
+use iced::futures::SinkExt;
+use iced::widget::{button, canvas, column, container, pick_list, row, text, text_input, Scrollable, Space};
+use iced::{event, mouse, Alignment, Application, Command, Element, Length, Settings, Subscription};
 use libc::pid_t;
-use std::sync::{mpsc, Arc, Mutex};
+use plotters::coord::ReverseCoordTranslate;
+use plotters::prelude::*;
+use plotters_iced::{Chart, ChartWidget, DrawingBackend};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, Instant};
+
+// Two clicks on the same row within this open its details
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+const CPU_COLOR: RGBColor = RGBColor(0, 120, 200);
+const MEMORY_COLOR: RGBColor = RGBColor(0, 150, 60);
+const SWAP_COLOR: RGBColor = RGBColor(220, 160, 0);
+const READ_COLOR: RGBColor = RGBColor(0, 150, 60);
+const WRITE_COLOR: RGBColor = RGBColor(200, 40, 40);
+
+fn format_percent(value: f64) -> String {
+  format!("{:.0}%", value)
+}
+
+// How much of the history the charts show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeWindow {
+  Minute,
+  FiveMinutes,
+  FifteenMinutes,
+  All,
+}
+
+impl TimeWindow {
+  const ALL: [TimeWindow; 4] = [
+    TimeWindow::Minute,
+    TimeWindow::FiveMinutes,
+    TimeWindow::FifteenMinutes,
+    TimeWindow::All,
+  ];
+
+  // The number of samples in the window at one sample every `refresh_rate` seconds
+  fn samples(self, refresh_rate: u64) -> usize {
+    let seconds = match self {
+      TimeWindow::Minute => 60,
+      TimeWindow::FiveMinutes => 300,
+      TimeWindow::FifteenMinutes => 900,
+      TimeWindow::All => return usize::MAX,
+    };
+    (seconds / refresh_rate.max(1)) as usize + 1
+  }
+}
+
+impl fmt::Display for TimeWindow {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      TimeWindow::Minute => "Last minute",
+      TimeWindow::FiveMinutes => "Last 5 minutes",
+      TimeWindow::FifteenMinutes => "Last 15 minutes",
+      TimeWindow::All => "Everything kept",
+    })
+  }
+}
+
+// The system samples behind the charts, one per sample of the sampler and `capacity` at most like the
+// TUI's
+struct History {
+  capacity: usize,
+  // seconds since the Unix epoch
+  timestamps: Vec<f64>,
+  // total first, then one per core
+  cpu: Vec<Vec<f64>>,
+  // percent used
+  memory: Vec<f64>,
+  swap: Vec<f64>,
+  // 1 minute average
+  load: Vec<f64>,
+  // read and write rates per device
+  disks: BTreeMap<String, (Vec<f64>, Vec<f64>)>,
+  // receive and transmit rates per interface
+  networks: BTreeMap<String, (Vec<f64>, Vec<f64>)>,
+}
+
+fn push_sample(samples: &mut Vec<f64>, value: f64, capacity: usize) {
+  if samples.len() >= capacity {
+    samples.remove(0);
+  }
+  samples.push(value);
+}
+
+impl History {
+  fn new(capacity: usize) -> Self {
+    History {
+      capacity,
+      timestamps: Vec::new(),
+      cpu: Vec::new(),
+      memory: Vec::new(),
+      swap: Vec::new(),
+      load: Vec::new(),
+      disks: BTreeMap::new(),
+      networks: BTreeMap::new(),
+    }
+  }
+
+  fn update(&mut self, sample: &pro::sampler::Sample) {
+    let capacity = self.capacity;
+    let snapshot = &sample.snapshot;
+    push_sample(&mut self.timestamps, snapshot.timestamp, capacity);
+
+    self.cpu.resize_with(snapshot.cpu_percent.len(), Vec::new);
+    for (samples, &usage) in self.cpu.iter_mut().zip(&snapshot.cpu_percent) {
+      push_sample(samples, usage, capacity);
+    }
+
+    let system = &snapshot.system;
+    let percent = |free: u64, total: u64| {
+      if total == 0 {
+        0.0
+      } else {
+        (total - free) as f64 / total as f64 * 100.0
+      }
+    };
+    push_sample(
+      &mut self.memory,
+      percent(system.free_ram_bytes, system.total_ram_bytes),
+      capacity,
+    );
+    push_sample(
+      &mut self.swap,
+      percent(system.free_swap_bytes, system.total_swap_bytes),
+      capacity,
+    );
+    push_sample(&mut self.load, system.load_average[0], capacity);
+
+    // the first sample has no rates
+    for (device, read, write) in &sample.disk_rates {
+      let (reads, writes) = self.disks.entry(device.clone()).or_default();
+      push_sample(reads, *read, capacity);
+      push_sample(writes, *write, capacity);
+    }
+    for (interface, received, transmitted) in &sample.network_rates {
+      let (rx, tx) = self.networks.entry(interface.clone()).or_default();
+      push_sample(rx, *received, capacity);
+      push_sample(tx, *transmitted, capacity);
+    }
+  }
+}
+
+// The last `samples` of `data`
+fn window(data: &[f64], samples: usize) -> &[f64] {
+  &data[data.len().saturating_sub(samples)..]
+}
+
+// Distinct colors for the cores
+fn series_color(index: usize) -> RGBColor {
+  let (r, g, b) = Palette99::pick(index).rgb();
+  RGBColor(r, g, b)
+}
+
+// Columns of the process table, in display order
+const COLUMNS: [pro::Field; 12] = [
+  pro::Field::User,
+  pro::Field::Pid,
+  pro::Field::Cpu,
+  pro::Field::Memory,
+  pro::Field::Priority,
+  pro::Field::State,
+  pro::Field::Threads,
+  pro::Field::VirtualMemory,
+  pro::Field::UserTime,
+  pro::Field::SystemTime,
+  pro::Field::Name,
+  pro::Field::Command,
+];
+
+fn column_width(field: pro::Field) -> Length {
+  match field {
+    pro::Field::Name => Length::FillPortion(3),
+    pro::Field::Command => Length::FillPortion(4),
+    _ => Length::FillPortion(1),
+  }
+}
 
 // Main application state
 struct ProcessManagerApp {
+  source: pro::ProcSource,
   processes: Vec<pro::ProcessInfo>,
   filtered_processes: Vec<pro::ProcessInfo>,
-  sort_column: String,
+  sort_column: pro::Field,
   sort_ascending: bool,
   search_input: String,
   selected_process_pid: Option<pid_t>,
   show_help: bool,
-  receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
-  cpu_usages: Vec<f64>,
+  kill_dialog: Option<KillDialog>,
+  // shared with the subscription that delivers its samples
+  sampler: Arc<pro::sampler::Sampler>,
+  latest: Option<Arc<pro::sampler::Sample>>,
+  // samples kept for the charts
+  history: usize,
+  system_history: History,
+  time_window: TimeWindow,
+  refresh_rate: u64,
+  current_tab: Tab,
+  last_click: Option<(pid_t, Instant)>,
+  detail: Option<DetailPanel>,
+}
+
+// Opened by double-clicking a row: everything about one process, charts sampled while it is open
+// and actions on it
+struct DetailPanel {
+  pid: pid_t,
+  details: Option<pro::detail::ProcessDetails>,
+  cpu: Vec<f64>,
+  // MiB
+  memory: Vec<f64>,
+  io_read: Vec<f64>,
+  io_write: Vec<f64>,
+  priority: String,
+  cpus: String,
+  // the outcome of the last refresh or action, and whether it failed
+  message: Option<(String, bool)>,
+}
+
+impl DetailPanel {
+  fn new(pid: pid_t) -> Self {
+    DetailPanel {
+      pid,
+      details: None,
+      cpu: Vec::new(),
+      memory: Vec::new(),
+      io_read: Vec::new(),
+      io_write: Vec::new(),
+      priority: String::new(),
+      cpus: String::new(),
+      message: None,
+    }
+  }
+
+  // `process` is the entry of the latest sample, which has the cpu and I/O rates
+  fn update(&mut self, source: &pro::ProcSource, process: Option<&pro::ProcessInfo>, history: usize) {
+    let details = match pro::detail::read_process_details(source, self.pid) {
+      Ok(details) => details,
+      // keep showing what we had
+      Err(e) => {
+        self.message = Some((e.to_string(), true));
+        return;
+      }
+    };
+    let process = process.filter(|p| p.start_time == details.info.start_time);
+    for (samples, value) in [
+      (&mut self.cpu, process.map_or(0.0, |p| p.cpu_percent)),
+      (&mut self.memory, details.info.memory as f64 / 1024.0),
+      (&mut self.io_read, process.map_or(0.0, |p| p.io_read_rate)),
+      (&mut self.io_write, process.map_or(0.0, |p| p.io_write_rate)),
+    ] {
+      if samples.len() >= history {
+        samples.remove(0);
+      }
+      samples.push(value);
+    }
+    if self.priority.is_empty() {
+      self.priority = details.info.priority.to_string();
+    }
+    self.details = Some(details);
+  }
+
+  // Runs an action of `pro` and keeps the message it reports
+  fn act(&mut self, action: impl FnOnce(&mpsc::Sender<pro::OutputMessage>) -> pro::Result<()>) {
+    let (tx, rx) = mpsc::channel();
+    self.message = Some(match action(&tx) {
+      Ok(()) => (rx.try_iter().map(|m| m.message).collect::<Vec<_>>().join("\n"), false),
+      Err(e) => (e.to_string(), true),
+    });
+  }
+}
+
+// A line chart with one sample per tick, the newest on the right. Series with fewer samples, like
+// a disk that showed up later, end on the right as well.
+struct LineChart<'a> {
+  series: Vec<(String, RGBColor, &'a [f64])>,
+  format: fn(f64) -> String,
+  // the top of the y axis, from the data if None
+  max: Option<f64>,
+  // each series drawn on top of the ones before it
+  stacked: bool,
+  area: bool,
+  // of the samples, for the tooltip
+  timestamps: &'a [f64],
+}
+
+impl<'a> LineChart<'a> {
+  fn new(series: Vec<(String, RGBColor, &'a [f64])>, format: fn(f64) -> String) -> Self {
+    LineChart {
+      series,
+      format,
+      max: None,
+      stacked: false,
+      area: false,
+      timestamps: &[],
+    }
+  }
+
+  fn max(mut self, max: f64) -> Self {
+    self.max = Some(max);
+    self
+  }
+
+  fn stacked(mut self) -> Self {
+    self.stacked = true;
+    self
+  }
+
+  fn area(mut self) -> Self {
+    self.area = true;
+    self
+  }
+
+  fn timestamps(mut self, timestamps: &'a [f64]) -> Self {
+    self.timestamps = timestamps;
+    self
+  }
+
+  fn samples(&self) -> usize {
+    self
+      .series
+      .iter()
+      .map(|(_, _, data)| data.len())
+      .max()
+      .unwrap_or_default()
+  }
+
+  // The points of every series, summed up with the ones before if stacked
+  fn points(&self) -> Vec<Vec<(f64, f64)>> {
+    let samples = self.samples();
+    let mut below = vec![0.0; samples];
+    self
+      .series
+      .iter()
+      .map(|(_, _, data)| {
+        let offset = samples - data.len();
+        data
+          .iter()
+          .enumerate()
+          .map(|(i, &y)| {
+            let y = if self.stacked { below[offset + i] + y } else { y };
+            below[offset + i] = y;
+            ((offset + i) as f64, y)
+          })
+          .collect()
+      })
+      .collect()
+  }
+
+  // The time and the value of every series at sample `index`
+  fn tooltip(&self, index: usize) -> Vec<String> {
+    let samples = self.samples();
+    let mut lines = vec![match self
+      .timestamps
+      .get(index + self.timestamps.len() - samples.min(self.timestamps.len()))
+    {
+      // just the time of day
+      Some(&timestamp) => {
+        let time = pro::record::format_timestamp(timestamp);
+        time.get(11..).unwrap_or(&time).to_string()
+      }
+      None => format!("sample {}", index + 1),
+    }];
+    for (name, _, data) in &self.series {
+      if let Some(&value) = (index + data.len()).checked_sub(samples).and_then(|i| data.get(i)) {
+        lines.push(format!("{}: {}", name, (self.format)(value)));
+      }
+    }
+    lines
+  }
+}
+
+impl Chart<Message> for LineChart<'_> {
+  // where the mouse is, for the tooltip
+  type State = Option<iced::Point>;
+
+  fn build_chart<DB: DrawingBackend>(&self, state: &Self::State, mut builder: ChartBuilder<DB>) {
+    let samples = self.samples();
+    let points = self.points();
+    let max = self.max.unwrap_or_else(|| {
+      let max = points.iter().flatten().fold(0.0, |a: f64, &(_, y)| a.max(y));
+      // leave room above the line, and keep an idle process from drawing a flat axis
+      (max * 1.1).max(1.0)
+    });
+    let Ok(mut chart) = builder
+      .margin(5)
+      .x_label_area_size(0)
+      .y_label_area_size(70)
+      .build_cartesian_2d(0.0..samples.max(2) as f64 - 1.0, 0.0..max)
+    else {
+      return;
+    };
+    let _ = chart
+      .configure_mesh()
+      .disable_x_mesh()
+      .y_labels(5)
+      .y_label_formatter(&|y| (self.format)(*y))
+      .draw();
+    // stacked areas are drawn from the top so each covers only its own band
+    for ((name, color, _), points) in self.series.iter().zip(points).rev() {
+      let color = *color;
+      let drawn = if self.area {
+        chart.draw_series(AreaSeries::new(points, 0.0, color.mix(0.3)).border_style(color.stroke_width(2)))
+      } else {
+        chart.draw_series(LineSeries::new(points, color.stroke_width(2)))
+      };
+      if let Ok(series) = drawn {
+        series
+          .label(name.as_str())
+          .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 15, y)], color));
+      }
+    }
+    if self.series.len() > 1 {
+      let _ = chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw();
+    }
+
+    let Some(point) = state else {
+      return;
+    };
+    let Some((x, _)) = chart
+      .as_coord_spec()
+      .reverse_translate((point.x as i32, point.y as i32))
+    else {
+      return;
+    };
+    if samples == 0 || x < -0.5 {
+      return;
+    }
+    let index = (x.round() as usize).min(samples - 1);
+    let lines = self.tooltip(index);
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or_default() as i32 * 7 + 10;
+    let height = lines.len() as i32 * 16 + 6;
+    // keep the tooltip inside the chart
+    let left = if index > samples / 2 { -width - 5 } else { 5 };
+    let x = index as f64;
+    let _ = chart.draw_series(std::iter::once(PathElement::new(
+      vec![(x, 0.0), (x, max)],
+      BLACK.mix(0.4),
+    )));
+    let plotting_area = chart.plotting_area();
+    let _ = plotting_area.draw(
+      &(EmptyElement::at((x, max))
+        + Rectangle::new([(left, 5), (left + width, 5 + height)], WHITE.mix(0.9).filled())
+        + Rectangle::new([(left, 5), (left + width, 5 + height)], BLACK.mix(0.5))),
+    );
+    for (i, line) in lines.iter().enumerate() {
+      let _ = plotting_area.draw(
+        &(EmptyElement::at((x, max)) + Text::new(line.clone(), (left + 5, 8 + i as i32 * 16), ("sans-serif", 14))),
+      );
+    }
+  }
+
+  fn update(
+    &self,
+    state: &mut Self::State,
+    event: canvas::Event,
+    bounds: iced::Rectangle,
+    cursor: mouse::Cursor,
+  ) -> (event::Status, Option<Message>) {
+    if let canvas::Event::Mouse(_) = event {
+      *state = cursor.position_in(bounds);
+    }
+    (event::Status::Ignored, None)
+  }
+}
+
+fn chart<'a>(title: &str, chart: LineChart<'a>) -> Element<'a, Message> {
+  column![
+    text(title).size(16),
+    ChartWidget::new(chart).width(Length::Fill).height(Length::Fixed(150.0))
+  ]
+  .spacing(5)
+  .into()
+}
+
+// Opened by the Kill button: pick a signal, then confirm sending it
+struct KillDialog {
+  pid: pid_t,
+  name: String,
+  signal: &'static str,
+  error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 enum Message {
-  SortByName,
-  SortByPid,
-  SortByUser,
-  SortByPriority,
-  SortByState,
-  SortByThreads,
-  SortByUserTime,
-  SortBySystemTime,
-  SortByVMSize,
-  SortByMemory,
+  SortBy(pro::Field),
   SearchInputChanged(String),
   SearchProcess,
   NiceProcess,
   KillProcess,
+  SignalSelected(&'static str),
+  ConfirmKill,
+  CancelKill,
   Quit,
   RefreshProcesses,
   ProcessSelected(pid_t),
+  TabSelected(Tab),
+  TimeWindowSelected(TimeWindow),
+  OpenDetail,
+  CloseDetail,
+  DetailSignal,
+  DetailPriorityChanged(String),
+  DetailRenice,
+  DetailCpusChanged(String),
+  DetailAffinity,
   Help,
   CloseHelp,
-  Tick,
+  Sampled(Arc<pro::sampler::Sample>),
 }
 
 impl Application for ProcessManagerApp {
   type Message = Message;
   type Executor = iced::executor::Default;
   type Theme = iced::Theme;
-  type Flags = ();
-
-  fn new(_flags: ()) -> (Self, Command<Message>) {
-    let processes = pro::read_processes().unwrap_or_default();
-    let cpu_usages = pro::get_cpu_usage().unwrap_or_default();
-
-    let (sender, receiver) = mpsc::channel();
+  type Flags = (pro::ProcSource, Config);
 
-    // Wrap the receiver in Arc<Mutex<_>> for shared access
-    let receiver = Arc::new(Mutex::new(receiver));
-
-    // Clone the Arc to move into the thread
-    let thread_receiver = receiver.clone();
-
-    // Spawn a background thread to send Tick messages every 2 seconds
-    thread::spawn(move || {
-      loop {
-        unsafe {
-          libc::sleep(2); // Sleep for 2 seconds
-        }
-        if sender.send(Message::Tick).is_err() {
-          // If the receiver has been dropped, exit the loop
-          break;
-        }
-      }
-    });
+  fn new((source, config): Self::Flags) -> (Self, Command<Message>) {
+    let refresh_rate = Duration::from_secs(config.gui.refresh_rate);
+    let sampler = Arc::new(pro::sampler::Sampler::spawn(source.clone(), refresh_rate));
 
     let mut app = Self {
-      processes: processes.clone(),
-      filtered_processes: processes,
-      sort_column: "pid".to_string(),
-      sort_ascending: true,
+      source,
+      processes: Vec::new(),
+      filtered_processes: Vec::new(),
+      sort_column: config.sort_by,
+      sort_ascending: !config.descending,
       search_input: String::new(),
       selected_process_pid: None,
       show_help: false,
-      receiver: thread_receiver,
-      cpu_usages,
+      kill_dialog: None,
+      sampler,
+      latest: None,
+      history: config.history,
+      system_history: History::new(config.history),
+      time_window: TimeWindow::FiveMinutes,
+      refresh_rate: config.gui.refresh_rate,
+      current_tab: Tab::Processes,
+      last_click: None,
+      detail: None,
     };
     app.apply_filters_and_sorting();
-    let command = Self::listen_for_tick(Arc::clone(&receiver));
-    (app, command)
+    (app, Command::none())
   }
 
   fn title(&self) -> String {
@@ -95,126 +562,126 @@ impl Application for ProcessManagerApp {
 
   fn update(&mut self, message: Message) -> Command<Message> {
     match message {
-      Message::SortByName => {
-        if self.sort_column == "name" {
+      Message::SortBy(field) => {
+        if self.sort_column == field {
           self.sort_ascending = !self.sort_ascending;
         } else {
-          self.sort_column = "name".to_string();
+          self.sort_column = field;
           self.sort_ascending = true;
         }
         self.apply_filters_and_sorting();
       }
-      Message::SortByPid => {
-        if self.sort_column == "pid" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "pid".to_string();
-          self.sort_ascending = true;
-        }
+      Message::SearchInputChanged(input) => {
+        self.search_input = input;
         self.apply_filters_and_sorting();
       }
-      Message::SortByUser => {
-        if self.sort_column == "user" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "user".to_string();
-          self.sort_ascending = true;
-        }
+      Message::SearchProcess => {
         self.apply_filters_and_sorting();
       }
-      Message::SortByPriority => {
-        if self.sort_column == "priority" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "priority".to_string();
-          self.sort_ascending = true;
+      Message::NiceProcess => {
+        if let Some(pid) = self.selected_process_pid {
+          if let Err(e) = pro::set_priority(pid, 10, None) {
+            eprintln!("Failed to set priority of process {}: {}", pid, e);
+          }
         }
-        self.apply_filters_and_sorting();
       }
-      Message::SortByMemory => {
-        if self.sort_column == "memory" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "memory".to_string();
-          self.sort_ascending = true;
+      Message::KillProcess => {
+        if let Some(process) = self.processes.iter().find(|p| Some(p.pid) == self.selected_process_pid) {
+          self.kill_dialog = Some(KillDialog {
+            pid: process.pid,
+            name: process.name.clone(),
+            signal: "TERM",
+            error: None,
+          });
         }
-        self.apply_filters_and_sorting();
       }
-      Message::SortByVMSize => {
-        if self.sort_column == "vmsize" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "vmsize".to_string();
-          self.sort_ascending = true;
+      Message::SignalSelected(signal) => {
+        if let Some(dialog) = &mut self.kill_dialog {
+          dialog.signal = signal;
         }
-        self.apply_filters_and_sorting();
       }
-      Message::SortByState => {
-        if self.sort_column == "state" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "state".to_string();
-          self.sort_ascending = true;
+      Message::ConfirmKill => {
+        if let Some(dialog) = &mut self.kill_dialog {
+          match pro::parse_signal(dialog.signal).and_then(|signal| pro::kill_process(dialog.pid, signal, None)) {
+            Ok(()) => self.kill_dialog = None,
+            // keep the dialog open so the error is visible
+            Err(e) => dialog.error = Some(e.to_string()),
+          }
         }
-        self.apply_filters_and_sorting();
       }
-      Message::SortByThreads => {
-        if self.sort_column == "threads" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "threads".to_string();
-          self.sort_ascending = true;
-        }
-        self.apply_filters_and_sorting();
+      Message::CancelKill => {
+        self.kill_dialog = None;
       }
-      Message::SortByUserTime => {
-        if self.sort_column == "utime" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "utime".to_string();
-          self.sort_ascending = true;
+      Message::RefreshProcesses => {
+        // the sample comes in like any other
+        self.sampler.refresh();
+      }
+      Message::Quit => {
+        std::process::exit(0);
+      }
+      Message::ProcessSelected(pid) => {
+        let now = Instant::now();
+        let double_click = matches!(self.last_click, Some((last, time)) if last == pid && now - time < DOUBLE_CLICK);
+        self.selected_process_pid = Some(pid);
+        self.last_click = Some((pid, now));
+        if double_click {
+          return self.update(Message::OpenDetail);
         }
-        self.apply_filters_and_sorting();
       }
-      Message::SortBySystemTime => {
-        if self.sort_column == "stime" {
-          self.sort_ascending = !self.sort_ascending;
-        } else {
-          self.sort_column = "stime".to_string();
-          self.sort_ascending = true;
+      Message::OpenDetail => {
+        if let Some(pid) = self.selected_process_pid {
+          let mut detail = DetailPanel::new(pid);
+          detail.update(&self.source, self.processes.iter().find(|p| p.pid == pid), self.history);
+          self.detail = Some(detail);
+          self.last_click = None;
         }
-        self.apply_filters_and_sorting();
       }
-      Message::SearchInputChanged(input) => {
-        self.search_input = input;
-        self.apply_filters_and_sorting();
+      Message::TabSelected(tab) => {
+        self.current_tab = tab;
       }
-      Message::SearchProcess => {
-        self.apply_filters_and_sorting();
+      Message::TimeWindowSelected(time_window) => {
+        self.time_window = time_window;
       }
-      Message::NiceProcess => {
-        if let Some(pid) = self.selected_process_pid {
-          pro::set_priority(pid, 10, None);
-          println!("Changed priority of process {}", pid);
+      Message::CloseDetail => {
+        self.detail = None;
+      }
+      Message::DetailSignal => {
+        if let Some(details) = self.detail.as_ref().and_then(|d| d.details.as_ref()) {
+          self.kill_dialog = Some(KillDialog {
+            pid: details.info.pid,
+            name: details.info.name.clone(),
+            signal: "TERM",
+            error: None,
+          });
         }
       }
-      Message::KillProcess => {
-        if let Some(pid) = self.selected_process_pid {
-          pro::kill_process(pid, libc::SIGTERM, None);
-          println!("Sent SIGTERM to process {}", pid);
+      Message::DetailPriorityChanged(priority) => {
+        if let Some(detail) = &mut self.detail {
+          detail.priority = priority;
         }
       }
-      Message::RefreshProcesses => {
-        if let Ok(new_processes) = pro::read_processes() {
-          self.processes = new_processes;
-          self.apply_filters_and_sorting();
+      Message::DetailRenice => {
+        if let Some(detail) = &mut self.detail {
+          let pid = detail.pid;
+          match detail.priority.trim().parse() {
+            Ok(priority) => detail.act(|tx| pro::set_priority(pid, priority, Some(tx))),
+            Err(_) => detail.message = Some((format!("invalid priority '{}'", detail.priority), true)),
+          }
         }
       }
-      Message::Quit => {
-        std::process::exit(0);
+      Message::DetailCpusChanged(cpus) => {
+        if let Some(detail) = &mut self.detail {
+          detail.cpus = cpus;
+        }
       }
-      Message::ProcessSelected(pid) => {
-        self.selected_process_pid = Some(pid);
+      Message::DetailAffinity => {
+        if let Some(detail) = &mut self.detail {
+          let pid = detail.pid;
+          match pro::parse_cpus(&detail.cpus) {
+            Ok(cpus) => detail.act(|tx| pro::bind_to_cpu_set(pid, &cpus, Some(tx))),
+            Err(e) => detail.message = Some((e.to_string(), true)),
+          }
+        }
       }
       Message::Help => {
         self.show_help = true;
@@ -222,35 +689,85 @@ impl Application for ProcessManagerApp {
       Message::CloseHelp => {
         self.show_help = false;
       }
-      Message::Tick => {
-        // Periodic update
-        if let Ok(new_processes) = pro::read_processes() {
-          self.processes = new_processes;
-          self.apply_filters_and_sorting();
-        }
-        if let Ok(new_cpu_usages) = pro::get_cpu_usage() {
-          self.cpu_usages = new_cpu_usages;
+      Message::Sampled(sample) => {
+        self.processes = sample.snapshot.processes.clone();
+        self.apply_filters_and_sorting();
+        self.system_history.update(&sample);
+        if let Some(detail) = &mut self.detail {
+          let process = self.processes.iter().find(|p| p.pid == detail.pid);
+          detail.update(&self.source, process, self.history);
         }
-        // Schedule the next Tick
-        return Self::listen_for_tick(Arc::clone(&self.receiver));
+        self.latest = Some(sample);
       }
     }
     Command::none()
   }
 
-  fn view(&self) -> Element<Message> {
-    if self.show_help {
+  fn subscription(&self) -> Subscription<Message> {
+    let sampler = Arc::clone(&self.sampler);
+    // iced runs this once, then keeps the thread handing over the samples for the life of the app
+    iced::subscription::channel(
+      std::any::TypeId::of::<pro::sampler::Sampler>(),
+      1,
+      |mut output| async move {
+        let samples = sampler.subscribe();
+        thread::spawn(move || {
+          for sample in samples {
+            if iced::futures::executor::block_on(output.send(Message::Sampled(sample))).is_err() {
+              break;
+            }
+          }
+        });
+        iced::futures::future::pending().await
+      },
+    )
+  }
+
+  fn view(&self) -> Element<'_, Message> {
+    if let Some(dialog) = &self.kill_dialog {
+      let signals: Vec<&'static str> = pro::SIGNALS.iter().map(|&(name, _)| name).collect();
+      let mut content = column![
+        text(format!("Send a signal to {} ({})", dialog.name, dialog.pid)).size(30),
+        pick_list(signals, Some(dialog.signal), Message::SignalSelected),
+        text(format!(
+          "Send SIG{} to {} ({})?",
+          dialog.signal, dialog.name, dialog.pid
+        ))
+        .size(20),
+        row![
+          button("Send").on_press(Message::ConfirmKill),
+          button("Cancel").on_press(Message::CancelKill),
+        ]
+        .spacing(10),
+      ]
+      .padding(20)
+      .spacing(10);
+      if let Some(error) = &dialog.error {
+        content = content.push(text(error).style(iced::Color::from_rgb(0.8, 0.1, 0.1)));
+      }
+
+      container(content)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x()
+        .center_y()
+        .into()
+    } else if self.show_help {
       // Display help content
       let content = column![
         text("Help").size(30),
         text("This is a Linux Process Manager application.").size(20),
         text("Use the buttons to sort, filter, and manage processes.").size(20),
-        text("Select a process by clicking on it in the list.").size(20),
+        text("Select a process by clicking on it in the list, double-click it for its details.").size(20),
+        text("Search by name or user, or with an expression like: user=root and (mem>500M or cpu>50)").size(20),
         text("Then you can kill or nice the selected process.").size(20),
+        text("The tabs show the processes and the history of CPU, memory, load, disk and network usage.").size(20),
+        text("Hover over a chart to see its values.").size(20),
         text("Buttons:").size(20),
         text("- Help: Show this help message.").size(16),
+        text("- Details: Show everything about the selected process, with charts and actions.").size(16),
         text("- Nice: Change the priority of the selected process.").size(16),
-        text("- Kill: Terminate the selected process.").size(16),
+        text("- Kill: Send a signal to the selected process, after confirmation.").size(16),
         text("- Refresh: Manually refresh the process list.").size(16),
         text("- Quit: Exit the application.").size(16),
         button("Close").on_press(Message::CloseHelp),
@@ -264,15 +781,19 @@ impl Application for ProcessManagerApp {
         .center_x()
         .center_y()
         .into()
+    } else if let Some(detail) = &self.detail {
+      self.render_detail(detail)
     } else {
-      let system_info = self.render_system_info();
-      let cpu_graph = self.render_cpu_usage_graph();
-      let process_table = self.render_process_table();
-      let action_buttons = self.render_action_buttons();
-
-      let top_row = row![system_info, cpu_graph].spacing(10);
-
-      let content = column![top_row, process_table, action_buttons].spacing(10).padding(10);
+      let tab: Element<Message> = match self.current_tab {
+        Tab::Processes => column![self.render_process_table(), self.render_action_buttons()]
+          .spacing(10)
+          .into(),
+        Tab::Cpu => self.render_cpu_tab(),
+        Tab::System => self.render_system_tab(),
+        Tab::Disk => self.render_rates_tab("Disk", &self.system_history.disks, ("read", "write")),
+        Tab::Network => self.render_rates_tab("Network", &self.system_history.networks, ("received", "transmitted")),
+      };
+      let content = column![self.render_tabs(), tab].spacing(10).padding(10);
 
       container(content)
         .width(Length::Fill)
@@ -285,150 +806,252 @@ impl Application for ProcessManagerApp {
 }
 
 impl ProcessManagerApp {
-  fn listen_for_tick(receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Command<Message> {
-    // Spawn a blocking task to wait for Tick
-    Command::perform(
-      async move {
-        // Run recv in a blocking thread
-        let receiver = Arc::clone(&receiver);
-        let msg = thread::spawn(move || {
-          let lock = receiver.lock().unwrap();
-          lock.recv().unwrap()
-        })
-        .join()
-        .unwrap();
-        msg
-      },
-      |msg| msg,
-    )
-  }
   fn apply_filters_and_sorting(&mut self) {
-    // Filter processes according to self.search_input
+    // Filter processes according to self.search_input, which is either a filter expression
+    // (e.g. "user=root and mem>100M") or a substring of the name or user
     if self.search_input.is_empty() {
       self.filtered_processes = self.processes.clone();
+    } else if let Ok(query) = self.search_input.parse::<pro::query::Query>() {
+      self.filtered_processes = self.processes.iter().filter(|p| query.matches(p)).cloned().collect();
     } else {
       let pattern = self.search_input.to_lowercase();
       self.filtered_processes = self
         .processes
         .iter()
-        .filter(|p| p.name.to_lowercase().contains(&pattern) || p.user.to_lowercase().contains(&pattern))
+        .filter(|p| {
+          p.name.to_lowercase().contains(&pattern)
+            || p.user.to_lowercase().contains(&pattern)
+            || p.command.to_lowercase().contains(&pattern)
+        })
         .cloned()
         .collect();
     }
 
     // Sort processes according to self.sort_column and self.sort_ascending
-    match self.sort_column.as_str() {
-      "name" => self.filtered_processes.sort_by_key(|p| p.name.clone()),
-      "pid" => self.filtered_processes.sort_by_key(|p| p.pid),
-      "user" => self.filtered_processes.sort_by_key(|p| p.user.clone()),
-      "priority" => self.filtered_processes.sort_by_key(|p| p.priority),
-      "memory" => self.filtered_processes.sort_by_key(|p| p.memory),
-      "vmsize" => self.filtered_processes.sort_by_key(|p| p.virtual_memory),
-      "state" => self.filtered_processes.sort_by_key(|p| p.state),
-      "threads" => self.filtered_processes.sort_by_key(|p| p.thread_count),
-      "utime" => self.filtered_processes.sort_by_key(|p| p.user_time),
-      "stime" => self.filtered_processes.sort_by_key(|p| p.system_time),
-      _ => {}
-    }
+    let sort_column = self.sort_column;
+    self.filtered_processes.sort_by(|a, b| sort_column.compare(a, b));
 
     if !self.sort_ascending {
       self.filtered_processes.reverse();
     }
   }
 
-  fn render_system_info(&self) -> Element<Message> {
-    let system_info = pro::get_sysinfo();
-    let cpu_usages = pro::get_cpu_usage().unwrap_or_default();
-    let mem_unit = 1_000_000 / system_info.mem_unit as u64;
-
-    let total_cpu = if let Some(&usage) = cpu_usages.first() {
-      usage
-    } else {
-      0.0
-    };
+  fn render_tabs(&self) -> Element<'_, Message> {
+    let mut tabs = row![].spacing(5).align_items(Alignment::Center);
+    for tab in Tab::ALL {
+      let style = if tab == self.current_tab {
+        iced::theme::Button::Primary
+      } else {
+        iced::theme::Button::Secondary
+      };
+      tabs = tabs.push(
+        button(text(tab.to_string()))
+          .style(style)
+          .on_press(Message::TabSelected(tab)),
+      );
+    }
+    // the charts of every tab but the processes share the window
+    if self.current_tab != Tab::Processes {
+      tabs = tabs.push(Space::with_width(Length::Fill)).push(pick_list(
+        &TimeWindow::ALL[..],
+        Some(self.time_window),
+        Message::TimeWindowSelected,
+      ));
+    }
+    tabs.into()
+  }
 
-    let info_text = column![
-      text(format!("Total RAM: {} MB", system_info.totalram / mem_unit)),
-      text(format!("Shared RAM: {} MB", system_info.sharedram / mem_unit)),
-      text(format!("Free RAM: {} MB", system_info.freeram / mem_unit)),
-      text(format!("Buffer RAM: {} MB", system_info.bufferram / mem_unit)),
-      text(format!("Total Swap: {} MB", system_info.totalswap / mem_unit)),
-      text(format!("Free Swap: {} MB", system_info.freeswap / mem_unit)),
-      text(format!("Uptime: {} seconds", system_info.uptime)),
-      text(format!("Loads: {:?}", system_info.loads)),
-      text("CPU Usage:"),
-      text(format!("Total: {:.2}%", total_cpu)),
+  fn render_cpu_tab(&self) -> Element<'_, Message> {
+    let samples = self.time_window.samples(self.refresh_rate);
+    let history = &self.system_history;
+    let timestamps = window(&history.timestamps, samples);
+    let total = history.cpu.first().map_or(&[][..], |total| window(total, samples));
+    let cores = history.cpu.iter().skip(1).enumerate();
+    // total first, then one per core like the samples
+    let usage = self
+      .latest
+      .iter()
+      .flat_map(|sample| &sample.snapshot.cpu_percent)
+      .enumerate()
+      .map(|(i, usage)| match i {
+        0 => format!("Total: {:.1}%", usage),
+        i => format!("cpu{}: {:.1}%", i - 1, usage),
+      })
+      .collect::<Vec<_>>()
+      .join("   ");
+    let content = column![
+      chart(
+        "CPU",
+        LineChart::new(vec![("Total".to_string(), CPU_COLOR, total)], format_percent)
+          .max(100.0)
+          .timestamps(timestamps),
+      ),
+      chart(
+        "CPU per core, stacked",
+        LineChart::new(
+          cores
+            .map(|(i, core)| (format!("cpu{}", i), series_color(i), window(core, samples)))
+            .collect(),
+          format_percent,
+        )
+        .max(100.0 * (history.cpu.len().max(2) - 1) as f64)
+        .stacked()
+        .area()
+        .timestamps(timestamps),
+      ),
+      text(usage),
     ]
+    .spacing(20)
+    .padding(10);
+    Scrollable::new(content).height(Length::Fill).into()
+  }
+
+  fn render_system_tab(&self) -> Element<'_, Message> {
+    let samples = self.time_window.samples(self.refresh_rate);
+    let history = &self.system_history;
+    let timestamps = window(&history.timestamps, samples);
+    let info_text = match &self.latest {
+      Some(sample) => {
+        let system = &sample.snapshot.system;
+        let size = |bytes: u64| humansize::format_size(bytes, humansize::BINARY);
+        let [load1, load5, load15] = system.load_average;
+        column![
+          text(format!(
+            "RAM: {} used of {}, {} shared, {} buffers",
+            size(system.total_ram_bytes - system.free_ram_bytes),
+            size(system.total_ram_bytes),
+            size(system.shared_ram_bytes),
+            size(system.buffer_ram_bytes)
+          )),
+          text(format!(
+            "Swap: {} used of {}",
+            size(system.total_swap_bytes - system.free_swap_bytes),
+            size(system.total_swap_bytes)
+          )),
+          text(format!(
+            "Uptime: {}",
+            pro::detail::format_elapsed(system.uptime_seconds.max(0) as u64)
+          )),
+          text(format!("Processes: {}", sample.snapshot.processes.len())),
+          text(format!(
+            "Load averages: 1min: {:.2}, 5min: {:.2}, 15min: {:.2}",
+            load1, load5, load15
+          )),
+        ]
+      }
+      None => column![text("Waiting for the first sample")],
+    }
     .spacing(5);
+    let content = column![
+      chart(
+        "Memory and swap",
+        LineChart::new(
+          vec![
+            ("Memory".to_string(), MEMORY_COLOR, window(&history.memory, samples)),
+            ("Swap".to_string(), SWAP_COLOR, window(&history.swap, samples)),
+          ],
+          format_percent,
+        )
+        .max(100.0)
+        .area()
+        .timestamps(timestamps),
+      ),
+      chart(
+        "Load average (1 min)",
+        LineChart::new(
+          vec![("Load".to_string(), CPU_COLOR, window(&history.load, samples))],
+          |y| format!("{:.2}", y),
+        )
+        .timestamps(timestamps),
+      ),
+      info_text,
+    ]
+    .spacing(20)
+    .padding(10);
+    Scrollable::new(content).height(Length::Fill).into()
+  }
 
-    container(info_text).padding(10).into()
+  // A chart per disk or interface, with the current rates below like the TUI's
+  fn render_rates_tab<'a>(
+    &'a self,
+    kind: &str,
+    rates: &'a BTreeMap<String, (Vec<f64>, Vec<f64>)>,
+    (read, write): (&str, &str),
+  ) -> Element<'a, Message> {
+    let samples = self.time_window.samples(self.refresh_rate);
+    let timestamps = window(&self.system_history.timestamps, samples);
+    let mut content = column![].spacing(20).padding(10);
+    if rates.is_empty() {
+      content = content.push(text(format!(
+        "Waiting for a second sample of the {} counters...",
+        kind.to_lowercase()
+      )));
+    }
+    let mut current = column![text(format!("Current {} rates", kind.to_lowercase())).size(20)].spacing(5);
+    for (name, (reads, writes)) in rates {
+      content = content.push(chart(
+        &format!("{} {}", kind, name),
+        LineChart::new(
+          vec![
+            (read.to_string(), READ_COLOR, window(reads, samples)),
+            (write.to_string(), WRITE_COLOR, window(writes, samples)),
+          ],
+          pro::format_rate,
+        )
+        .timestamps(timestamps),
+      ));
+      current = current.push(text(format!(
+        "{}: {} {}, {} {}",
+        name,
+        read,
+        pro::format_rate(reads.last().copied().unwrap_or_default()),
+        write,
+        pro::format_rate(writes.last().copied().unwrap_or_default())
+      )));
+    }
+    Scrollable::new(content.push(current)).height(Length::Fill).into()
   }
 
-  fn render_process_table(&self) -> Element<Message> {
+  fn render_process_table(&self) -> Element<'_, Message> {
     let processes_list = self
       .filtered_processes
       .iter()
       .map(|process| {
-        let row_content = row![
-          text(&process.user).width(Length::FillPortion(1)),
-          text(process.pid.to_string()).width(Length::FillPortion(1)),
-          text((process.memory / 1000).to_string()).width(Length::FillPortion(1)),
-          text(process.priority.to_string()).width(Length::FillPortion(1)),
-          text(process.state.to_string()).width(Length::FillPortion(1)),
-          text(process.thread_count.to_string()).width(Length::FillPortion(1)),
-          text((process.virtual_memory / 1000).to_string()).width(Length::FillPortion(1)),
-          text(process.user_time.to_string()).width(Length::FillPortion(1)),
-          text(process.system_time.to_string()).width(Length::FillPortion(1)),
-          text(&process.name).width(Length::FillPortion(3)),
-        ]
+        let row_content = row(
+          COLUMNS
+            .iter()
+            .map(|&field| text(field.format(process)).width(column_width(field)).into())
+            .collect::<Vec<Element<Message>>>(),
+        )
         .spacing(15)
         .padding(5)
         .align_items(Alignment::Center);
 
-        let row_element: Element<_> = if Some(process.pid) == self.selected_process_pid {
-          container(row_content)
-            .style(iced::theme::Container::Custom(Box::new(SelectedRowStyle)))
-            .into()
-        } else {
-          button(row_content)
-            .on_press(Message::ProcessSelected(process.pid))
-            .style(iced::theme::Button::Custom(Box::new(RegularRowStyle)))
-            .into()
-        };
-        row_element
+        // the selected row stays clickable for double-clicks
+        let style: Box<dyn iced::widget::button::StyleSheet<Style = iced::Theme>> =
+          if Some(process.pid) == self.selected_process_pid {
+            Box::new(SelectedRowStyle)
+          } else {
+            Box::new(RegularRowStyle)
+          };
+        button(row_content)
+          .on_press(Message::ProcessSelected(process.pid))
+          .style(iced::theme::Button::Custom(style))
+          .into()
       })
       .collect::<Vec<Element<Message>>>();
 
-    let header = row![
-      button("User")
-        .on_press(Message::SortByUser)
-        .width(Length::FillPortion(1)),
-      button("PID").on_press(Message::SortByPid).width(Length::FillPortion(1)),
-      button("Mem")
-        .on_press(Message::SortByMemory)
-        .width(Length::FillPortion(1)),
-      button("Priority")
-        .on_press(Message::SortByPriority)
-        .width(Length::FillPortion(1)),
-      button("State")
-        .on_press(Message::SortByState)
-        .width(Length::FillPortion(1)),
-      button("Threads")
-        .on_press(Message::SortByThreads)
-        .width(Length::FillPortion(1)),
-      button("V_MEM)")
-        .on_press(Message::SortByVMSize)
-        .width(Length::FillPortion(1)),
-      button("U_time")
-        .on_press(Message::SortByUserTime)
-        .width(Length::FillPortion(1)),
-      button("S_time")
-        .on_press(Message::SortBySystemTime)
-        .width(Length::FillPortion(1)),
-      button("Name")
-        .on_press(Message::SortByName)
-        .width(Length::FillPortion(3)),
-    ]
+    let header = row(
+      COLUMNS
+        .iter()
+        .map(|&field| {
+          button(field.title())
+            .on_press(Message::SortBy(field))
+            .width(column_width(field))
+            .into()
+        })
+        .collect::<Vec<Element<Message>>>(),
+    )
     .spacing(15)
     .align_items(Alignment::Center)
     .padding(5);
@@ -442,14 +1065,15 @@ impl ProcessManagerApp {
       .into()
   }
 
-  fn render_action_buttons(&self) -> Element<Message> {
+  fn render_action_buttons(&self) -> Element<'_, Message> {
     let buttons = row![
       button("Help").on_press(Message::Help),
-      text_input("Search", &self.search_input)
+      text_input("Search or filter, e.g. cpu>5", &self.search_input)
         .on_input(Message::SearchInputChanged)
         .on_submit(Message::SearchProcess)
         .padding(5)
         .width(Length::Fixed(200.0)),
+      button("Details").on_press(Message::OpenDetail),
       button("Nice").on_press(Message::NiceProcess),
       button("Kill").on_press(Message::KillProcess),
       button("Refresh").on_press(Message::RefreshProcesses),
@@ -461,103 +1085,146 @@ impl ProcessManagerApp {
     container(buttons).padding(10).center_x().into()
   }
 
-  fn render_cpu_usage_graph(&self) -> Element<Message> {
-    // Create a bar-like representation of CPU usage
-    let cpu_bars = self
-      .cpu_usages
-      .iter()
-      .enumerate()
-      .map(|(i, &usage)| {
-        let bar_height = (usage / 100.0) * 150.0; // Maximum height of 200 pixels
-        let bar_color = if i == 0 {
-          iced::Color::from_rgb(0.2, 0.6, 0.8) // Total CPU in a different color
-        } else {
-          iced::Color::from_rgb(0.4, 0.6, 1.0)
-        };
-
-        let label = if i == 0 {
-          "Total".to_string()
-        } else {
-          format!("Core {}", i - 1)
-        };
-
-        column![
-          container(Space::new(
-            Length::Fixed(20.0),
-            Length::Fixed((150.0 - bar_height) as f32)
-          ))
-          .style(iced::theme::Container::Custom(Box::new(EmptyCpuBarStyle))),
-          container(Space::new(Length::Fixed(20.0), Length::Fixed(bar_height as f32))).style(
-            iced::theme::Container::Custom(Box::new(CpuBarStyle { color: bar_color }))
-          ),
-          text(label)
-            .horizontal_alignment(iced::alignment::Horizontal::Center)
-            .size(12),
-        ]
-        .width(Length::Fixed(20.0))
-        .spacing(2)
-        .into()
-      })
-      .collect::<Vec<Element<Message>>>();
-
-    let cpu_graph_container = row(cpu_bars).spacing(10).align_items(Alignment::End);
-
-    let cpu_graph = column![
-      text("CPU Usage")
-        .size(16)
-        .horizontal_alignment(iced::alignment::Horizontal::Center),
-      cpu_graph_container
+  fn render_detail<'a>(&'a self, detail: &'a DetailPanel) -> Element<'a, Message> {
+    let title = match &detail.details {
+      Some(details) => format!("Process {} ({})", detail.pid, details.info.name),
+      None => format!("Process {}", detail.pid),
+    };
+    let header = row![
+      text(title).size(30).width(Length::Fill),
+      button("Close").on_press(Message::CloseDetail)
     ]
-    .spacing(10)
-    .padding(10);
-
-    container(cpu_graph).style(iced::theme::Container::Box).into()
-  }
-}
-
-struct CpuBarStyle {
-  color: iced::Color,
-}
-
-impl iced::widget::container::StyleSheet for CpuBarStyle {
-  type Style = iced::Theme;
+    .align_items(Alignment::Center);
 
-  fn appearance(&self, _style: &Self::Style) -> iced::widget::container::Appearance {
-    iced::widget::container::Appearance {
-      background: Some(self.color.into()),
-      border: Default::default(),
-      shadow: Default::default(),
-      text_color: None,
+    let mut facts = column![].spacing(5);
+    if let Some(details) = &detail.details {
+      let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+      let section = |title: &str| text(title.to_string()).size(20);
+      for (label, value) in details.overview(now) {
+        facts = facts.push(text(format!("{}: {}", label, value)));
+      }
+      facts = facts.push(section("Memory"));
+      for (key, kb) in &details.memory {
+        facts = facts.push(text(format!(
+          "{}: {}",
+          key,
+          humansize::format_size(kb * 1024, humansize::BINARY)
+        )));
+      }
+      facts = facts.push(section("I/O"));
+      for (key, value) in details.io.iter().flatten() {
+        facts = facts.push(text(format!("{}: {}", key, value)));
+      }
+      facts = facts.push(section("Limits"));
+      for limit in details.limits.iter().flatten() {
+        facts = facts.push(text(format!(
+          "{}: {} / {} {}",
+          limit.name, limit.soft, limit.hard, limit.units
+        )));
+      }
+      facts = facts.push(section("Namespaces"));
+      for (name, target) in details.namespaces.iter().flatten() {
+        facts = facts.push(text(format!("{}: {}", name, target)));
+      }
+      facts = facts.push(section("Status"));
+      for (key, value) in &details.status {
+        facts = facts.push(text(format!("{}: {}", key, value)));
+      }
+      facts = facts.push(section("Environment"));
+      match &details.environment {
+        Some(environment) => {
+          for entry in environment {
+            facts = facts.push(text(entry));
+          }
+        }
+        None => facts = facts.push(text(pro::UNREADABLE)),
+      }
     }
-  }
-}
 
-struct EmptyCpuBarStyle;
+    let charts = column![
+      chart(
+        "CPU %",
+        LineChart::new(vec![("CPU".to_string(), CPU_COLOR, &detail.cpu)], format_percent)
+      ),
+      chart(
+        "RSS",
+        LineChart::new(vec![("RSS".to_string(), MEMORY_COLOR, &detail.memory)], |y| {
+          format!("{:.0} MiB", y)
+        })
+      ),
+      chart(
+        "I/O",
+        LineChart::new(
+          vec![
+            ("read".to_string(), READ_COLOR, &detail.io_read),
+            ("write".to_string(), WRITE_COLOR, &detail.io_write),
+          ],
+          pro::format_rate
+        )
+      ),
+    ]
+    .spacing(10)
+    .width(Length::FillPortion(1));
 
-impl iced::widget::container::StyleSheet for EmptyCpuBarStyle {
-  type Style = iced::Theme;
+    let actions = row![
+      button("Send signal...").on_press(Message::DetailSignal),
+      text_input("nice", &detail.priority)
+        .on_input(Message::DetailPriorityChanged)
+        .on_submit(Message::DetailRenice)
+        .padding(5)
+        .width(Length::Fixed(60.0)),
+      button("Renice").on_press(Message::DetailRenice),
+      text_input("CPUs, e.g. 0,2", &detail.cpus)
+        .on_input(Message::DetailCpusChanged)
+        .on_submit(Message::DetailAffinity)
+        .padding(5)
+        .width(Length::Fixed(120.0)),
+      button("Set affinity").on_press(Message::DetailAffinity),
+    ]
+    .spacing(10)
+    .align_items(Alignment::Center);
 
-  fn appearance(&self, _style: &Self::Style) -> iced::widget::container::Appearance {
-    iced::widget::container::Appearance {
-      background: Some(iced::Color::from_rgb(0.9, 0.9, 0.9).into()),
-      border: Default::default(),
-      shadow: Default::default(),
-      text_color: None,
+    let mut content = column![
+      header,
+      row![
+        Scrollable::new(facts.padding(5))
+          .width(Length::FillPortion(1))
+          .height(Length::Fill),
+        charts
+      ]
+      .spacing(20)
+      .height(Length::Fill),
+      actions
+    ]
+    .spacing(10)
+    .padding(20);
+    if let Some((message, is_error)) = &detail.message {
+      let color = if *is_error {
+        iced::Color::from_rgb(0.8, 0.1, 0.1)
+      } else {
+        iced::Color::from_rgb(0.1, 0.5, 0.1)
+      };
+      content = content.push(text(message).style(color));
     }
+    container(content).width(Length::Fill).height(Length::Fill).into()
   }
 }
 
 struct SelectedRowStyle;
 
-impl iced::widget::container::StyleSheet for SelectedRowStyle {
+impl iced::widget::button::StyleSheet for SelectedRowStyle {
   type Style = iced::Theme;
 
-  fn appearance(&self, _style: &Self::Style) -> iced::widget::container::Appearance {
-    iced::widget::container::Appearance {
+  fn active(&self, _style: &Self::Style) -> iced::widget::button::Appearance {
+    iced::widget::button::Appearance {
       background: Some(iced::Color::from_rgb(0.9, 0.9, 1.0).into()),
       border: Default::default(),
+      shadow_offset: Default::default(),
       shadow: Default::default(),
-      text_color: None,
+      text_color: iced::Color::BLACK,
     }
   }
 }
@@ -588,6 +1255,6 @@ impl iced::widget::button::StyleSheet for RegularRowStyle {
   }
 }
 
-pub fn run() -> iced::Result {
-  ProcessManagerApp::run(Settings::default())
+pub fn run(source: pro::ProcSource, config: Config) -> iced::Result {
+  ProcessManagerApp::run(Settings::with_flags((source, config)))
 }
@@ -1,15 +1,13 @@
-use std::env;
-use std::io;
 pub mod pro;
 mod cli;
+mod config;
+mod tabs;
 mod tui;
+mod daemon;
 mod icegui;
+mod serve;
 
-fn main() -> io::Result<()> {
-  if env::args().len() > 1 {
-    cli::run()?
-  } else {
-    tui::run()?
-  }
-  Ok(())
+fn main() {
+  // opens the TUI without arguments
+  cli::run()
 }
@@ -1,7 +1,7 @@
-use core::panic;
-use getopts::Options;
 use libc::{self, cpu_set_t, pid_t, sched_setaffinity, sysinfo, CPU_SET, CPU_ZERO};
-use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
@@ -9,63 +9,273 @@ use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::{BufRead, BufReader};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub mod alert;
+pub mod detail;
+pub mod diff;
+pub mod metrics;
+pub mod policy;
+pub mod query;
+pub mod record;
+pub mod sampler;
+
+/// Where the collectors read their data from. The default is the live system; pointing it at another
+/// directory lets us inspect a container's or chroot's procfs, or a fixture tree in tests.
+#[derive(Debug, Clone)]
+pub struct ProcSource {
+  proc_root: PathBuf,
+  etc_root: PathBuf,
+}
+
+impl ProcSource {
+  /// Uses `root/proc` and `root/etc`.
+  pub fn new<P: AsRef<Path>>(root: P) -> Self {
+    Self {
+      proc_root: root.as_ref().join("proc"),
+      etc_root: root.as_ref().join("etc"),
+    }
+  }
+
+  /// Overrides the procfs mount point, e.g. when it is not mounted under the root.
+  pub fn with_proc_root<P: Into<PathBuf>>(mut self, proc_root: P) -> Self {
+    self.proc_root = proc_root.into();
+    self
+  }
+
+  pub fn proc<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+    self.proc_root.join(path)
+  }
+
+  pub fn etc<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+    self.etc_root.join(path)
+  }
+}
+
+impl Default for ProcSource {
+  fn default() -> Self {
+    Self::new("/")
+  }
+}
+
+#[derive(Debug)]
+pub enum Error {
+  /// A sort or filter key that does not name a process field.
+  UnknownField(String),
+  /// A malformed line (or a missing required key when `line` is `None`) in a procfs file.
+  Parse {
+    path: PathBuf,
+    line: Option<usize>,
+    message: String,
+  },
+  PermissionDenied(PathBuf),
+  /// The process exited while we were reading or acting on it.
+  ProcessVanished(pid_t),
+  InvalidArgument(String),
+  Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+  pub(crate) fn parse<P: AsRef<Path>, M: ToString>(path: P, line: Option<usize>, message: M) -> Self {
+    Error::Parse {
+      path: path.as_ref().to_path_buf(),
+      line,
+      message: message.to_string(),
+    }
+  }
+
+  /// Classifies the error of reading `path`, which belongs to `pid` if it is under /proc/[pid].
+  pub(crate) fn from_read(error: io::Error, path: &Path, pid: Option<pid_t>) -> Self {
+    match (error.kind(), pid) {
+      (io::ErrorKind::PermissionDenied, _) => Error::PermissionDenied(path.to_path_buf()),
+      (io::ErrorKind::NotFound, Some(pid)) => Error::ProcessVanished(pid),
+      // reading a file of an exited process fails with ESRCH
+      _ if pid.is_some() && error.raw_os_error() == Some(libc::ESRCH) => Error::ProcessVanished(pid.unwrap()),
+      _ => Error::Io(error),
+    }
+  }
+
+  /// Classifies `errno` after a failed syscall on `pid`.
+  fn last_os_error(pid: pid_t) -> Self {
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+      Some(libc::ESRCH) => Error::ProcessVanished(pid),
+      Some(libc::EPERM) | Some(libc::EACCES) => Error::PermissionDenied(PathBuf::from(format!("/proc/{}", pid))),
+      _ => Error::Io(error),
+    }
+  }
+}
+
+/// Parses TOML, with the line of the error if there is one.
+pub(crate) fn toml_from_str<T: serde::de::DeserializeOwned>(text: &str, path: &Path) -> Result<T> {
+  toml::from_str(text).map_err(|e| {
+    let line = e.span().map(|span| text[..span.start].matches('\n').count() + 1);
+    Error::parse(path, line, e.message())
+  })
+}
+
+impl Display for Error {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Error::UnknownField(field) => write!(f, "unknown field '{}', expected one of {}", field, Field::names()),
+      Error::Parse {
+        path,
+        line: Some(line),
+        message,
+      } => write!(f, "{}:{}: {}", path.display(), line, message),
+      Error::Parse {
+        path,
+        line: None,
+        message,
+      } => write!(f, "{}: {}", path.display(), message),
+      Error::PermissionDenied(path) => write!(f, "permission denied: {}", path.display()),
+      Error::ProcessVanished(pid) => write!(f, "process {} no longer exists", pid),
+      Error::InvalidArgument(message) => write!(f, "{}", message),
+      Error::Io(error) => write!(f, "{}", error),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(error) => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Self {
+    Error::Io(error)
+  }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+  fs::read_to_string(path).map_err(|e| Error::from_read(e, path, None))
+}
+
+fn read_pid_file(source: &ProcSource, pid: pid_t, name: &str) -> Result<(PathBuf, String)> {
+  let path = source.proc(format!("{}/{}", pid, name));
+  match fs::read_to_string(&path) {
+    Ok(content) => Ok((path, content)),
+    Err(e) => Err(Error::from_read(e, &path, Some(pid))),
+  }
+}
 
 pub struct OutputMessage {
   pub message: String,
   pub is_error: bool,
 }
 
-#[derive(Clone)]
+// Serialized names carry their unit, see `Snapshot`
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ProcessInfo {
   pub user: String,
   pub pid: pid_t,
   pub ppid: pid_t,
   pub name: String,
   pub state: char,
+  /// Resident set size in kB.
+  #[serde(rename = "memory_kb")]
   pub memory: u64,
   pub thread_count: u64,
+  /// In kB.
+  #[serde(rename = "virtual_memory_kb")]
   pub virtual_memory: u64,
+  #[serde(rename = "user_time_ticks")]
   pub user_time: u64,
+  #[serde(rename = "system_time_ticks")]
   pub system_time: u64,
   pub priority: i32,
+  /// Clock ticks after boot.
+  #[serde(rename = "start_time_ticks")]
+  pub start_time: u64,
+  pub cpu_percent: f64,
+  /// The full command line, "[name]" for kernel threads.
+  pub command: String,
+  /// Target of the /proc/[pid]/exe link, empty for kernel threads.
+  pub exe: String,
+  /// Target of the /proc/[pid]/cwd link.
+  pub cwd: String,
+  // the fields below are missing from older recordings
+  /// Seconds since the Unix epoch.
+  #[serde(default, rename = "start_timestamp")]
+  pub started: u64,
+  /// Bytes read from storage, 0 if /proc/[pid]/io is unreadable.
+  #[serde(default, rename = "io_read_bytes")]
+  pub io_read: u64,
+  /// Bytes written to storage, 0 if /proc/[pid]/io is unreadable.
+  #[serde(default, rename = "io_write_bytes")]
+  pub io_write: u64,
+  /// Needs a previous sample like `cpu_percent`.
+  #[serde(default, rename = "io_read_bytes_per_second")]
+  pub io_read_rate: f64,
+  #[serde(default, rename = "io_write_bytes_per_second")]
+  pub io_write_rate: f64,
+  /// The cgroup v2 path, or the first v1 hierarchy's.
+  #[serde(default)]
+  pub cgroup: String,
 }
 
-pub struct ProcessInfoIntoIterator {
-  process_info: ProcessInfo,
-  index: usize,
+/// Shown instead of the command, exe or cwd of processes we are not allowed to inspect.
+pub const UNREADABLE: &str = "?";
+
+impl ProcessInfo {
+  pub const CSV_HEADER: &'static str = "user,pid,ppid,name,state,memory_kb,thread_count,virtual_memory_kb,\
+user_time_ticks,system_time_ticks,priority,start_time_ticks,cpu_percent,command,exe,cwd,start_timestamp,io_read_bytes,\
+io_write_bytes,io_read_bytes_per_second,io_write_bytes_per_second,cgroup";
+
+  /// One CSV record with the columns of `CSV_HEADER`, without a line break.
+  pub fn to_csv(&self) -> String {
+    [
+      csv_field(&self.user),
+      self.pid.to_string(),
+      self.ppid.to_string(),
+      csv_field(&self.name),
+      self.state.to_string(),
+      self.memory.to_string(),
+      self.thread_count.to_string(),
+      self.virtual_memory.to_string(),
+      self.user_time.to_string(),
+      self.system_time.to_string(),
+      self.priority.to_string(),
+      self.start_time.to_string(),
+      format!("{:.2}", self.cpu_percent),
+      csv_field(&self.command),
+      csv_field(&self.exe),
+      csv_field(&self.cwd),
+      self.started.to_string(),
+      self.io_read.to_string(),
+      self.io_write.to_string(),
+      format!("{:.0}", self.io_read_rate),
+      format!("{:.0}", self.io_write_rate),
+      csv_field(&self.cgroup),
+    ]
+    .join(",")
+  }
 }
 
-impl IntoIterator for ProcessInfo {
-  type Item = String;
-  type IntoIter = ProcessInfoIntoIterator;
-  fn into_iter(self) -> Self::IntoIter {
-    ProcessInfoIntoIterator {
-      process_info: self,
-      index: 0,
-    }
+// Quotes a CSV field if needed (RFC 4180)
+fn csv_field(value: &str) -> String {
+  if value.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_string()
   }
 }
 
-impl Iterator for ProcessInfoIntoIterator {
-  type Item = String;
-  fn next(&mut self) -> Option<Self::Item> {
-    let result = match self.index {
-      0 => Some(self.process_info.user.clone()),
-      1 => Some(self.process_info.pid.to_string()),
-      2 => Some(self.process_info.ppid.to_string()),
-      3 => Some(self.process_info.state.to_string()),
-      4 => Some((self.process_info.memory / 1000).to_string()),
-      5 => Some(self.process_info.thread_count.to_string()),
-      6 => Some((self.process_info.virtual_memory / 1000).to_string()),
-      7 => Some(self.process_info.user_time.to_string()),
-      8 => Some(self.process_info.system_time.to_string()),
-      9 => Some(self.process_info.priority.to_string()),
-      10 => Some(self.process_info.name.clone()),
-      _ => None,
-    };
-    self.index += 1;
-    result
+impl ProcessInfo {
+  /// The table cells of `columns`, see `Field::format`.
+  pub fn cells(&self, columns: &[Field]) -> Vec<String> {
+    columns.iter().map(|field| field.format(self)).collect()
   }
 }
 
@@ -73,39 +283,342 @@ impl Display for ProcessInfo {
   fn fmt(&self, f: &mut Formatter) -> fmt::Result {
     write!(
       f,
-      "{:<10}\t{:<6}\t{:<6}\t{:<1}\t{:<6}\t{:<2}\t{:<6}\t{:<10}\t{:<10}\t{:<3}\t{:<40}",
+      "{:<10}\t{:<6}\t{:<6}\t{:<1}\t{:<5.1}\t{:<6}\t{:<2}\t{:<6}\t{:<10}\t{:<10}\t{:<3}\t{:<40}",
       self.user,
       self.pid,
       self.ppid,
       self.state,
-      self.memory / 1000,
+      self.cpu_percent,
+      self.memory / 1024,
       self.thread_count,
-      self.virtual_memory / 1000,
+      self.virtual_memory / 1024,
       self.user_time,
       self.system_time,
       self.priority,
-      self.name
+      // {:#} shows the full command line
+      if f.alternate() { &self.command } else { &self.name }
     )
   }
 }
 
-fn get_username_from_uid(target_uid: u32) -> Option<String> {
+/// A process column, used to pick what to sort by, filter on and display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Field {
+  User,
+  Pid,
+  Ppid,
+  State,
+  Cpu,
+  Memory,
+  Threads,
+  VirtualMemory,
+  UserTime,
+  SystemTime,
+  Priority,
+  Name,
+  Command,
+  Exe,
+  Cwd,
+  StartTime,
+  IoRead,
+  IoWrite,
+  Cgroup,
+}
+
+impl Field {
+  /// Every field, for lookups by name or key.
+  pub const ALL: [Field; 19] = [
+    Field::User,
+    Field::Pid,
+    Field::Ppid,
+    Field::State,
+    Field::Cpu,
+    Field::Memory,
+    Field::Threads,
+    Field::VirtualMemory,
+    Field::UserTime,
+    Field::SystemTime,
+    Field::Priority,
+    Field::Name,
+    Field::Command,
+    Field::Exe,
+    Field::Cwd,
+    Field::StartTime,
+    Field::IoRead,
+    Field::IoWrite,
+    Field::Cgroup,
+  ];
+
+  /// The default table columns, in the order they are displayed.
+  pub const COLUMNS: [Field; 12] = [
+    Field::User,
+    Field::Pid,
+    Field::Ppid,
+    Field::State,
+    Field::Cpu,
+    Field::Memory,
+    Field::Threads,
+    Field::VirtualMemory,
+    Field::UserTime,
+    Field::SystemTime,
+    Field::Priority,
+    Field::Name,
+  ];
+
+  /// The name used on the command line and in messages.
+  pub fn name(self) -> &'static str {
+    match self {
+      Field::User => "user",
+      Field::Pid => "pid",
+      Field::Ppid => "ppid",
+      Field::State => "state",
+      Field::Cpu => "cpu",
+      Field::Memory => "memory",
+      Field::Threads => "threads",
+      Field::VirtualMemory => "vmsize",
+      Field::UserTime => "utime",
+      Field::SystemTime => "stime",
+      Field::Priority => "priority",
+      Field::Name => "name",
+      Field::Command => "command",
+      Field::Exe => "exe",
+      Field::Cwd => "cwd",
+      Field::StartTime => "start",
+      Field::IoRead => "io_read",
+      Field::IoWrite => "io_write",
+      Field::Cgroup => "cgroup",
+    }
+  }
+
+  /// The column header.
+  pub fn title(self) -> &'static str {
+    match self {
+      Field::User => "USER",
+      Field::Pid => "PID",
+      Field::Ppid => "PPID",
+      Field::State => "STATE",
+      Field::Cpu => "CPU%",
+      Field::Memory => "RSS(MiB)",
+      Field::Threads => "THREADS",
+      Field::VirtualMemory => "VIRT(MiB)",
+      Field::UserTime => "USER_TIME",
+      Field::SystemTime => "SYS_TIME",
+      Field::Priority => "NICE",
+      Field::Name => "Name",
+      Field::Command => "Command",
+      Field::Exe => "Exe",
+      Field::Cwd => "Cwd",
+      Field::StartTime => "START",
+      Field::IoRead => "IO_READ",
+      Field::IoWrite => "IO_WRITE",
+      Field::Cgroup => "Cgroup",
+    }
+  }
+
+  /// The key that selects this field after `s` in the TUI.
+  pub fn key(self) -> char {
+    match self {
+      Field::User => 'u',
+      Field::Pid => 'p',
+      Field::Ppid => 'P',
+      Field::State => 's',
+      Field::Cpu => 'c',
+      Field::Memory => 'm',
+      Field::Threads => 't',
+      Field::VirtualMemory => 'v',
+      Field::UserTime => 'U',
+      Field::SystemTime => 'S',
+      Field::Priority => 'r',
+      Field::Name => 'n',
+      Field::Command => 'C',
+      Field::Exe => 'e',
+      Field::Cwd => 'w',
+      Field::StartTime => 'T',
+      Field::IoRead => 'i',
+      Field::IoWrite => 'o',
+      Field::Cgroup => 'g',
+    }
+  }
+
+  pub fn from_key(key: char) -> Option<Field> {
+    Field::ALL.into_iter().find(|field| field.key() == key)
+  }
+
+  pub fn compare(self, a: &ProcessInfo, b: &ProcessInfo) -> Ordering {
+    match self {
+      Field::User => a.user.cmp(&b.user),
+      Field::Pid => a.pid.cmp(&b.pid),
+      Field::Ppid => a.ppid.cmp(&b.ppid),
+      Field::State => a.state.cmp(&b.state),
+      Field::Cpu => a.cpu_percent.total_cmp(&b.cpu_percent),
+      Field::Memory => a.memory.cmp(&b.memory),
+      Field::Threads => a.thread_count.cmp(&b.thread_count),
+      Field::VirtualMemory => a.virtual_memory.cmp(&b.virtual_memory),
+      Field::UserTime => a.user_time.cmp(&b.user_time),
+      Field::SystemTime => a.system_time.cmp(&b.system_time),
+      Field::Priority => a.priority.cmp(&b.priority),
+      Field::Name => a.name.cmp(&b.name),
+      Field::Command => a.command.cmp(&b.command),
+      Field::Exe => a.exe.cmp(&b.exe),
+      Field::Cwd => a.cwd.cmp(&b.cwd),
+      Field::StartTime => a.started.cmp(&b.started).then(a.start_time.cmp(&b.start_time)),
+      Field::IoRead => a.io_read_rate.total_cmp(&b.io_read_rate),
+      Field::IoWrite => a.io_write_rate.total_cmp(&b.io_write_rate),
+      Field::Cgroup => a.cgroup.cmp(&b.cgroup),
+    }
+  }
+
+  /// The raw value, as matched by filters.
+  pub fn value(self, process: &ProcessInfo) -> String {
+    match self {
+      Field::User => process.user.clone(),
+      Field::Pid => process.pid.to_string(),
+      Field::Ppid => process.ppid.to_string(),
+      Field::State => process.state.to_string(),
+      Field::Cpu => process.cpu_percent.to_string(),
+      Field::Memory => process.memory.to_string(),
+      Field::Threads => process.thread_count.to_string(),
+      Field::VirtualMemory => process.virtual_memory.to_string(),
+      Field::UserTime => process.user_time.to_string(),
+      Field::SystemTime => process.system_time.to_string(),
+      Field::Priority => process.priority.to_string(),
+      Field::Name => process.name.clone(),
+      Field::Command => process.command.clone(),
+      Field::Exe => process.exe.clone(),
+      Field::Cwd => process.cwd.clone(),
+      Field::StartTime => process.started.to_string(),
+      Field::IoRead => process.io_read_rate.to_string(),
+      Field::IoWrite => process.io_write_rate.to_string(),
+      Field::Cgroup => process.cgroup.clone(),
+    }
+  }
+
+  /// The value as shown in a table cell, in the units of `title`.
+  pub fn format(self, process: &ProcessInfo) -> String {
+    match self {
+      Field::Cpu => format!("{:.1}", process.cpu_percent),
+      Field::Memory => (process.memory / 1024).to_string(),
+      Field::VirtualMemory => (process.virtual_memory / 1024).to_string(),
+      // to the minute, the seconds rarely matter
+      Field::StartTime => {
+        let started = record::format_timestamp(process.started as f64);
+        started.get(..16).unwrap_or(&started).to_string()
+      }
+      Field::IoRead => format_rate(process.io_read_rate),
+      Field::IoWrite => format_rate(process.io_write_rate),
+      _ => self.value(process),
+    }
+  }
+
+  /// "a|b|c" of all field names, for usage messages.
+  pub fn names() -> String {
+    Field::ALL.map(Field::name).join("|")
+  }
+}
+
+impl FromStr for Field {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "mem" | "rss" => Ok(Field::Memory),
+      "vsz" => Ok(Field::VirtualMemory),
+      "nice" => Ok(Field::Priority),
+      "cmd" | "cmdline" | "args" => Ok(Field::Command),
+      "started" | "start_time" => Ok(Field::StartTime),
+      _ => Field::ALL
+        .into_iter()
+        .find(|field| field.name() == s)
+        .ok_or_else(|| Error::UnknownField(s.to_string())),
+    }
+  }
+}
+
+impl Display for Field {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+// By name, as in config files
+impl Serialize for Field {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.name())
+  }
+}
+
+impl<'de> Deserialize<'de> for Field {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+/// Matches `pattern` against one field, or against the whole row when `field` is `None`, and
+/// optionally a `query` expression on top of that.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+  pub field: Option<Field>,
+  pub pattern: String,
+  pub exact_match: bool,
+  pub query: Option<query::Query>,
+}
+
+impl Filter {
+  /// Parses `filter_by`, where "any" matches the whole row.
+  pub fn new(filter_by: &str, pattern: &str, exact_match: bool) -> Result<Self> {
+    Ok(Filter {
+      field: match filter_by {
+        "any" => None,
+        _ => Some(filter_by.parse()?),
+      },
+      pattern: pattern.to_string(),
+      exact_match,
+      query: None,
+    })
+  }
+
+  /// A filter that only applies `query`.
+  pub fn with_query(query: query::Query) -> Self {
+    Filter {
+      query: Some(query),
+      ..Default::default()
+    }
+  }
+
+  pub fn matches(&self, process: &ProcessInfo) -> bool {
+    if let Some(query) = &self.query {
+      if !query.matches(process) {
+        return false;
+      }
+    }
+    let value = match self.field {
+      Some(field) => field.value(process),
+      None => format!("{} {}", process, process.command),
+    };
+    if self.exact_match {
+      value == self.pattern
+    } else {
+      value.contains(&self.pattern)
+    }
+  }
+}
+
+fn get_username_from_uid(source: &ProcSource, target_uid: u32) -> Option<String> {
   // cache the usernames to avoid opening the file multiple times
-  static mut CACHE: BTreeMap<u32, String> = BTreeMap::new();
-  if let Some(username) = unsafe { CACHE.get(&target_uid) } {
+  static CACHE: Mutex<BTreeMap<(PathBuf, u32), String>> = Mutex::new(BTreeMap::new());
+  let passwd_path = source.etc("passwd");
+  if let Some(username) = CACHE.lock().unwrap().get(&(passwd_path.clone(), target_uid)) {
     return Some(username.clone());
   }
-  let file = File::open("/etc/passwd").ok()?;
+  let file = File::open(&passwd_path).ok()?;
   let reader = BufReader::new(file);
 
-  for line in reader.lines().map_while(Result::ok) {
+  for line in reader.lines().map_while(io::Result::ok) {
     let fields: Vec<&str> = line.split(':').collect();
     if fields.len() >= 3 {
       if let Ok(uid) = fields[2].parse::<u32>() {
         if uid == target_uid {
-          unsafe {
-            CACHE.insert(uid, fields[0].to_string());
-          }
+          CACHE.lock().unwrap().insert((passwd_path, uid), fields[0].to_string());
           return Some(fields[0].to_string());
         }
       }
@@ -116,18 +629,76 @@ fn get_username_from_uid(target_uid: u32) -> Option<String> {
 }
 
 pub fn parse_status_line(line: &str) -> io::Result<(String, Vec<String>)> {
-  let line_parts: Vec<&str> = line.split(':').collect();
-  match line_parts.len() {
-    2 => {
-      let key = line_parts[0].trim().to_string();
-      let values: Vec<String> = line_parts[1].split_whitespace().map(|s| s.to_string()).collect();
+  // only split on the first ':', names like "kworker/0:1" contain more
+  match line.split_once(':') {
+    Some((key, value)) => {
+      let key = key.trim().to_string();
+      let values: Vec<String> = value.split_whitespace().map(|s| s.to_string()).collect();
       Ok((key, values))
     }
-    _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid status line")),
+    None => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid status line")),
+  }
+}
+
+/// The fields of /proc/[pid]/stat that we care about. Times are in clock ticks.
+#[derive(Debug, Clone, Default)]
+pub struct ProcStat {
+  pub pid: pid_t,
+  pub comm: String,
+  pub state: char,
+  pub ppid: pid_t,
+  pub utime: u64,
+  pub stime: u64,
+  pub priority: i64,
+  pub nice: i64,
+  pub num_threads: u64,
+  pub starttime: u64,
+  pub processor: i32,
+}
+
+pub fn parse_stat_line(line: &str) -> io::Result<ProcStat> {
+  // comm may contain spaces and parentheses, so split around the last ')'
+  let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid stat line");
+  let open = line.find('(').ok_or_else(invalid)?;
+  let close = line.rfind(')').ok_or_else(invalid)?;
+  if close < open {
+    return Err(invalid());
+  }
+  let fields: Vec<&str> = line[close + 1..].split_whitespace().collect();
+  // fields[0] is field 3 (state) in proc(5) numbering
+  if fields.len() < 20 {
+    return Err(invalid());
+  }
+  let field = |n: usize| fields.get(n - 3).copied().unwrap_or_default();
+  Ok(ProcStat {
+    pid: line[..open].trim().parse().map_err(|_| invalid())?,
+    comm: line[open + 1..close].to_string(),
+    state: field(3).chars().next().unwrap_or_default(),
+    ppid: field(4).parse().unwrap_or_default(),
+    utime: field(14).parse().unwrap_or_default(),
+    stime: field(15).parse().unwrap_or_default(),
+    priority: field(18).parse().unwrap_or_default(),
+    nice: field(19).parse().unwrap_or_default(),
+    num_threads: field(20).parse().unwrap_or_default(),
+    starttime: field(22).parse().unwrap_or_default(),
+    processor: field(39).parse().unwrap_or_default(),
+  })
+}
+
+/// Fails on a cpu that doesn't fit in a `cpu_set_t`, which `CPU_SET` would abort on.
+pub(crate) fn check_cpus(cpu_ids: &[usize]) -> Result<()> {
+  match cpu_ids.iter().find(|&&cpu| cpu >= libc::CPU_SETSIZE as usize) {
+    Some(cpu) => Err(Error::InvalidArgument(format!(
+      "invalid CPU '{}', expected less than {}",
+      cpu,
+      libc::CPU_SETSIZE
+    ))),
+    None => Ok(()),
   }
 }
 
-pub fn bind_to_cpu_set(pid: pid_t, cpu_ids: &Vec<usize>, sender: Option<&Sender<OutputMessage>>) -> io::Result<()> {
+pub fn bind_to_cpu_set(pid: pid_t, cpu_ids: &Vec<usize>, sender: Option<&Sender<OutputMessage>>) -> Result<()> {
+  check_cpus(cpu_ids)?;
   let mut cpuset: cpu_set_t = unsafe { std::mem::zeroed() };
 
   unsafe {
@@ -151,29 +722,37 @@ pub fn bind_to_cpu_set(pid: pid_t, cpu_ids: &Vec<usize>, sender: Option<&Sender<
     }
     Ok(())
   } else {
-    let error = io::Error::last_os_error();
-    let msg = format!("Failed to set CPU affinity for process {}. Error: {:?}", pid, error);
+    let error = Error::last_os_error(pid);
+    // without a sender the caller reports the returned error
     if let Some(tx) = sender {
       let _ = tx.send(OutputMessage {
-        message: msg,
+        message: format!("Failed to set CPU affinity for process {}: {}", pid, error),
         is_error: true,
       });
-    } else {
-      eprintln!("{}", msg);
     }
     Err(error)
   }
 }
 
-pub fn read_process_info(pid: pid_t) -> io::Result<ProcessInfo> {
-  pub fn parse_status_file(status_path: &str) -> io::Result<HashMap<String, Vec<String>>> {
-    let status_content = fs::read_to_string(status_path)?;
+/// The real uid of `pid`, the user `ProcessInfo::user` names.
+pub fn read_uid(source: &ProcSource, pid: pid_t) -> Result<u32> {
+  let (path, content) = read_pid_file(source, pid, "status")?;
+  content
+    .lines()
+    .find_map(|line| line.strip_prefix("Uid:"))
+    .and_then(|uids| uids.split_whitespace().next())
+    .and_then(|uid| uid.parse().ok())
+    .ok_or_else(|| Error::parse(&path, None, "missing Uid"))
+}
+
+pub fn read_process_info(source: &ProcSource, pid: pid_t) -> Result<ProcessInfo> {
+  fn parse_status_file(path: &Path, content: &str) -> Result<HashMap<String, Vec<String>>> {
     let mut status_map = HashMap::new();
-    for line in status_content.lines() {
+    for (index, line) in content.lines().enumerate() {
       if line.is_empty() {
         continue;
       }
-      let (key, values) = parse_status_line(line)?;
+      let (key, values) = parse_status_line(line).map_err(|e| Error::parse(path, Some(index + 1), e))?;
       if !key.is_empty() {
         status_map.insert(key, values);
       }
@@ -181,14 +760,31 @@ pub fn read_process_info(pid: pid_t) -> io::Result<ProcessInfo> {
     Ok(status_map)
   }
 
-  let status_path = format!("/proc/{}/status", pid);
-  let status_map = parse_status_file(&status_path)?;
+  let (status_path, status_content) = read_pid_file(source, pid, "status")?;
+  let status_map = parse_status_file(&status_path, &status_content)?;
+  let required = |key: &str| -> Result<&str> {
+    match status_map.get(key).and_then(|values| values.first()) {
+      Some(value) => Ok(value),
+      None => Err(Error::parse(&status_path, None, format!("missing {}", key))),
+    }
+  };
+
+  let (stat_path, stat_content) = read_pid_file(source, pid, "stat")?;
+  let stat = parse_stat_line(stat_content.trim_end()).map_err(|e| Error::parse(&stat_path, Some(1), e))?;
+
+  let name = status_map
+    .get("Name")
+    .and_then(|v| v.first())
+    .cloned()
+    .unwrap_or_default();
+  let command = read_command(source, pid, &name);
+  let (io_read, io_write) = read_io(source, pid);
 
   let process_info = ProcessInfo {
-    user: get_username_from_uid(status_map["Uid"][0].parse().unwrap_or_default()).unwrap_or_default(),
+    user: get_username_from_uid(source, required("Uid")?.parse().unwrap_or_default()).unwrap_or_default(),
     pid,
-    ppid: status_map["PPid"][0].parse().unwrap_or_default(),
-    state: status_map["State"][0].chars().next().unwrap_or_default(),
+    ppid: required("PPid")?.parse().unwrap_or_default(),
+    state: required("State")?.chars().next().unwrap_or_default(),
     memory: {
       if let Some(vm_rss) = status_map.get("VmRSS") {
         vm_rss[0].parse::<u64>().unwrap_or_default()
@@ -196,88 +792,355 @@ pub fn read_process_info(pid: pid_t) -> io::Result<ProcessInfo> {
         0
       }
     },
-    name: status_map["Name"][0].clone(),
+    name,
     thread_count: status_map.get("Threads").and_then(|v| v[0].parse().ok()).unwrap_or(0),
     virtual_memory: status_map.get("VmSize").and_then(|v| v[0].parse().ok()).unwrap_or(0),
-    user_time: status_map.get("Utime").and_then(|v| v[0].parse().ok()).unwrap_or(0),
-    system_time: status_map.get("Stime").and_then(|v| v[0].parse().ok()).unwrap_or(0),
-    priority: get_priority(pid),
+    user_time: stat.utime,
+    system_time: stat.stime,
+    priority: stat.nice as i32,
+    start_time: stat.starttime,
+    // needs a previous sample, filled in by read_processes
+    cpu_percent: 0.0,
+    command,
+    exe: read_link(source, pid, "exe"),
+    cwd: read_link(source, pid, "cwd"),
+    started: boot_time(source) + stat.starttime / clock_ticks_per_second(),
+    io_read,
+    io_write,
+    // like cpu_percent
+    io_read_rate: 0.0,
+    io_write_rate: 0.0,
+    cgroup: read_cgroup(source, pid),
   };
 
   Ok(process_info)
 }
 
-pub fn filter_processes(
-  processes: Vec<ProcessInfo>,
-  filter_by: &str,
-  pattern: &str,
-  exact_match: bool,
-) -> Vec<ProcessInfo> {
-  processes
-    .into_iter()
-    .filter(|p| {
-      let field = match filter_by {
-        "name" => p.name.clone(),
-        "user" => p.user.clone(),
-        "pid" => p.pid.to_string(),
-        "ppid" => p.ppid.to_string(),
-        "state" => p.state.to_string(),
-        "any" => p.to_string(),
-        _ => panic!("Invalid filter_by value"),
-      };
-      if exact_match {
-        field == pattern
+/// Seconds since the Unix epoch at boot, the `btime` of /proc/stat.
+fn boot_time(source: &ProcSource) -> u64 {
+  // it doesn't change, and every process needs it
+  static CACHE: Mutex<BTreeMap<PathBuf, u64>> = Mutex::new(BTreeMap::new());
+  let path = source.proc("stat");
+  if let Some(&btime) = CACHE.lock().unwrap().get(&path) {
+    return btime;
+  }
+  let btime = read_file(&path)
+    .ok()
+    .and_then(|stat| {
+      stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+    })
+    .unwrap_or_default();
+  CACHE.lock().unwrap().insert(path, btime);
+  btime
+}
+
+/// The read_bytes and write_bytes of /proc/[pid]/io, zeros if we may not read it.
+fn read_io(source: &ProcSource, pid: pid_t) -> (u64, u64) {
+  let Ok(content) = fs::read_to_string(source.proc(format!("{}/io", pid))) else {
+    return (0, 0);
+  };
+  let value = |key: &str| {
+    content
+      .lines()
+      .find_map(|line| line.strip_prefix(key))
+      .and_then(|value| value.trim().parse().ok())
+      .unwrap_or_default()
+  };
+  (value("read_bytes:"), value("write_bytes:"))
+}
+
+/// The path of the unified hierarchy in /proc/[pid]/cgroup, or of the first one without it.
+fn read_cgroup(source: &ProcSource, pid: pid_t) -> String {
+  let Ok(content) = fs::read_to_string(source.proc(format!("{}/cgroup", pid))) else {
+    return String::new();
+  };
+  // lines are "id:controllers:path", the unified one is "0::path"
+  let paths: Vec<(&str, &str)> = content
+    .lines()
+    .filter_map(|line| {
+      let (id, rest) = line.split_once(':')?;
+      Some((id, rest.split_once(':')?.1))
+    })
+    .collect();
+  paths
+    .iter()
+    .find(|(id, _)| *id == "0")
+    .or(paths.first())
+    .map(|(_, path)| path.to_string())
+    .unwrap_or_default()
+}
+
+/// Reads /proc/[pid]/cmdline, whose arguments are separated by NULs.
+fn read_command(source: &ProcSource, pid: pid_t, name: &str) -> String {
+  match fs::read(source.proc(format!("{}/cmdline", pid))) {
+    Ok(content) => {
+      let args: Vec<String> = content
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+      if args.is_empty() {
+        // kernel threads and zombies have no command line
+        format!("[{}]", name)
       } else {
-        field.contains(pattern)
+        args.join(" ")
       }
-    })
-    .collect()
-}
-
-pub fn read_processes() -> io::Result<Vec<ProcessInfo>> {
-  let mut processes = Vec::new();
-  for entry in fs::read_dir("/proc")? {
-    let path = entry?.path();
-    if let Some(name) = path.file_name() {
-      if let Some(name_str) = name.to_str() {
-        if let Ok(pid) = name_str.parse::<pid_t>() {
-          match read_process_info(pid) {
-            Ok(info) => processes.push(info),
-            Err(_) => continue, // Skip processes we can't read
+    }
+    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => UNREADABLE.to_string(),
+    Err(_) => format!("[{}]", name),
+  }
+}
+
+fn read_link(source: &ProcSource, pid: pid_t, name: &str) -> String {
+  link_or_marker(fs::read_link(source.proc(format!("{}/{}", pid, name))))
+}
+
+fn link_or_marker(link: io::Result<PathBuf>) -> String {
+  match link {
+    Ok(target) => target.to_string_lossy().into_owned(),
+    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => UNREADABLE.to_string(),
+    // kernel threads have no exe
+    Err(_) => String::new(),
+  }
+}
+
+pub fn filter_processes(processes: Vec<ProcessInfo>, filter: &Filter) -> Vec<ProcessInfo> {
+  processes.into_iter().filter(|p| filter.matches(p)).collect()
+}
+
+/// Like `filter_processes`, but also keeps the ancestors of every match so they can still be shown as a
+/// tree. Returns the kept processes and the pids that actually matched.
+pub fn filter_tree(processes: Vec<ProcessInfo>, filter: &Filter) -> (Vec<ProcessInfo>, HashSet<pid_t>) {
+  let parents: HashMap<pid_t, pid_t> = processes.iter().map(|p| (p.pid, p.ppid)).collect();
+  let matched: HashSet<pid_t> = processes.iter().filter(|p| filter.matches(p)).map(|p| p.pid).collect();
+  let mut keep = HashSet::new();
+  for &pid in &matched {
+    let mut pid = pid;
+    // stop at the first pid we already walked up from, which also ends ppid cycles
+    while keep.insert(pid) {
+      match parents.get(&pid) {
+        Some(&ppid) => pid = ppid,
+        None => break,
+      }
+    }
+  }
+  let processes = processes.into_iter().filter(|p| keep.contains(&p.pid)).collect();
+  (processes, matched)
+}
+
+/// Reads every process once. Their `cpu_percent` and I/O rates need a previous sample and are 0,
+/// poll with `Counters::read_processes` for those.
+pub fn read_processes(source: &ProcSource) -> Result<Vec<ProcessInfo>> {
+  Counters::default().read_processes(source)
+}
+
+/// The previous samples that cpu usage and I/O rates are computed from. Every poller keeps its own,
+/// so two of them don't skew each other's deltas; `sampler::Sampler` keeps one for the frontends.
+#[derive(Default)]
+pub struct Counters {
+  ticks: TickSamples,
+  io: IoSamples,
+  threads: TickSamples,
+  // (total, idle) ticks of the /proc/stat cpu lines
+  cpus: Vec<(u64, u64)>,
+}
+
+impl Counters {
+  /// Reads every process, with the cpu usage and I/O rates since the previous call.
+  pub fn read_processes(&mut self, source: &ProcSource) -> Result<Vec<ProcessInfo>> {
+    let cpu_stats = parse_cpu_stats(&read_file(&source.proc("stat"))?);
+    let mut processes = Vec::new();
+    let proc_root = source.proc("");
+    for entry in fs::read_dir(&proc_root).map_err(|e| Error::from_read(e, &proc_root, None))? {
+      let path = entry?.path();
+      if let Some(name) = path.file_name() {
+        if let Some(name_str) = name.to_str() {
+          if let Ok(pid) = name_str.parse::<pid_t>() {
+            match read_process_info(source, pid) {
+              Ok(info) => processes.push(info),
+              Err(_) => continue, // Skip processes that vanished or that we can't read
+            }
           }
         }
       }
     }
+    update_cpu_percent(&mut processes, &cpu_stats, &mut self.ticks);
+    update_io_rates(&mut processes, Instant::now(), &mut self.io);
+    Ok(processes)
+  }
+
+  /// Reads the threads of `pid`, sorted by tid. Their `cpu_percent` is relative to the previous call,
+  /// so it is only meaningful when polling the same process.
+  pub fn read_threads(&mut self, source: &ProcSource, pid: pid_t) -> Result<Vec<ThreadInfo>> {
+    let cpu_stats = parse_cpu_stats(&read_file(&source.proc("stat"))?);
+    let task_dir = source.proc(format!("{}/task", pid));
+    let mut threads = Vec::new();
+    for entry in fs::read_dir(&task_dir).map_err(|e| Error::from_read(e, &task_dir, Some(pid)))? {
+      let path = entry?.path().join("stat");
+      // threads may exit while we are reading
+      if let Ok(stat) = read_file(&path).and_then(|line| parse_stat_line(&line).map_err(Error::from)) {
+        threads.push(ThreadInfo::from(stat));
+      }
+    }
+    threads.sort_by_key(|t| t.tid);
+
+    let samples = threads
+      .iter_mut()
+      .map(|t| (t.tid, t.start_time, t.user_time + t.system_time, &mut t.cpu_percent));
+    update_tick_percent(samples, &cpu_stats, &mut self.threads);
+    Ok(threads)
+  }
+
+  /// Usage in percent since the previous call, total first and then one entry per core. Empty on the
+  /// first call.
+  pub fn cpu_usage(&mut self, source: &ProcSource) -> Result<Vec<f64>> {
+    let stats = parse_cpu_stats(&read_file(&source.proc("stat"))?);
+    let cpu_usage = self
+      .cpus
+      .iter()
+      .zip(&stats)
+      .map(|(&(total1, idle1), &(total2, idle2))| {
+        let total_diff = total2.saturating_sub(total1);
+        let idle_diff = idle2.saturating_sub(idle1);
+        if total_diff > 0 {
+          total_diff.saturating_sub(idle_diff) as f64 / total_diff as f64 * 100.0
+        } else {
+          0.0
+        }
+      })
+      .collect();
+    self.cpus = stats;
+    Ok(cpu_usage)
+  }
+
+  /// Collects a `Snapshot` like `take_snapshot`, with the usage since the previous call.
+  pub fn take_snapshot(
+    &mut self,
+    source: &ProcSource,
+    nprocs: usize,
+    sort_by: Field,
+    descending: bool,
+    filter: Option<&Filter>,
+  ) -> Result<Snapshot> {
+    let timestamp = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_secs_f64();
+    Ok(Snapshot {
+      timestamp,
+      clock_ticks_per_second: clock_ticks_per_second(),
+      system: SystemSummary::read(source)?,
+      cpu_percent: self.cpu_usage(source)?,
+      disks: get_disk_stats(source)?,
+      networks: get_network_stats(source)?,
+      processes: list_processes(self.read_processes(source)?, 0, nprocs, sort_by, !descending, filter)?,
+    })
+  }
+}
+
+// (time of the sample, pid -> (start_time, read bytes, written bytes))
+type IoSamples = (Option<Instant>, BTreeMap<pid_t, (u64, u64, u64)>);
+
+/// Computes `io_read_rate` and `io_write_rate` from the byte counts in `prev`.
+fn update_io_rates(processes: &mut [ProcessInfo], now: Instant, prev: &mut IoSamples) {
+  let elapsed = prev.0.map_or(0.0, |time| now.duration_since(time).as_secs_f64());
+  let mut next = BTreeMap::new();
+  for p in processes.iter_mut() {
+    if let Some(&(start_time, read, written)) = prev.1.get(&p.pid) {
+      // a different start time means the pid was reused
+      if start_time == p.start_time && elapsed > 0.0 {
+        p.io_read_rate = p.io_read.saturating_sub(read) as f64 / elapsed;
+        p.io_write_rate = p.io_write.saturating_sub(written) as f64 / elapsed;
+      }
+    }
+    next.insert(p.pid, (p.start_time, p.io_read, p.io_write));
+  }
+  *prev = (Some(now), next);
+}
+
+// (total ticks of all cpus, pid -> (start_time, utime + stime))
+type TickSamples = (u64, BTreeMap<pid_t, (u64, u64)>);
+
+/// Computes `cpu_percent` from the tick deltas since `prev`. 100% means one full core.
+fn update_cpu_percent(processes: &mut [ProcessInfo], cpu_stats: &[(u64, u64)], prev: &mut TickSamples) {
+  let samples = processes
+    .iter_mut()
+    .map(|p| (p.pid, p.start_time, p.user_time + p.system_time, &mut p.cpu_percent));
+  update_tick_percent(samples, cpu_stats, prev);
+}
+
+// samples are (pid or tid, start_time, utime + stime, cpu_percent to fill in)
+fn update_tick_percent<'a>(
+  samples: impl Iterator<Item = (pid_t, u64, u64, &'a mut f64)>,
+  cpu_stats: &[(u64, u64)],
+  prev: &mut TickSamples,
+) {
+  let Some(&(total, _)) = cpu_stats.first() else {
+    return;
+  };
+  let ncpus = cpu_stats.len().saturating_sub(1).max(1) as f64;
+  let total_diff = total.saturating_sub(prev.0);
+  let mut next = BTreeMap::new();
+
+  for (id, start_time, ticks, cpu_percent) in samples {
+    if let Some(&(prev_start_time, prev_ticks)) = prev.1.get(&id) {
+      // a different start time means the id was reused
+      if prev_start_time == start_time && total_diff > 0 {
+        *cpu_percent = ticks.saturating_sub(prev_ticks) as f64 / total_diff as f64 * ncpus * 100.0;
+      }
+    }
+    next.insert(id, (start_time, ticks));
+  }
+
+  *prev = (total, next);
+}
+
+/// One entry of /proc/[pid]/task. Times are in clock ticks.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+  pub tid: pid_t,
+  pub name: String,
+  pub state: char,
+  pub user_time: u64,
+  pub system_time: u64,
+  pub priority: i64,
+  pub start_time: u64,
+  /// The cpu the thread last ran on.
+  pub processor: i32,
+  pub cpu_percent: f64,
+}
+
+impl From<ProcStat> for ThreadInfo {
+  fn from(stat: ProcStat) -> Self {
+    ThreadInfo {
+      tid: stat.pid,
+      name: stat.comm,
+      state: stat.state,
+      user_time: stat.utime,
+      system_time: stat.stime,
+      priority: stat.nice,
+      start_time: stat.starttime,
+      processor: stat.processor,
+      cpu_percent: 0.0,
+    }
   }
-  Ok(processes)
 }
 
 pub fn list_processes(
   mut processes: Vec<ProcessInfo>,
   mut from: usize,
   mut nprocs: usize,
-  sort_by: &str,
+  sort_by: Field,
   ascending: bool,
-  filter_by: &str,
-  pattern: &str,
-  exact_match: bool,
-) -> io::Result<Vec<ProcessInfo>> {
-  match sort_by {
-    "name" => processes.sort_by_key(|p| p.name.clone()),
-    "pid" => processes.sort_by_key(|p| p.pid),
-    "memory" => processes.sort_by_key(|p| p.memory),
-    "priority" => processes.sort_by_key(|p| p.priority),
-    "user" => processes.sort_by_key(|p| p.user.clone()),
-    "state" => processes.sort_by_key(|p| p.state),
-    "threads" => processes.sort_by_key(|p| p.thread_count),
-    "vmsize" => processes.sort_by_key(|p| p.virtual_memory),
-    "utime" => processes.sort_by_key(|p| p.user_time),
-    "stime" => processes.sort_by_key(|p| p.system_time),
-    _ => panic!("Invalid sort_by value"),
-  }
-
-  if !filter_by.is_empty() {
-    processes = filter_processes(processes, filter_by, pattern, exact_match);
+  filter: Option<&Filter>,
+) -> Result<Vec<ProcessInfo>> {
+  processes.sort_by(|a, b| sort_by.compare(a, b));
+
+  if let Some(filter) = filter {
+    processes = filter_processes(processes, filter);
   }
 
   if nprocs > processes.len() {
@@ -305,86 +1168,211 @@ pub fn list_processes(
 }
 
 pub struct Tree {
-  children: Vec<Tree>,
-  pid: pid_t,
+  pub children: Vec<Tree>,
+  pub pid: pid_t,
+}
+
+/// One row of a rendered tree, see `Tree::lines`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeLine {
+  pub pid: pid_t,
+  pub depth: usize,
+  /// Indentation guides to draw before the name, e.g. "│  └─ ".
+  pub guides: String,
+  pub has_children: bool,
+  pub collapsed: bool,
 }
 
 impl Tree {
-  pub fn print(&self, indent: usize) {
-    if indent == 0 {
-      println!("{}", self.pid);
-    } else {
-      let prefix = "│   ".repeat(indent / 4 - 1);
-      println!("{}├── {}", prefix, self.pid);
+  /// Renders the tree like `pstree -p`, one "name(pid)" per line followed by `fields`. Pid 0 is the
+  /// invisible root of everything, any other root gets a line of its own. Names in `highlight` are
+  /// printed in bold.
+  pub fn format(&self, processes: &[ProcessInfo], fields: &[Field], highlight: Option<&HashSet<pid_t>>) -> String {
+    let by_pid: HashMap<pid_t, &ProcessInfo> = processes.iter().map(|p| (p.pid, p)).collect();
+    let format_line = |guides: &str, pid: pid_t| {
+      let Some(process) = by_pid.get(&pid) else {
+        return format!("{}?({})\n", guides, pid);
+      };
+      let mut line = if highlight.is_some_and(|pids| pids.contains(&pid)) {
+        format!("{}\x1b[1m{}\x1b[0m({})", guides, process.name, pid)
+      } else {
+        format!("{}{}({})", guides, process.name, pid)
+      };
+      for field in fields {
+        line.push(' ');
+        line.push_str(&field.format(process));
+      }
+      line.push('\n');
+      line
+    };
+
+    let mut output = String::new();
+    if self.pid != 0 {
+      output.push_str(&format_line("", self.pid));
     }
-    for child in &self.children {
-      child.print(indent + 4);
+    for line in self.lines(&HashSet::new()) {
+      output.push_str(&format_line(&line.guides, line.pid));
     }
+    output
   }
-}
 
-pub fn build_tree(processes: &Vec<ProcessInfo>, pid: pid_t) -> Tree {
-  let children: Vec<Tree> = processes
-    .iter()
-    .filter(|p| p.ppid == pid)
-    .map(|p| build_tree(processes, p.pid))
-    .collect();
-  Tree { children, pid }
-}
-
-pub fn get_cpu_usage() -> io::Result<Vec<f64>> {
-  pub fn parse_cpu_stats(content: &str) -> Vec<(u64, u64)> {
-    let mut stats = Vec::new();
-    for line in content.lines() {
-      if line.starts_with("cpu") {
-        let values: Vec<&str> = line.split_whitespace().collect();
-        let total: u64 = values[1..].iter().map(|&s| s.parse::<u64>().unwrap_or(0)).sum();
-        let idle: u64 = values[4].parse().unwrap_or(0);
-        stats.push((total, idle));
-      }
+  /// The tree as nested JSON objects, each process with a `matched` flag and its `children`.
+  pub fn to_json(&self, processes: &[ProcessInfo], matched: Option<&HashSet<pid_t>>) -> serde_json::Value {
+    let by_pid: HashMap<pid_t, &ProcessInfo> = processes.iter().map(|p| (p.pid, p)).collect();
+    if self.pid == 0 {
+      serde_json::Value::Array(self.json_nodes(&by_pid, matched))
+    } else {
+      self.json_node(&by_pid, matched)
     }
-    stats
   }
 
-  let stat_content = fs::read_to_string("/proc/stat")?;
-  let stats = parse_cpu_stats(&stat_content);
-  static mut PREV_STATS: Vec<(u64, u64)> = Vec::new();
-  let mut cpu_usage = Vec::new();
+  fn json_nodes(
+    &self,
+    by_pid: &HashMap<pid_t, &ProcessInfo>,
+    matched: Option<&HashSet<pid_t>>,
+  ) -> Vec<serde_json::Value> {
+    self
+      .children
+      .iter()
+      .map(|child| child.json_node(by_pid, matched))
+      .collect()
+  }
 
-  unsafe {
-    for (stat1, stat2) in PREV_STATS.iter().zip(stats.iter()) {
-      let (total1, idle1) = stat1;
-      let (total2, idle2) = stat2;
+  fn json_node(&self, by_pid: &HashMap<pid_t, &ProcessInfo>, matched: Option<&HashSet<pid_t>>) -> serde_json::Value {
+    let mut node = by_pid
+      .get(&self.pid)
+      .and_then(|p| serde_json::to_value(p).ok())
+      .unwrap_or_else(|| serde_json::json!({ "pid": self.pid }));
+    node["matched"] = matched.is_none_or(|pids| pids.contains(&self.pid)).into();
+    node["children"] = self.json_nodes(by_pid, matched).into();
+    node
+  }
+
+  /// Keeps the first `nprocs` processes in display order, counting the root unless it is pid 0.
+  pub fn truncate(&mut self, nprocs: usize) {
+    let mut left = if self.pid != 0 {
+      nprocs.saturating_sub(1)
+    } else {
+      nprocs
+    };
+    self.truncate_children(&mut left);
+  }
 
-      let total_diff = total2 - total1;
-      let idle_diff = idle2 - idle1;
+  fn truncate_children(&mut self, left: &mut usize) {
+    let mut kept = 0;
+    for child in &mut self.children {
+      if *left == 0 {
+        break;
+      }
+      *left -= 1;
+      kept += 1;
+      child.truncate_children(left);
+    }
+    self.children.truncate(kept);
+  }
 
-      let usage = if total_diff > 0 {
-        (total_diff - idle_diff) as f64 / total_diff as f64 * 100.0
-      } else {
-        0.0
-      };
+  /// Flattens the tree below this node in display order, skipping the children of `collapsed` pids.
+  pub fn lines(&self, collapsed: &HashSet<pid_t>) -> Vec<TreeLine> {
+    let mut lines = Vec::new();
+    self.push_lines(collapsed, &mut String::new(), 0, &mut lines);
+    lines
+  }
 
-      cpu_usage.push(usage);
+  fn push_lines(&self, collapsed: &HashSet<pid_t>, indent: &mut String, depth: usize, lines: &mut Vec<TreeLine>) {
+    for (i, child) in self.children.iter().enumerate() {
+      let last = i + 1 == self.children.len();
+      let is_collapsed = collapsed.contains(&child.pid);
+      lines.push(TreeLine {
+        pid: child.pid,
+        depth,
+        guides: format!("{}{}", indent, if last { "└─ " } else { "├─ " }),
+        has_children: !child.children.is_empty(),
+        collapsed: is_collapsed,
+      });
+      if !is_collapsed {
+        let len = indent.len();
+        indent.push_str(if last { "   " } else { "│  " });
+        child.push_lines(collapsed, indent, depth + 1, lines);
+        indent.truncate(len);
+      }
     }
+  }
+}
 
-    PREV_STATS = stats;
+/// Builds the tree below `pid`, keeping the order of `processes` among siblings. Processes whose
+/// parent is not in `processes` are attached to pid 0, so building from 0 never loses any.
+pub fn build_tree(processes: &[ProcessInfo], pid: pid_t) -> Tree {
+  let present: HashSet<pid_t> = processes.iter().map(|p| p.pid).collect();
+  let mut children: HashMap<pid_t, Vec<pid_t>> = HashMap::new();
+  for process in processes {
+    let parent = if present.contains(&process.ppid) {
+      process.ppid
+    } else {
+      0
+    };
+    if parent != process.pid {
+      children.entry(parent).or_default().push(process.pid);
+    }
   }
+  build_subtree(&mut children, pid)
+}
 
-  Ok(cpu_usage)
+fn build_subtree(children: &mut HashMap<pid_t, Vec<pid_t>>, pid: pid_t) -> Tree {
+  // removing the entry means every pid is visited once, even if ppids form a cycle
+  let pids = children.remove(&pid).unwrap_or_default();
+  Tree {
+    children: pids.into_iter().map(|child| build_subtree(children, child)).collect(),
+    pid,
+  }
 }
 
-pub fn show_stats(
-  nprocs: usize,
-  sort_by: &str,
-  descending: bool,
-  filter_by: &str,
-  pattern: &str,
-  exact_match: bool,
-) -> String {
+fn parse_cpu_stats(content: &str) -> Vec<(u64, u64)> {
+  let mut stats = Vec::new();
+  for line in content.lines() {
+    if line.starts_with("cpu") {
+      let values: Vec<&str> = line.split_whitespace().collect();
+      let total: u64 = values[1..].iter().map(|&s| s.parse::<u64>().unwrap_or(0)).sum();
+      let idle: u64 = values[4].parse().unwrap_or(0);
+      stats.push((total, idle));
+    }
+  }
+  stats
+}
+
+/// Modes of the /proc/stat cpu lines, in the order of their columns.
+pub const CPU_MODES: [&str; 8] = ["user", "nice", "system", "idle", "iowait", "irq", "softirq", "steal"];
+
+/// Clock ticks a cpu spent in each of `CPU_MODES` since boot, "cpu" is the sum of all cpus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuTimes {
+  pub cpu: String,
+  pub ticks: [u64; 8],
+}
+
+pub fn get_cpu_times(source: &ProcSource) -> Result<Vec<CpuTimes>> {
+  let content = read_file(&source.proc("stat"))?;
+  Ok(
+    content
+      .lines()
+      .filter(|line| line.starts_with("cpu"))
+      .map(|line| {
+        let mut values = line.split_whitespace();
+        let cpu = values.next().unwrap_or_default().to_string();
+        let mut ticks = [0; 8];
+        for (tick, value) in ticks.iter_mut().zip(values) {
+          *tick = value.parse().unwrap_or(0);
+        }
+        CpuTimes { cpu, ticks }
+      })
+      .collect(),
+  )
+}
+
+/// The system summary, CPU usage and process table of `snapshot`, as `top` prints them.
+pub fn format_stats(snapshot: &Snapshot, full_command: bool) -> String {
   let mut output = String::new();
 
-  let system_info = get_sysinfo();
+  let system_info = snapshot.system.to_sysinfo();
   let mem_unit = 1_000_000 / system_info.mem_unit as u64;
 
   output.push_str(&format!(
@@ -399,96 +1387,363 @@ pub fn show_stats(
     system_info.loads
   ));
 
-  match get_cpu_usage() {
-    Ok(cpu_usage) => {
-      output.push_str("CPU Usage:\n");
-      for (i, usage) in cpu_usage.iter().enumerate() {
-        if i == 0 {
-          output.push_str(&format!("Total CPU: {:.2}%\n", usage));
-        } else {
-          output.push_str(&format!("Core {}: {:.2}%\n", i, usage));
-        }
-      }
+  output.push_str("CPU Usage:\n");
+  for (i, usage) in snapshot.cpu_percent.iter().enumerate() {
+    if i == 0 {
+      output.push_str(&format!("Total CPU: {:.2}%\n", usage));
+    } else {
+      output.push_str(&format!("Core {}: {:.2}%\n", i, usage));
     }
-    Err(e) => output.push_str(&format!("Error retrieving CPU usage: {}\n", e)),
   }
 
+  output.push_str(&format_process_table(&snapshot.processes, full_command));
+  output
+}
+
+/// The process table of `format_stats`, one line per process.
+pub fn format_process_table(processes: &[ProcessInfo], full_command: bool) -> String {
+  let mut output = String::new();
   output.push_str(&format!(
-    "{:<6}\t{:<6}\t{:<6}\t{:<6}\t{:<8}\t{:<8}\t{:<12}\t{:<10}\t{:<10}\t{:<8}\t{:<20}\n",
-    "UID", "PID", "PPID", "STATE", "MEM(MB)", "THREADS", "VIRT_MEM(MB)", "USER_TIME", "SYS_TIME", "Priority", "Name",
+    "{:<6}\t{:<6}\t{:<6}\t{:<6}\t{:<5}\t{:<8}\t{:<8}\t{:<12}\t{:<10}\t{:<10}\t{:<8}\t{:<20}\n",
+    "USER", "PID", "PPID", "STATE", "CPU%", "RSS(MiB)", "THREADS", "VIRT(MiB)", "USER_TIME", "SYS_TIME", "NICE", "Name",
   ));
 
   output.push_str(&format!("{}\n", "-".repeat(150)));
 
-  match list_processes(
-    read_processes().unwrap(),
-    0,
-    nprocs,
-    sort_by,
-    !descending,
-    filter_by,
-    pattern,
-    exact_match,
-  ) {
-    Ok(processes) => {
-      for process in processes {
-        output.push_str(&process.to_string());
-        output.push('\n');
-      }
-    }
-    Err(e) => {
-      output.push_str(&format!("Error listing processes: {}\n", e));
+  for process in processes {
+    if full_command {
+      output.push_str(&format!("{:#}", process));
+    } else {
+      output.push_str(&process.to_string());
     }
+    output.push('\n');
   }
   output
 }
 
-pub fn kill_process(pid: pid_t, signal: i32, sender: Option<&Sender<OutputMessage>>) {
-  let msg = format!("Killing process {} with signal {}", pid, signal);
-  if let Some(tx) = sender {
-    let _ = tx.send(OutputMessage {
-      message: msg,
-      is_error: false,
-    });
-  } else {
-    println!("{}", msg);
+/// Everything `format_stats` shows, for machine readable output. Field names carry their unit: `_kb` and
+/// `_bytes` are sizes, `_ticks` are clock ticks (see `clock_ticks_per_second`), `_seconds` are seconds,
+/// and `_percent` is relative to one core. `timestamp` is in seconds since the Unix epoch.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+  pub timestamp: f64,
+  pub clock_ticks_per_second: u64,
+  pub system: SystemSummary,
+  /// Total usage first, then one entry per core. Like the `cpu_percent` of processes it needs a
+  /// previous sample, so it is empty in the first snapshot.
+  pub cpu_percent: Vec<f64>,
+  pub disks: Vec<DiskStats>,
+  pub networks: Vec<NetworkStats>,
+  pub processes: Vec<ProcessInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SystemSummary {
+  pub total_ram_bytes: u64,
+  pub shared_ram_bytes: u64,
+  pub free_ram_bytes: u64,
+  pub buffer_ram_bytes: u64,
+  pub total_swap_bytes: u64,
+  pub free_swap_bytes: u64,
+  /// `MemAvailable` of /proc/meminfo: free RAM plus the caches the kernel can drop, so what can be
+  /// handed out without swapping. None if the kernel doesn't say, or in older recordings.
+  #[serde(default)]
+  pub available_ram_bytes: Option<u64>,
+  pub uptime_seconds: i64,
+  /// 1, 5 and 15 minute load averages.
+  pub load_average: [f64; 3],
+}
+
+impl From<&sysinfo> for SystemSummary {
+  fn from(info: &sysinfo) -> Self {
+    let unit = info.mem_unit.max(1) as u64;
+    SystemSummary {
+      total_ram_bytes: info.totalram * unit,
+      shared_ram_bytes: info.sharedram * unit,
+      free_ram_bytes: info.freeram * unit,
+      buffer_ram_bytes: info.bufferram * unit,
+      total_swap_bytes: info.totalswap * unit,
+      free_swap_bytes: info.freeswap * unit,
+      available_ram_bytes: None,
+      uptime_seconds: info.uptime,
+      // fixed point with 16 bits of fraction
+      load_average: info.loads.map(|load| load as f64 / 65536.0),
+    }
   }
-  unsafe {
-    libc::kill(pid, signal);
+}
+
+impl SystemSummary {
+  /// Reads /proc/meminfo, /proc/uptime and /proc/loadavg of `source`, so the summary is of the same
+  /// machine as the processes read from it.
+  pub fn read(source: &ProcSource) -> Result<Self> {
+    let meminfo = read_file(&source.proc("meminfo"))?;
+    let memory: HashMap<&str, u64> = meminfo
+      .lines()
+      .filter_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        Some((key, value.split_whitespace().next()?.parse::<u64>().ok()? * 1024))
+      })
+      .collect();
+    let bytes = |key: &str| memory.get(key).copied().unwrap_or(0);
+    let uptime = read_file(&source.proc("uptime"))?;
+    let loadavg = read_file(&source.proc("loadavg"))?;
+    let mut loads = loadavg.split_whitespace().map(|load| load.parse().unwrap_or(0.0));
+    Ok(SystemSummary {
+      total_ram_bytes: bytes("MemTotal"),
+      shared_ram_bytes: bytes("Shmem"),
+      free_ram_bytes: bytes("MemFree"),
+      buffer_ram_bytes: bytes("Buffers"),
+      total_swap_bytes: bytes("SwapTotal"),
+      free_swap_bytes: bytes("SwapFree"),
+      available_ram_bytes: memory.get("MemAvailable").copied(),
+      uptime_seconds: uptime
+        .split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .unwrap_or(0.0) as i64,
+      load_average: [(); 3].map(|_| loads.next().unwrap_or(0.0)),
+    })
   }
+
+  /// The summary in the shape `get_sysinfo` returns, for code written against it.
+  pub fn to_sysinfo(&self) -> sysinfo {
+    let mut info: sysinfo = unsafe { std::mem::zeroed() };
+    info.totalram = self.total_ram_bytes;
+    info.sharedram = self.shared_ram_bytes;
+    info.freeram = self.free_ram_bytes;
+    info.bufferram = self.buffer_ram_bytes;
+    info.totalswap = self.total_swap_bytes;
+    info.freeswap = self.free_swap_bytes;
+    info.uptime = self.uptime_seconds;
+    info.loads = self.load_average.map(|load| (load * 65536.0) as u64);
+    info.mem_unit = 1;
+    info
+  }
+}
+
+/// Collects a `Snapshot`, listing the processes like `list_processes`. Usage and rates need a previous
+/// sample, so they are empty or 0; `Counters::take_snapshot` polls for them.
+pub fn take_snapshot(
+  source: &ProcSource,
+  nprocs: usize,
+  sort_by: Field,
+  descending: bool,
+  filter: Option<&Filter>,
+) -> Result<Snapshot> {
+  Counters::default().take_snapshot(source, nprocs, sort_by, descending, filter)
 }
 
-pub fn print_usage(program: &str, opts: Options) {
-  let brief = format!("Usage: {} [options]", program);
-  print!("{}", opts.usage(&brief));
+/// Signals by name, without the SIG prefix, in numeric order.
+pub const SIGNALS: [(&str, i32); 31] = [
+  ("HUP", libc::SIGHUP),
+  ("INT", libc::SIGINT),
+  ("QUIT", libc::SIGQUIT),
+  ("ILL", libc::SIGILL),
+  ("TRAP", libc::SIGTRAP),
+  ("ABRT", libc::SIGABRT),
+  ("BUS", libc::SIGBUS),
+  ("FPE", libc::SIGFPE),
+  ("KILL", libc::SIGKILL),
+  ("USR1", libc::SIGUSR1),
+  ("SEGV", libc::SIGSEGV),
+  ("USR2", libc::SIGUSR2),
+  ("PIPE", libc::SIGPIPE),
+  ("ALRM", libc::SIGALRM),
+  ("TERM", libc::SIGTERM),
+  ("STKFLT", libc::SIGSTKFLT),
+  ("CHLD", libc::SIGCHLD),
+  ("CONT", libc::SIGCONT),
+  ("STOP", libc::SIGSTOP),
+  ("TSTP", libc::SIGTSTP),
+  ("TTIN", libc::SIGTTIN),
+  ("TTOU", libc::SIGTTOU),
+  ("URG", libc::SIGURG),
+  ("XCPU", libc::SIGXCPU),
+  ("XFSZ", libc::SIGXFSZ),
+  ("VTALRM", libc::SIGVTALRM),
+  ("PROF", libc::SIGPROF),
+  ("WINCH", libc::SIGWINCH),
+  ("IO", libc::SIGIO),
+  ("PWR", libc::SIGPWR),
+  ("SYS", libc::SIGSYS),
+];
+
+/// Parses a signal number or name, e.g. "15", "TERM", "SIGTERM" or "term".
+pub fn parse_signal(signal: &str) -> Result<i32> {
+  let signal = signal.trim();
+  if let Ok(number) = signal.parse::<i32>() {
+    if (0..=libc::SIGRTMAX()).contains(&number) {
+      return Ok(number);
+    }
+  }
+  let upper = signal.to_ascii_uppercase();
+  let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+  SIGNALS
+    .iter()
+    .find(|(n, _)| *n == name)
+    .map(|&(_, number)| number)
+    .ok_or_else(|| Error::InvalidArgument(format!("unknown signal '{}'", signal)))
 }
 
-pub fn set_priority(pid: pid_t, priority: i32, sender: Option<&Sender<OutputMessage>>) {
-  unsafe {
-    if libc::setpriority(libc::PRIO_PROCESS, pid.try_into().unwrap(), priority) == -1 {
-      let msg = format!("Failed to set priority: {}", std::io::Error::last_os_error());
+/// Parses a comma separated list of CPU numbers, e.g. "0,2".
+pub fn parse_cpus(cpus: &str) -> Result<Vec<usize>> {
+  let cpus = cpus
+    .split(',')
+    .map(|arg| {
+      arg
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| Error::InvalidArgument(format!("invalid CPU '{}'", arg)))
+    })
+    .collect::<Result<Vec<usize>>>()?;
+  check_cpus(&cpus)?;
+  Ok(cpus)
+}
+
+/// "SIGTERM" for 15, the bare number for signals without a name.
+pub fn signal_name(signal: i32) -> String {
+  match SIGNALS.iter().find(|&&(_, number)| number == signal) {
+    Some((name, _)) => format!("SIG{}", name),
+    None => signal.to_string(),
+  }
+}
+
+pub fn kill_process(pid: pid_t, signal: i32, sender: Option<&Sender<OutputMessage>>) -> Result<()> {
+  let result = if unsafe { libc::kill(pid, signal) } == 0 {
+    Ok(())
+  } else {
+    Err(Error::last_os_error(pid))
+  };
+  report_signal(pid, signal, result, sender)
+}
+
+fn report_signal(pid: pid_t, signal: i32, result: Result<()>, sender: Option<&Sender<OutputMessage>>) -> Result<()> {
+  match result {
+    Ok(()) => {
+      let msg = format!("Sent {} to process {}", signal_name(signal), pid);
       if let Some(tx) = sender {
         let _ = tx.send(OutputMessage {
           message: msg,
-          is_error: true,
+          is_error: false,
         });
       } else {
-        eprintln!("{}", msg);
+        println!("{}", msg);
       }
-    } else {
-      let msg = format!("Successfully set priority of process {} to {}", pid, priority);
+      Ok(())
+    }
+    Err(error) => {
+      // without a sender the caller reports the returned error
       if let Some(tx) = sender {
         let _ = tx.send(OutputMessage {
-          message: msg,
-          is_error: false,
+          message: format!("Failed to send {} to process {}: {}", signal_name(signal), pid, error),
+          is_error: true,
         });
-      } else {
-        println!("{}", msg);
       }
+      Err(error)
     }
   }
 }
 
+/// A live process held by a pidfd, which keeps naming it after it exits and its pid is reused.
+/// Checks made through it and the signals it sends can't hit another process.
+pub struct ProcessHandle {
+  pid: pid_t,
+  fd: OwnedFd,
+}
+
+impl ProcessHandle {
+  pub fn open(pid: pid_t) -> Result<Self> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+      return Err(Error::last_os_error(pid));
+    }
+    Ok(ProcessHandle {
+      pid,
+      fd: unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+    })
+  }
+
+  /// Opens the process `process` was read from, failing with `ProcessVanished` if its pid went to
+  /// another process since, as told by the start time in the live /proc.
+  pub fn open_process(process: &ProcessInfo) -> Result<Self> {
+    let handle = ProcessHandle::open(process.pid)?;
+    let start_time = read_process_info(&ProcSource::default(), process.pid)?.start_time;
+    handle.check_running()?;
+    if start_time != process.start_time {
+      return Err(Error::ProcessVanished(process.pid));
+    }
+    Ok(handle)
+  }
+
+  pub fn pid(&self) -> pid_t {
+    self.pid
+  }
+
+  fn pidfd_send_signal(&self, signal: i32) -> Result<()> {
+    let null = std::ptr::null::<libc::siginfo_t>();
+    if unsafe { libc::syscall(libc::SYS_pidfd_send_signal, self.fd.as_raw_fd(), signal, null, 0) } == 0 {
+      Ok(())
+    } else {
+      Err(Error::last_os_error(self.pid))
+    }
+  }
+
+  /// Fails with `ProcessVanished` once the process exited. While it runs its pid is still its own,
+  /// so anything done to the pid before this succeeds was done to this process.
+  pub fn check_running(&self) -> Result<()> {
+    match self.pidfd_send_signal(0) {
+      // signal 0 only checks for existence, EPERM means it exists
+      Err(Error::PermissionDenied(_)) => Ok(()),
+      result => result,
+    }
+  }
+
+  /// The real uid, read from the live /proc whatever `ProcSource` the caller reads from.
+  pub fn uid(&self) -> Result<u32> {
+    let uid = read_uid(&ProcSource::default(), self.pid)?;
+    // the file may have been of a process that reused the pid
+    self.check_running()?;
+    Ok(uid)
+  }
+
+  pub fn send_signal(&self, signal: i32, sender: Option<&Sender<OutputMessage>>) -> Result<()> {
+    report_signal(self.pid, signal, self.pidfd_send_signal(signal), sender)
+  }
+}
+
+pub fn set_priority(pid: pid_t, priority: i32, sender: Option<&Sender<OutputMessage>>) -> Result<()> {
+  let id = libc::id_t::try_from(pid).map_err(|_| Error::InvalidArgument(format!("invalid pid {}", pid)))?;
+  if unsafe { libc::setpriority(libc::PRIO_PROCESS, id, priority) } == 0 {
+    let msg = format!("Successfully set priority of process {} to {}", pid, priority);
+    if let Some(tx) = sender {
+      let _ = tx.send(OutputMessage {
+        message: msg,
+        is_error: false,
+      });
+    } else {
+      println!("{}", msg);
+    }
+    Ok(())
+  } else {
+    let error = Error::last_os_error(pid);
+    // without a sender the caller reports the returned error
+    if let Some(tx) = sender {
+      let _ = tx.send(OutputMessage {
+        message: format!("Failed to set priority: {}", error),
+        is_error: true,
+      });
+    }
+    Err(error)
+  }
+}
+
+pub fn clock_ticks_per_second() -> u64 {
+  match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+    ticks if ticks > 0 => ticks as u64,
+    _ => 100,
+  }
+}
+
 pub fn get_priority(pid: pid_t) -> i32 {
   unsafe { libc::getpriority(libc::PRIO_PROCESS, pid.try_into().unwrap()) }
 }
@@ -501,26 +1756,44 @@ pub fn get_sysinfo() -> sysinfo {
   system_info
 }
 
+type ArgFn<T> = fn(pid_t, T, Option<&Sender<OutputMessage>>) -> Result<()>;
+
 pub fn execute_on_with_arg<T: std::marker::Copy>(
   pids: Vec<pid_t>,
   arg: T,
-  fn_ptr: fn(pid_t, T, Option<&Sender<OutputMessage>>),
+  fn_ptr: ArgFn<T>,
   sender: Option<&Sender<OutputMessage>>,
-) {
+) -> Result<()> {
+  // keep going on failure, but report the first error
+  let mut result = Ok(());
   for pid in pids {
-    fn_ptr(pid, arg, sender);
+    if let Err(e) = fn_ptr(pid, arg, sender) {
+      if result.is_ok() {
+        result = Err(e);
+      }
+    }
   }
+  result
 }
 
+type ArgsFn<T> = fn(pid_t, &Vec<T>, Option<&Sender<OutputMessage>>) -> Result<()>;
+
 pub fn execute_on_with_args<T: std::marker::Copy>(
   pids: Vec<pid_t>,
   args: &Vec<T>,
-  fn_ptr: fn(pid_t, &Vec<T>, Option<&Sender<OutputMessage>>) -> io::Result<()>,
+  fn_ptr: ArgsFn<T>,
   sender: Option<&Sender<OutputMessage>>,
-) {
+) -> Result<()> {
+  // keep going on failure, but report the first error
+  let mut result = Ok(());
   for pid in pids {
-    let _ = fn_ptr(pid, args, sender);
+    if let Err(e) = fn_ptr(pid, args, sender) {
+      if result.is_ok() {
+        result = Err(e);
+      }
+    }
   }
+  result
 }
 
 pub fn execute_on(pids: Vec<pid_t>, fn_ptr: fn(pid_t)) {
@@ -529,7 +1802,8 @@ pub fn execute_on(pids: Vec<pid_t>, fn_ptr: fn(pid_t)) {
   }
 }
 
-#[derive(Debug, Clone)]
+/// Counters of /proc/diskstats since boot. Times are in milliseconds, sectors are 512 bytes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiskStats {
   pub device: String,
   pub reads_completed: u64,
@@ -545,7 +1819,8 @@ pub struct DiskStats {
   pub weighted_time_io: u64,
 }
 
-#[derive(Debug, Clone)]
+/// Counters of /proc/net/dev since boot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkStats {
   pub interface: String,
   pub bytes_received: u64,
@@ -558,8 +1833,8 @@ pub struct NetworkStats {
   pub drops_transmitted: u64,
 }
 
-pub fn get_disk_stats() -> io::Result<Vec<DiskStats>> {
-  let content = fs::read_to_string("/proc/diskstats")?;
+pub fn get_disk_stats(source: &ProcSource) -> Result<Vec<DiskStats>> {
+  let content = read_file(&source.proc("diskstats"))?;
   let mut stats = Vec::new();
 
   for line in content.lines() {
@@ -596,8 +1871,9 @@ pub fn get_disk_rates(previous: &[DiskStats], current: &[DiskStats], elapsed_sec
 
   for curr in current {
     if let Some(prev) = previous.iter().find(|p| p.device == curr.device) {
-      let read_bytes = (curr.sectors_read - prev.sectors_read) * 512;
-      let write_bytes = (curr.sectors_written - prev.sectors_written) * 512;
+      // counters start over when a device is replaced
+      let read_bytes = curr.sectors_read.saturating_sub(prev.sectors_read) * 512;
+      let write_bytes = curr.sectors_written.saturating_sub(prev.sectors_written) * 512;
 
       let read_rate = read_bytes as f64 / elapsed_seconds;
       let write_rate = write_bytes as f64 / elapsed_seconds;
@@ -609,8 +1885,8 @@ pub fn get_disk_rates(previous: &[DiskStats], current: &[DiskStats], elapsed_sec
   rates
 }
 
-pub fn get_network_stats() -> io::Result<Vec<NetworkStats>> {
-  let content = fs::read_to_string("/proc/net/dev")?;
+pub fn get_network_stats(source: &ProcSource) -> Result<Vec<NetworkStats>> {
+  let content = read_file(&source.proc("net/dev"))?;
   let mut stats = Vec::new();
 
   for line in content.lines().skip(2) {
@@ -651,8 +1927,8 @@ pub fn get_network_rates(
 
   for curr in current {
     if let Some(prev) = previous.iter().find(|p| p.interface == curr.interface) {
-      let rx_rate = (curr.bytes_received - prev.bytes_received) as f64 / elapsed_seconds;
-      let tx_rate = (curr.bytes_transmitted - prev.bytes_transmitted) as f64 / elapsed_seconds;
+      let rx_rate = curr.bytes_received.saturating_sub(prev.bytes_received) as f64 / elapsed_seconds;
+      let tx_rate = curr.bytes_transmitted.saturating_sub(prev.bytes_transmitted) as f64 / elapsed_seconds;
 
       rates.push((curr.interface.clone(), rx_rate, tx_rate));
     }
@@ -676,3 +1952,434 @@ pub fn format_rate(bytes_per_sec: f64) -> String {
     format!("{:.0} B/s", bytes_per_sec)
   }
 }
+
+/// The procfs tree under `tests/fixtures/<name>`.
+#[cfg(test)]
+pub(crate) fn fixture(name: &str) -> ProcSource {
+  ProcSource::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name))
+}
+
+/// A snapshot of every process of the basic fixture, as if taken at `timestamp` with 100 ticks a
+/// second.
+#[cfg(test)]
+pub(crate) fn fixture_snapshot(timestamp: f64) -> Snapshot {
+  let mut snapshot = take_snapshot(&fixture("basic"), usize::MAX, Field::Pid, false, None).unwrap();
+  snapshot.timestamp = timestamp;
+  snapshot.clock_ticks_per_second = 100;
+  snapshot
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn find(processes: &[ProcessInfo], pid: pid_t) -> &ProcessInfo {
+    processes
+      .iter()
+      .find(|p| p.pid == pid)
+      .expect("process missing from fixture")
+  }
+
+  #[test]
+  fn parse_stat_line_handles_parens_in_comm() {
+    let stat = parse_stat_line(
+      "200 ((sd-pam)) S 1 200 200 0 -1 4194560 100 0 0 0 7 3 0 0 15 -5 2 0 40 102400000 1000 0 0 0 0 0 0 0 0 0 0 0 \
+       0 0 0 3",
+    )
+    .unwrap();
+    assert_eq!(stat.pid, 200);
+    assert_eq!(stat.comm, "(sd-pam)");
+    assert_eq!(stat.state, 'S');
+    assert_eq!(stat.ppid, 1);
+    assert_eq!((stat.utime, stat.stime), (7, 3));
+    assert_eq!(stat.nice, -5);
+    assert_eq!(stat.num_threads, 2);
+    assert_eq!(stat.starttime, 40);
+    assert_eq!(stat.processor, 3);
+  }
+
+  #[test]
+  fn parse_status_line_splits_on_first_colon() {
+    let (key, values) = parse_status_line("Name:\tkworker/0:1").unwrap();
+    assert_eq!(key, "Name");
+    assert_eq!(values, vec!["kworker/0:1"]);
+    assert!(parse_status_line("no colon here").is_err());
+  }
+
+  #[test]
+  fn parse_stat_line_rejects_garbage() {
+    assert!(parse_stat_line("").is_err());
+    assert!(parse_stat_line("12 (short) S 1").is_err());
+  }
+
+  #[test]
+  fn read_processes_from_fixture() {
+    let source = fixture("basic");
+    let processes = read_processes(&source).unwrap();
+    assert_eq!(processes.len(), 5);
+
+    let java = find(&processes, 42);
+    assert_eq!(java.name, "java");
+    assert_eq!(java.user, "app");
+    assert_eq!(java.ppid, 1);
+    assert_eq!(java.state, 'S');
+    assert_eq!(java.memory, 812000);
+    assert_eq!(java.virtual_memory, 4200000);
+    assert_eq!(java.thread_count, 64);
+    assert_eq!((java.user_time, java.system_time), (5000, 700));
+    assert_eq!(java.start_time, 1500);
+
+    let postgres = find(&processes, 100);
+    assert_eq!(postgres.user, "postgres");
+    assert_eq!(postgres.priority, 5);
+
+    let kworker = find(&processes, 201);
+    assert_eq!(kworker.memory, 0);
+    assert_eq!(kworker.virtual_memory, 0);
+  }
+
+  #[test]
+  fn read_process_info_missing_pid_has_vanished() {
+    assert!(matches!(
+      read_process_info(&fixture("basic"), 4242),
+      Err(Error::ProcessVanished(4242))
+    ));
+  }
+
+  #[test]
+  fn read_process_info_reports_path_and_line() {
+    let source = fixture("broken");
+    match read_process_info(&source, 7) {
+      Err(Error::Parse { path, line, .. }) => {
+        assert_eq!(path, source.proc("7/status"));
+        assert_eq!(line, Some(3));
+      }
+      other => panic!("expected a parse error, got {:?}", other.map(|p| p.pid)),
+    }
+    match read_process_info(&source, 8) {
+      Err(Error::Parse { line, message, .. }) => {
+        assert_eq!(line, None);
+        assert_eq!(message, "missing Uid");
+      }
+      other => panic!("expected a parse error, got {:?}", other.map(|p| p.pid)),
+    }
+  }
+
+  #[test]
+  fn read_processes_skips_unreadable_processes() {
+    assert!(read_processes(&fixture("broken")).unwrap().is_empty());
+  }
+
+  #[test]
+  fn missing_proc_root_is_io_error() {
+    assert!(matches!(read_processes(&fixture("does-not-exist")), Err(Error::Io(_))));
+  }
+
+  #[test]
+  fn unknown_sort_and_filter_fields_are_errors() {
+    assert!(matches!("colour".parse::<Field>(), Err(Error::UnknownField(field)) if field == "colour"));
+    assert!(matches!(
+      Filter::new("flavour", "x", false),
+      Err(Error::UnknownField(field)) if field == "flavour"
+    ));
+  }
+
+  #[test]
+  fn field_names_and_keys_round_trip() {
+    for field in Field::ALL {
+      assert_eq!(field.to_string().parse::<Field>().unwrap(), field);
+      assert_eq!(Field::from_key(field.key()), Some(field));
+    }
+  }
+
+  #[test]
+  fn start_time_is_to_the_minute() {
+    let mut java = find(&read_processes(&fixture("basic")).unwrap(), 42).clone();
+    assert_eq!(Field::StartTime.format(&java).len(), "2023-11-14 22:13".len());
+    // past what localtime can represent the raw timestamp is printed, whatever its length
+    java.started = u64::MAX;
+    assert!(!Field::StartTime.format(&java).is_empty());
+  }
+
+  #[test]
+  fn list_processes_sorts_and_filters() {
+    let processes = read_processes(&fixture("basic")).unwrap();
+    let pids = |list: Vec<ProcessInfo>| list.iter().map(|p| p.pid).collect::<Vec<_>>();
+
+    let by_memory = list_processes(processes.clone(), 0, 3, Field::Memory, false, None).unwrap();
+    assert_eq!(pids(by_memory), vec![42, 100, 1]);
+
+    let by_name = list_processes(processes.clone(), 0, usize::MAX, Field::Name, true, None).unwrap();
+    assert_eq!(pids(by_name), vec![200, 42, 201, 100, 1]);
+
+    let root = Filter::new("user", "root", true).unwrap();
+    let roots = list_processes(processes.clone(), 0, usize::MAX, Field::Pid, true, Some(&root)).unwrap();
+    assert_eq!(pids(roots), vec![1, 200, 201]);
+
+    let any = Filter::new("any", "gres", false).unwrap();
+    assert_eq!(pids(filter_processes(processes.clone(), &any)), vec![100]);
+
+    let any = Filter::new("any", "app.jar", false).unwrap();
+    assert_eq!(pids(filter_processes(processes, &any)), vec![42]);
+  }
+
+  #[test]
+  fn reads_command_exe_and_cwd() {
+    let processes = read_processes(&fixture("basic")).unwrap();
+
+    let java = find(&processes, 42);
+    assert_eq!(java.command, "/usr/lib/jvm/bin/java -Xmx2g -jar /srv/app/app.jar");
+    assert_eq!(java.exe, "/usr/lib/jvm/bin/java");
+    assert_eq!(java.cwd, "/srv/app");
+    assert_eq!(
+      format!("{:#}", java).trim_end().rsplit('\t').next(),
+      Some(java.command.as_str())
+    );
+
+    // kernel threads have an empty cmdline and no links
+    let kworker = find(&processes, 201);
+    assert_eq!(kworker.command, "[kworker/0:1]");
+    assert_eq!((kworker.exe.as_str(), kworker.cwd.as_str()), ("", ""));
+  }
+
+  #[test]
+  fn unreadable_links_degrade_to_marker() {
+    let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+    assert_eq!(link_or_marker(Err(denied)), UNREADABLE);
+    assert_eq!(link_or_marker(Err(io::Error::from(io::ErrorKind::NotFound))), "");
+    assert_eq!(link_or_marker(Ok(PathBuf::from("/bin/sh"))), "/bin/sh");
+  }
+
+  #[test]
+  fn username_lookup_uses_source_passwd() {
+    let source = fixture("basic");
+    assert_eq!(get_username_from_uid(&source, 998).as_deref(), Some("postgres"));
+    assert_eq!(get_username_from_uid(&source, 12345), None);
+  }
+
+  #[test]
+  fn parse_cpu_stats_from_fixture() {
+    let content = fs::read_to_string(fixture("basic").proc("stat")).unwrap();
+    let stats = parse_cpu_stats(&content);
+    assert_eq!(stats, vec![(93800, 80000), (46900, 40000), (46900, 40000)]);
+  }
+
+  #[test]
+  fn update_cpu_percent_uses_tick_deltas() {
+    let mut processes = vec![read_process_info(&fixture("basic"), 42).unwrap()];
+    let mut prev = (0, BTreeMap::new());
+    update_cpu_percent(&mut processes, &[(1000, 0), (500, 0), (500, 0)], &mut prev);
+    assert_eq!(processes[0].cpu_percent, 0.0);
+
+    // 100 more ticks out of 1000 total over two cpus is 20% of one core
+    processes[0].user_time += 100;
+    update_cpu_percent(&mut processes, &[(2000, 0), (1000, 0), (1000, 0)], &mut prev);
+    assert!((processes[0].cpu_percent - 20.0).abs() < 1e-9);
+
+    // a reused pid starts from scratch
+    processes[0].start_time += 1;
+    processes[0].cpu_percent = 0.0;
+    update_cpu_percent(&mut processes, &[(3000, 0), (1500, 0), (1500, 0)], &mut prev);
+    assert_eq!(processes[0].cpu_percent, 0.0);
+  }
+
+  #[test]
+  fn reads_start_io_and_cgroup() {
+    let processes = read_processes(&fixture("basic")).unwrap();
+    let java = find(&processes, 42);
+    // btime 1700000000 plus 1500 ticks
+    assert_eq!(java.started, 1_700_000_015);
+    assert_eq!((java.io_read, java.io_write), (1_048_576, 4096));
+    assert_eq!(java.cgroup, "/system.slice/app.service");
+    assert_eq!(
+      java.cells(&[Field::Pid, Field::Memory, Field::Cgroup]),
+      vec!["42", "792", "/system.slice/app.service"]
+    );
+
+    // missing files are zeros and an empty path
+    let kworker = find(&processes, 201);
+    assert_eq!((kworker.io_read, kworker.cgroup.as_str()), (0, ""));
+  }
+
+  #[test]
+  fn update_io_rates_uses_byte_deltas() {
+    let mut processes = vec![read_process_info(&fixture("basic"), 42).unwrap()];
+    let mut prev = (None, BTreeMap::new());
+    let start = Instant::now();
+    update_io_rates(&mut processes, start, &mut prev);
+    assert_eq!(processes[0].io_read_rate, 0.0);
+
+    processes[0].io_read += 2048;
+    update_io_rates(&mut processes, start + std::time::Duration::from_secs(2), &mut prev);
+    assert_eq!((processes[0].io_read_rate, processes[0].io_write_rate), (1024.0, 0.0));
+  }
+
+  #[test]
+  fn process_handle_outlives_its_pid() {
+    let mut child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+    let process = ProcessHandle::open(child.id() as pid_t).unwrap();
+    process.check_running().unwrap();
+    assert_eq!(process.uid().unwrap(), unsafe { libc::getuid() });
+    let mut info = read_process_info(&ProcSource::default(), process.pid()).unwrap();
+    assert_eq!(ProcessHandle::open_process(&info).unwrap().pid(), process.pid());
+    info.start_time += 1;
+    assert!(matches!(
+      ProcessHandle::open_process(&info),
+      Err(Error::ProcessVanished(_))
+    ));
+    let (tx, _rx) = std::sync::mpsc::channel();
+    process.send_signal(libc::SIGKILL, Some(&tx)).unwrap();
+    child.wait().unwrap();
+    // the pid may be reused now, the handle still names the exited process
+    assert!(matches!(process.check_running(), Err(Error::ProcessVanished(_))));
+    assert!(process.send_signal(libc::SIGTERM, Some(&tx)).is_err());
+  }
+
+  #[test]
+  fn read_threads_from_fixture() {
+    let threads = Counters::default().read_threads(&fixture("basic"), 42).unwrap();
+    let tids: Vec<pid_t> = threads.iter().map(|t| t.tid).collect();
+    assert_eq!(tids, vec![42, 43, 57]);
+
+    let gc = &threads[2];
+    assert_eq!(gc.name, "GC Thread#0");
+    assert_eq!(gc.state, 'R');
+    assert_eq!((gc.user_time, gc.system_time), (3900, 100));
+    assert_eq!(gc.processor, 1);
+
+    assert!(matches!(
+      Counters::default().read_threads(&fixture("basic"), 4242),
+      Err(Error::ProcessVanished(4242))
+    ));
+  }
+
+  #[test]
+  fn build_tree_keeps_sibling_order_and_orphans() {
+    let processes = read_processes(&fixture("basic")).unwrap();
+    let by_name = list_processes(processes, 0, usize::MAX, Field::Name, true, None).unwrap();
+    let tree = build_tree(&by_name, 0);
+    let lines = tree.lines(&HashSet::new());
+    let rows: Vec<(pid_t, &str)> = lines.iter().map(|l| (l.pid, l.guides.as_str())).collect();
+    // kworker's parent kthreadd is not in the fixture, so it hangs off the root
+    assert_eq!(
+      rows,
+      vec![
+        (201, "├─ "),
+        (1, "└─ "),
+        (200, "   ├─ "),
+        (42, "   ├─ "),
+        (100, "   └─ ")
+      ]
+    );
+    assert!(lines[1].has_children);
+
+    let collapsed = tree.lines(&HashSet::from([1]));
+    assert_eq!(collapsed.iter().map(|l| l.pid).collect::<Vec<_>>(), vec![201, 1]);
+    assert!(collapsed[1].collapsed);
+
+    let mut subtree = build_tree(&by_name, 1);
+    assert_eq!(subtree.children.len(), 3);
+    subtree.truncate(3);
+    assert_eq!(
+      subtree.format(&by_name, &[], None),
+      "systemd(1)\n├─ (sd-pam)(200)\n└─ java(42)\n"
+    );
+
+    let mut truncated = build_tree(&by_name, 0);
+    truncated.truncate(2);
+    let pids: Vec<pid_t> = truncated.lines(&HashSet::new()).iter().map(|l| l.pid).collect();
+    assert_eq!(pids, vec![201, 1]);
+  }
+
+  #[test]
+  fn filter_tree_keeps_ancestors() {
+    let processes = read_processes(&fixture("basic")).unwrap();
+    let by_pid = list_processes(processes, 0, usize::MAX, Field::Pid, true, None).unwrap();
+    let (kept, matched) = filter_tree(by_pid, &Filter::new("name", "java", true).unwrap());
+    assert_eq!(kept.iter().map(|p| p.pid).collect::<Vec<_>>(), vec![1, 42]);
+    assert_eq!(matched, HashSet::from([42]));
+
+    let tree = build_tree(&kept, 0);
+    assert_eq!(
+      tree.format(&kept, &[Field::User], None),
+      "└─ systemd(1) root\n   └─ java(42) app\n"
+    );
+    assert_eq!(
+      build_tree(&kept, 1).format(&kept, &[], Some(&matched)),
+      "systemd(1)\n└─ \x1b[1mjava\x1b[0m(42)\n"
+    );
+
+    let json = tree.to_json(&kept, Some(&matched));
+    assert_eq!(json[0]["name"], "systemd");
+    assert_eq!(json[0]["matched"], false);
+    assert_eq!(json[0]["children"][0]["pid"], 42);
+    assert_eq!(json[0]["children"][0]["matched"], true);
+  }
+
+  #[test]
+  fn parse_signal_accepts_names_and_numbers() {
+    assert_eq!(parse_signal("TERM").unwrap(), libc::SIGTERM);
+    assert_eq!(parse_signal("SIGHUP").unwrap(), libc::SIGHUP);
+    assert_eq!(parse_signal("usr1").unwrap(), libc::SIGUSR1);
+    assert_eq!(parse_signal("9").unwrap(), libc::SIGKILL);
+    assert!(matches!(parse_signal("SIGFOO"), Err(Error::InvalidArgument(_))));
+    assert!(matches!(parse_signal("-1"), Err(Error::InvalidArgument(_))));
+    assert_eq!(signal_name(libc::SIGKILL), "SIGKILL");
+    assert_eq!(signal_name(libc::SIGRTMIN()), libc::SIGRTMIN().to_string());
+  }
+
+  #[test]
+  fn parse_cpus_checks_the_range() {
+    assert_eq!(parse_cpus("0, 2,3").unwrap(), vec![0, 2, 3]);
+    let last = libc::CPU_SETSIZE as usize - 1;
+    assert_eq!(parse_cpus(&last.to_string()).unwrap(), vec![last]);
+    assert!(matches!(parse_cpus("1,x"), Err(Error::InvalidArgument(_))));
+    assert!(matches!(parse_cpus("0,5000"), Err(Error::InvalidArgument(_))));
+    assert!(matches!(
+      parse_cpus(&libc::CPU_SETSIZE.to_string()),
+      Err(Error::InvalidArgument(_))
+    ));
+  }
+
+  #[test]
+  fn csv_records_match_header() {
+    let processes = read_processes(&fixture("basic")).unwrap();
+    let java = find(&processes, 42);
+    let record = java.to_csv();
+    assert_eq!(
+      record,
+      "app,42,1,java,S,812000,64,4200000,5000,700,0,1500,0.00,/usr/lib/jvm/bin/java -Xmx2g -jar /srv/app/app.jar,\
+/usr/lib/jvm/bin/java,/srv/app,1700000015,1048576,4096,0,0,/system.slice/app.service"
+    );
+    assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+
+    // the CSV and JSON names agree
+    let json = serde_json::to_value(java).unwrap();
+    for column in ProcessInfo::CSV_HEADER.split(',') {
+      assert!(json.get(column).is_some(), "{} is not serialized", column);
+    }
+    assert_eq!(
+      json.as_object().unwrap().len(),
+      ProcessInfo::CSV_HEADER.split(',').count()
+    );
+  }
+
+  #[test]
+  fn disk_stats_from_fixture() {
+    let stats = get_disk_stats(&fixture("basic")).unwrap();
+    let devices: Vec<&str> = stats.iter().map(|d| d.device.as_str()).collect();
+    assert_eq!(devices, vec!["sda", "sda1", "nvme0n1"]);
+    assert_eq!(stats[0].sectors_read, 884412);
+    assert_eq!(stats[0].sectors_written, 1632288);
+  }
+
+  #[test]
+  fn network_stats_from_fixture() {
+    let stats = get_network_stats(&fixture("basic")).unwrap();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].interface, "eth0");
+    assert_eq!(stats[0].bytes_received, 98765432);
+    assert_eq!(stats[0].bytes_transmitted, 12345678);
+    assert_eq!(stats[0].drops_transmitted, 4);
+  }
+}
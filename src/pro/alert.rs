@@ -0,0 +1,588 @@
+//! Alert rules evaluated on every sample, e.g. in a rules file:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "busy cpu"
+//! metric = "cpu"
+//! above = "90%"
+//! for = "60s"
+//! command = "notify-send \"$ALERT_MESSAGE\""
+//!
+//! [[rule]]
+//! name = "nginx down"
+//! metric = "processes"
+//! where = "name=nginx"
+//! below = 1
+//! log = "/var/log/procstat-alerts.log"
+//! ```
+//!
+//! Metrics are `cpu`, `memory_used` and `swap_free` in percent, `load` (1 minute), `disk_read`,
+//! `disk_write`, `network_receive` and `network_transmit` in bytes per second, summed over all
+//! devices unless `device` or `interface` is given, and `processes`, the number of processes
+//! matching `where`. A rule fires once its condition held for `for` and resolves as soon as it
+//! doesn't, running `command` with `ALERT_NAME`, `ALERT_STATE` (firing or resolved), `ALERT_VALUE`
+//! and `ALERT_MESSAGE` in its environment and appending to `log`.
+
+use super::query::{parse_duration, parse_size, Query};
+use super::{
+  format_rate, get_disk_rates, get_network_rates, record, toml_from_str, DiskStats, Error, NetworkStats, ProcessInfo,
+  Result, Snapshot, SystemSummary,
+};
+use serde::Deserialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone)]
+pub enum Metric {
+  Cpu,
+  Load,
+  /// RAM that isn't available, see `SystemSummary::available_ram_bytes`, so caches the kernel can
+  /// drop don't count as used. Free RAM stands in where the kernel doesn't report it.
+  MemoryUsed,
+  SwapFree,
+  /// Of one device, or all of them.
+  DiskRead(Option<String>),
+  DiskWrite(Option<String>),
+  /// Of one interface, or all of them.
+  NetworkReceive(Option<String>),
+  NetworkTransmit(Option<String>),
+  /// Number of processes matching the query, all of them without one.
+  Processes(Option<Query>),
+}
+
+impl Metric {
+  fn format(&self, value: f64) -> String {
+    match self {
+      Metric::Cpu | Metric::MemoryUsed | Metric::SwapFree => format!("{:.1}%", value),
+      Metric::Load => format!("{:.2}", value),
+      Metric::DiskRead(_) | Metric::DiskWrite(_) | Metric::NetworkReceive(_) | Metric::NetworkTransmit(_) => {
+        format_rate(value)
+      }
+      Metric::Processes(_) => format!("{}", value),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+  pub name: String,
+  pub metric: Metric,
+  /// Fires above this value, or below it if `below` is set.
+  pub threshold: f64,
+  pub below: bool,
+  /// Seconds the condition has to hold before firing.
+  pub duration: f64,
+  pub command: Option<String>,
+  pub log: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Threshold {
+  Number(f64),
+  Text(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+  name: String,
+  metric: String,
+  above: Option<Threshold>,
+  below: Option<Threshold>,
+  #[serde(rename = "for")]
+  duration: Option<Threshold>,
+  device: Option<String>,
+  interface: Option<String>,
+  #[serde(rename = "where")]
+  query: Option<String>,
+  command: Option<String>,
+  log: Option<PathBuf>,
+}
+
+/// The `[[rule]]` tables of a rules file.
+#[derive(Deserialize, Default)]
+pub struct Rules {
+  #[serde(default, rename = "rule")]
+  raw: Vec<RawRule>,
+}
+
+impl TryFrom<RawRule> for Rule {
+  type Error = String;
+
+  fn try_from(raw: RawRule) -> std::result::Result<Self, String> {
+    let unexpected = |option: &str, value: &Option<String>| match value {
+      Some(_) => Err(format!("'{}' doesn't apply to metric '{}'", option, raw.metric)),
+      None => Ok(()),
+    };
+    let metric = match raw.metric.as_str() {
+      "disk_read" | "disk_write" => {
+        unexpected("interface", &raw.interface)?;
+        unexpected("where", &raw.query)?;
+        match raw.metric.as_str() {
+          "disk_read" => Metric::DiskRead(raw.device.clone()),
+          _ => Metric::DiskWrite(raw.device.clone()),
+        }
+      }
+      "network_receive" | "network_transmit" => {
+        unexpected("device", &raw.device)?;
+        unexpected("where", &raw.query)?;
+        match raw.metric.as_str() {
+          "network_receive" => Metric::NetworkReceive(raw.interface.clone()),
+          _ => Metric::NetworkTransmit(raw.interface.clone()),
+        }
+      }
+      "processes" => {
+        unexpected("device", &raw.device)?;
+        unexpected("interface", &raw.interface)?;
+        match &raw.query {
+          Some(query) => Metric::Processes(Some(query.parse().map_err(|e: Error| e.to_string())?)),
+          None => Metric::Processes(None),
+        }
+      }
+      metric => {
+        unexpected("device", &raw.device)?;
+        unexpected("interface", &raw.interface)?;
+        unexpected("where", &raw.query)?;
+        match metric {
+          "cpu" => Metric::Cpu,
+          "load" => Metric::Load,
+          "memory_used" => Metric::MemoryUsed,
+          "swap_free" => Metric::SwapFree,
+          _ => return Err(format!("unknown metric '{}'", metric)),
+        }
+      }
+    };
+
+    let (threshold, below) = match (&raw.above, &raw.below) {
+      (Some(above), None) => (above, false),
+      (None, Some(below)) => (below, true),
+      _ => return Err("needs either 'above' or 'below'".to_string()),
+    };
+    let threshold = match threshold {
+      Threshold::Number(number) => Some(*number),
+      Threshold::Text(text) => {
+        let text = text.trim();
+        let text = text.strip_suffix("/s").unwrap_or(text);
+        match text.strip_suffix('%') {
+          Some(percent) => percent.trim().parse().ok(),
+          None => parse_size(text),
+        }
+      }
+    }
+    .ok_or("invalid threshold")?;
+    let duration = match &raw.duration {
+      None => 0.0,
+      Some(Threshold::Number(seconds)) => *seconds,
+      Some(Threshold::Text(text)) => parse_duration(text.trim()).ok_or(format!("invalid duration '{}'", text))?,
+    };
+
+    Ok(Rule {
+      name: raw.name,
+      metric,
+      threshold,
+      below,
+      duration,
+      command: raw.command,
+      log: raw.log,
+    })
+  }
+}
+
+impl Rules {
+  /// Validates the rules, `path` is where they were read from, for errors.
+  pub fn into_rules(self, path: &Path) -> Result<Vec<Rule>> {
+    let rules = self
+      .raw
+      .into_iter()
+      .map(|raw| {
+        let name = raw.name.clone();
+        Rule::try_from(raw).map_err(|e| Error::parse(path, None, format!("rule '{}': {}", name, e)))
+      })
+      .collect::<Result<Vec<Rule>>>()?;
+    // events name their rule, so the names must tell the rules apart
+    for (i, rule) in rules.iter().enumerate() {
+      if rules[..i].iter().any(|other| other.name == rule.name) {
+        return Err(Error::parse(
+          path,
+          None,
+          format!("rule '{}' is defined twice", rule.name),
+        ));
+      }
+    }
+    Ok(rules)
+  }
+}
+
+/// Reads the `[[rule]]` tables of the TOML file at `path`.
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>> {
+  let text = fs::read_to_string(path).map_err(|e| Error::from_read(e, path, None))?;
+  toml_from_str::<Rules>(&text, path)?.into_rules(path)
+}
+
+/// What the rules look at, borrowed from a `Snapshot` or the state of a frontend.
+pub struct Sample<'a> {
+  pub timestamp: f64,
+  /// Total first, as from `Counters::cpu_usage`.
+  pub cpu_percent: &'a [f64],
+  pub system: &'a SystemSummary,
+  pub disks: &'a [DiskStats],
+  pub networks: &'a [NetworkStats],
+  pub processes: &'a [ProcessInfo],
+}
+
+impl<'a> From<&'a Snapshot> for Sample<'a> {
+  fn from(snapshot: &'a Snapshot) -> Self {
+    Sample {
+      timestamp: snapshot.timestamp,
+      cpu_percent: &snapshot.cpu_percent,
+      system: &snapshot.system,
+      disks: &snapshot.disks,
+      networks: &snapshot.networks,
+      processes: &snapshot.processes,
+    }
+  }
+}
+
+/// A rule starting or stopping to fire.
+#[derive(Debug, Clone)]
+pub struct Event {
+  pub rule: String,
+  pub firing: bool,
+  pub timestamp: f64,
+  pub value: f64,
+  pub message: String,
+}
+
+#[derive(Default)]
+struct State {
+  // when the condition started to hold
+  since: Option<f64>,
+  firing: bool,
+  value: Option<f64>,
+}
+
+/// A firing rule.
+pub struct Alert<'a> {
+  pub rule: &'a Rule,
+  pub since: f64,
+  pub value: f64,
+  pub message: String,
+}
+
+/// Evaluates rules on consecutive samples.
+pub struct Alerts {
+  rules: Vec<Rule>,
+  states: Vec<State>,
+  // for the disk and network rates
+  previous: Option<(f64, Vec<DiskStats>, Vec<NetworkStats>)>,
+}
+
+impl Alerts {
+  pub fn new(rules: Vec<Rule>) -> Self {
+    let states = rules.iter().map(|_| State::default()).collect();
+    Alerts {
+      rules,
+      states,
+      previous: None,
+    }
+  }
+
+  pub fn rules(&self) -> &[Rule] {
+    &self.rules
+  }
+
+  fn value(&self, metric: &Metric, sample: &Sample) -> Option<f64> {
+    let percent = |part: u64, total: u64| (total > 0).then(|| part as f64 / total as f64 * 100.0);
+    let system = sample.system;
+    let rates = |disk: bool| {
+      let (timestamp, disks, networks) = self.previous.as_ref()?;
+      let elapsed = sample.timestamp - timestamp;
+      if elapsed <= 0.0 {
+        return None;
+      }
+      Some(match disk {
+        true => get_disk_rates(disks, sample.disks, elapsed),
+        false => get_network_rates(networks, sample.networks, elapsed),
+      })
+    };
+    let sum = |rates: Vec<(String, f64, f64)>, name: &Option<String>, second: bool| {
+      let matching = rates
+        .iter()
+        .filter(|(n, _, _)| name.as_ref().is_none_or(|name| name == n));
+      matching
+        .map(|(_, first_rate, second_rate)| if second { second_rate } else { first_rate })
+        .sum()
+    };
+    match metric {
+      Metric::Cpu => sample.cpu_percent.first().copied(),
+      Metric::Load => Some(system.load_average[0]),
+      Metric::MemoryUsed => {
+        let available = system.available_ram_bytes.unwrap_or(system.free_ram_bytes);
+        percent(system.total_ram_bytes.saturating_sub(available), system.total_ram_bytes)
+      }
+      Metric::SwapFree => percent(system.free_swap_bytes, system.total_swap_bytes),
+      Metric::DiskRead(device) => Some(sum(rates(true)?, device, false)),
+      Metric::DiskWrite(device) => Some(sum(rates(true)?, device, true)),
+      Metric::NetworkReceive(interface) => Some(sum(rates(false)?, interface, false)),
+      Metric::NetworkTransmit(interface) => Some(sum(rates(false)?, interface, true)),
+      Metric::Processes(query) => Some(
+        sample
+          .processes
+          .iter()
+          .filter(|p| query.as_ref().is_none_or(|q| q.matches(p)))
+          .count() as f64,
+      ),
+    }
+  }
+
+  /// Evaluates every rule on `sample`, returning the rules that started or stopped firing.
+  pub fn update(&mut self, sample: &Sample) -> Vec<Event> {
+    let mut events = Vec::new();
+    for index in 0..self.rules.len() {
+      let rule = &self.rules[index];
+      let value = self.value(&rule.metric, sample);
+      let state = &mut self.states[index];
+      state.value = value;
+      // without a value, e.g. rates in the first sample, nothing changes
+      let Some(value) = value else {
+        continue;
+      };
+      let holds = if rule.below {
+        value < rule.threshold
+      } else {
+        value > rule.threshold
+      };
+      if !holds {
+        state.since = None;
+        if state.firing {
+          state.firing = false;
+          events.push(Event {
+            rule: rule.name.clone(),
+            firing: false,
+            timestamp: sample.timestamp,
+            value,
+            message: format!(
+              "{}: resolved, {} is {}",
+              rule.name,
+              describe(&rule.metric),
+              rule.metric.format(value)
+            ),
+          });
+        }
+        continue;
+      }
+      let since = *state.since.get_or_insert(sample.timestamp);
+      if !state.firing && sample.timestamp - since >= rule.duration {
+        state.firing = true;
+        events.push(Event {
+          rule: rule.name.clone(),
+          firing: true,
+          timestamp: sample.timestamp,
+          value,
+          message: firing_message(rule, value),
+        });
+      }
+    }
+    self.previous = Some((sample.timestamp, sample.disks.to_vec(), sample.networks.to_vec()));
+    events
+  }
+
+  /// The rules firing right now.
+  pub fn firing(&self) -> Vec<Alert<'_>> {
+    self
+      .rules
+      .iter()
+      .zip(&self.states)
+      .filter(|(_, state)| state.firing)
+      .map(|(rule, state)| {
+        let value = state.value.unwrap_or(f64::NAN);
+        Alert {
+          rule,
+          since: state.since.unwrap_or_default(),
+          value,
+          message: firing_message(rule, value),
+        }
+      })
+      .collect()
+  }
+
+  /// Runs the command and appends to the log of the rule of `event`. The command runs in the
+  /// background, we don't wait for it.
+  pub fn notify(&self, event: &Event) -> Result<()> {
+    let Some(rule) = self.rules.iter().find(|rule| rule.name == event.rule) else {
+      return Ok(());
+    };
+    let state = if event.firing { "firing" } else { "resolved" };
+    if let Some(log) = &rule.log {
+      let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .map_err(|e| Error::from_read(e, log, None))?;
+      writeln!(
+        file,
+        "{} {} {}",
+        record::format_timestamp(event.timestamp),
+        state.to_uppercase(),
+        event.message
+      )?;
+    }
+    if let Some(command) = &rule.command {
+      let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("ALERT_NAME", &rule.name)
+        .env("ALERT_STATE", state)
+        .env("ALERT_VALUE", event.value.to_string())
+        .env("ALERT_MESSAGE", &event.message)
+        .stdin(Stdio::null())
+        .spawn()?;
+      // reaps the child, so it doesn't linger as a zombie
+      std::thread::spawn(move || child.wait());
+    }
+    Ok(())
+  }
+}
+
+fn describe(metric: &Metric) -> String {
+  match metric {
+    Metric::Cpu => "cpu".to_string(),
+    Metric::Load => "load".to_string(),
+    Metric::MemoryUsed => "memory used".to_string(),
+    Metric::SwapFree => "free swap".to_string(),
+    Metric::DiskRead(device) => format!("reads of {}", device.as_deref().unwrap_or("all disks")),
+    Metric::DiskWrite(device) => format!("writes of {}", device.as_deref().unwrap_or("all disks")),
+    Metric::NetworkReceive(interface) => {
+      format!("receive rate of {}", interface.as_deref().unwrap_or("all interfaces"))
+    }
+    Metric::NetworkTransmit(interface) => {
+      format!("transmit rate of {}", interface.as_deref().unwrap_or("all interfaces"))
+    }
+    Metric::Processes(Some(query)) => format!("number of processes matching '{}'", query),
+    Metric::Processes(None) => "number of processes".to_string(),
+  }
+}
+
+fn firing_message(rule: &Rule, value: f64) -> String {
+  format!(
+    "{}: {} is {}, {} {}",
+    rule.name,
+    describe(&rule.metric),
+    rule.metric.format(value),
+    if rule.below { "below" } else { "above" },
+    rule.metric.format(rule.threshold)
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::fixture_snapshot;
+  use super::*;
+
+  fn rules(text: &str) -> Result<Vec<Rule>> {
+    toml_from_str::<Rules>(text, Path::new("rules.toml"))?.into_rules(Path::new("rules.toml"))
+  }
+
+  fn snapshot(timestamp: f64, cpu: f64) -> Snapshot {
+    Snapshot {
+      cpu_percent: vec![cpu],
+      ..fixture_snapshot(timestamp)
+    }
+  }
+
+  #[test]
+  fn fires_after_duration_and_resolves() {
+    let mut alerts =
+      Alerts::new(rules("[[rule]]\nname = \"busy\"\nmetric = \"cpu\"\nabove = \"90%\"\nfor = \"60s\"").unwrap());
+    assert!(alerts.update(&Sample::from(&snapshot(0.0, 95.0))).is_empty());
+    assert!(alerts.update(&Sample::from(&snapshot(30.0, 95.0))).is_empty());
+    let events = alerts.update(&Sample::from(&snapshot(60.0, 99.0)));
+    assert_eq!(events.len(), 1);
+    assert!(events[0].firing);
+    assert_eq!(events[0].message, "busy: cpu is 99.0%, above 90.0%");
+    assert_eq!(alerts.firing().len(), 1);
+    assert!(alerts.update(&Sample::from(&snapshot(70.0, 97.0))).is_empty());
+
+    let events = alerts.update(&Sample::from(&snapshot(80.0, 10.0)));
+    assert_eq!((events.len(), events[0].firing), (1, false));
+    assert!(alerts.firing().is_empty());
+    // a dip starts the duration over
+    alerts.update(&Sample::from(&snapshot(90.0, 95.0)));
+    assert!(alerts.update(&Sample::from(&snapshot(120.0, 95.0))).is_empty());
+  }
+
+  #[test]
+  fn memory_used_leaves_out_available_memory() {
+    let mut alerts =
+      Alerts::new(rules("[[rule]]\nname = \"memory\"\nmetric = \"memory_used\"\nabove = \"50%\"").unwrap());
+    let mut snapshot = snapshot(0.0, 0.0);
+    // 8000000 kB in all, 500000 kB free and 6000000 kB available counting caches
+    assert_eq!(snapshot.system.available_ram_bytes, Some(6_000_000 * 1024));
+    snapshot.system.total_ram_bytes = 8_000_000 * 1024;
+    snapshot.system.free_ram_bytes = 500_000 * 1024;
+    assert!(alerts.update(&Sample::from(&snapshot)).is_empty());
+
+    snapshot.system.available_ram_bytes = None;
+    let events = alerts.update(&Sample::from(&snapshot));
+    assert_eq!(events[0].message, "memory: memory used is 93.8%, above 50.0%");
+  }
+
+  #[test]
+  fn counts_processes_and_rates() {
+    let text = r#"
+      [[rule]]
+      name = "nginx down"
+      metric = "processes"
+      where = "name=nginx"
+      below = 1
+
+      [[rule]]
+      name = "big java"
+      metric = "processes"
+      where = "name=java and mem>500M"
+      above = 0
+
+      [[rule]]
+      name = "sda writes"
+      metric = "disk_write"
+      device = "sda"
+      above = "1M/s"
+    "#;
+    let mut alerts = Alerts::new(rules(text).unwrap());
+    let names = |events: Vec<Event>| events.into_iter().map(|e| e.rule).collect::<Vec<_>>();
+    let first = snapshot(0.0, 0.0);
+    assert_eq!(
+      names(alerts.update(&Sample::from(&first))),
+      vec!["nginx down", "big java"]
+    );
+
+    let mut second = snapshot(1.0, 0.0);
+    let sda = second.disks.iter_mut().find(|d| d.device == "sda").unwrap();
+    // 2 MiB in a second
+    sda.sectors_written += 4096;
+    let events = alerts.update(&Sample::from(&second));
+    assert_eq!(names(events), vec!["sda writes"]);
+    assert_eq!(
+      alerts.firing().last().unwrap().message,
+      "sda writes: writes of sda is 2.00 MB/s, above 1.00 MB/s"
+    );
+  }
+
+  #[test]
+  fn rejects_bad_rules() {
+    let error = |text: &str| rules(text).unwrap_err().to_string();
+    assert!(error("[[rule]]\nname = \"x\"\nmetric = \"cpu\"").contains("rule 'x': needs either 'above' or 'below'"));
+    assert!(error("[[rule]]\nname = \"x\"\nmetric = \"gpu\"\nabove = 1").contains("unknown metric 'gpu'"));
+    assert!(error("[[rule]]\nname = \"x\"\nmetric = \"cpu\"\ndevice = \"sda\"\nabove = 1").contains("'device'"));
+    assert!(error("[[rule]]\nname = \"x\"\nmetric = \"cpu\"\nabove = \"lots\"").contains("invalid threshold"));
+    let rule = "[[rule]]\nname = \"x\"\nmetric = \"cpu\"\nabove = 1\n";
+    assert!(error(&format!("{}{}", rule, rule)).contains("rule 'x' is defined twice"));
+    match rules("[[rule]]\nname = \"x\"\nmetric = \"cpu\"\nabove = 1\n\nbogus = 2") {
+      Err(Error::Parse { line, .. }) => assert_eq!(line, Some(6)),
+      other => panic!("expected a parse error, got {:?}", other.map(|r| r.len())),
+    }
+  }
+}
@@ -0,0 +1,237 @@
+//! Everything readable about one process under /proc/[pid], for the detail views. Files we may not
+//! read, like the environment of another user's process, are left empty rather than failing the
+//! whole read.
+
+use super::{
+  parse_status_line, read_pid_file, read_process_info, record, Error, ProcSource, ProcessInfo, Result, UNREADABLE,
+};
+use libc::pid_t;
+use serde::Serialize;
+use std::fs;
+
+/// The /proc/[pid]/status keys of the memory breakdown, in kB.
+pub const MEMORY_KEYS: [&str; 13] = [
+  "VmPeak", "VmSize", "VmHWM", "VmRSS", "RssAnon", "RssFile", "RssShmem", "VmData", "VmStk", "VmExe", "VmLib", "VmPTE",
+  "VmSwap",
+];
+
+/// A row of /proc/[pid]/limits.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Limit {
+  pub name: String,
+  pub soft: String,
+  pub hard: String,
+  /// Empty for counts.
+  pub units: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ProcessDetails {
+  pub info: ProcessInfo,
+  /// The lines of /proc/[pid]/status in order, values joined by a space.
+  pub status: Vec<(String, String)>,
+  /// The `MEMORY_KEYS` found in the status, in kB.
+  pub memory: Vec<(String, u64)>,
+  /// "NAME=value" entries, None if unreadable.
+  pub environment: Option<Vec<String>>,
+  pub limits: Option<Vec<Limit>>,
+  /// Namespace name and link target, e.g. ("net", "net:[4026531840]").
+  pub namespaces: Option<Vec<(String, String)>>,
+  pub open_files: Option<usize>,
+  /// All counters of /proc/[pid]/io, e.g. ("rchar", 2000000).
+  pub io: Option<Vec<(String, u64)>>,
+}
+
+pub fn read_process_details(source: &ProcSource, pid: pid_t) -> Result<ProcessDetails> {
+  let info = read_process_info(source, pid)?;
+  let (path, content) = read_pid_file(source, pid, "status")?;
+  let mut status = Vec::new();
+  for (index, line) in content.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+    let (key, values) = parse_status_line(line).map_err(|e| Error::parse(&path, Some(index + 1), e))?;
+    status.push((key, values.join(" ")));
+  }
+  let memory = MEMORY_KEYS
+    .iter()
+    .filter_map(|&key| {
+      let (_, value) = status.iter().find(|(k, _)| k == key)?;
+      // "812000 kB"
+      Some((key.to_string(), value.split_whitespace().next()?.parse().ok()?))
+    })
+    .collect();
+
+  Ok(ProcessDetails {
+    info,
+    status,
+    memory,
+    environment: read_environment(source, pid),
+    limits: read_pid_file(source, pid, "limits")
+      .ok()
+      .map(|(_, content)| parse_limits(&content)),
+    namespaces: read_namespaces(source, pid),
+    open_files: read_dir_names(source, pid, "fd").map(|names| names.len()),
+    io: read_pid_file(source, pid, "io")
+      .ok()
+      .map(|(_, content)| parse_io(&content)),
+  })
+}
+
+impl ProcessDetails {
+  /// The main facts as label and value, `now` in seconds since the Unix epoch.
+  pub fn overview(&self, now: u64) -> Vec<(&'static str, String)> {
+    let info = &self.info;
+    vec![
+      ("Command", info.command.clone()),
+      ("Exe", info.exe.clone()),
+      ("Cwd", info.cwd.clone()),
+      ("User", info.user.clone()),
+      ("PID", info.pid.to_string()),
+      ("PPID", info.ppid.to_string()),
+      ("State", info.state.to_string()),
+      ("Threads", info.thread_count.to_string()),
+      ("Nice", info.priority.to_string()),
+      (
+        "Started",
+        format!(
+          "{} ({} ago)",
+          record::format_timestamp(info.started as f64),
+          format_elapsed(now.saturating_sub(info.started))
+        ),
+      ),
+      ("Cgroup", info.cgroup.clone()),
+      (
+        "Open files",
+        self.open_files.map_or(UNREADABLE.to_string(), |n| n.to_string()),
+      ),
+    ]
+  }
+}
+
+fn read_environment(source: &ProcSource, pid: pid_t) -> Option<Vec<String>> {
+  let content = fs::read(source.proc(format!("{}/environ", pid))).ok()?;
+  Some(
+    content
+      .split(|&b| b == 0)
+      .filter(|entry| !entry.is_empty())
+      .map(|entry| String::from_utf8_lossy(entry).into_owned())
+      .collect(),
+  )
+}
+
+// The header names the columns, which are separated by at least two spaces
+fn parse_limits(content: &str) -> Vec<Limit> {
+  let mut lines = content.lines();
+  let Some(header) = lines.next() else {
+    return Vec::new();
+  };
+  let soft = header.find("Soft Limit").unwrap_or(26);
+  let hard = header.find("Hard Limit").unwrap_or(47);
+  let units = header.find("Units").unwrap_or(68);
+  let column =
+    |line: &str, start: usize, end: usize| line.get(start..end.min(line.len())).unwrap_or("").trim().to_string();
+  lines
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| Limit {
+      name: column(line, 0, soft),
+      soft: column(line, soft, hard),
+      hard: column(line, hard, units),
+      units: column(line, units, line.len()),
+    })
+    .collect()
+}
+
+fn read_namespaces(source: &ProcSource, pid: pid_t) -> Option<Vec<(String, String)>> {
+  let names = read_dir_names(source, pid, "ns")?;
+  Some(
+    names
+      .into_iter()
+      .filter_map(|name| {
+        let target = fs::read_link(source.proc(format!("{}/ns/{}", pid, name))).ok()?;
+        Some((name, target.to_string_lossy().into_owned()))
+      })
+      .collect(),
+  )
+}
+
+// Sorted entries of /proc/[pid]/`dir`
+fn read_dir_names(source: &ProcSource, pid: pid_t, dir: &str) -> Option<Vec<String>> {
+  let entries = fs::read_dir(source.proc(format!("{}/{}", pid, dir))).ok()?;
+  let mut names: Vec<String> = entries
+    .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().into_owned()))
+    .collect();
+  names.sort();
+  Some(names)
+}
+
+fn parse_io(content: &str) -> Vec<(String, u64)> {
+  content
+    .lines()
+    .filter_map(|line| {
+      let (key, value) = line.split_once(':')?;
+      Some((key.trim().to_string(), value.trim().parse().ok()?))
+    })
+    .collect()
+}
+
+/// "3d 04:05:06" or "04:05:06".
+pub fn format_elapsed(seconds: u64) -> String {
+  let (days, seconds) = (seconds / 86400, seconds % 86400);
+  let clock = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds % 3600 / 60, seconds % 60);
+  if days > 0 {
+    format!("{}d {}", days, clock)
+  } else {
+    clock
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::fixture;
+  use super::*;
+
+  #[test]
+  fn reads_details_from_fixture() {
+    let details = read_process_details(&fixture("basic"), 42).unwrap();
+    assert_eq!(details.info.name, "java");
+    let overview = details.overview(1_700_000_015 + 90061);
+    assert_eq!(
+      overview[9].1,
+      format!("{} (1d 01:01:01 ago)", record::format_timestamp(1_700_000_015.0))
+    );
+    assert_eq!(details.status[2], ("State".to_string(), "S (sleeping)".to_string()));
+    assert_eq!(
+      details.memory,
+      vec![("VmSize".to_string(), 4_200_000), ("VmRSS".to_string(), 812_000)]
+    );
+    assert_eq!(
+      details.environment.unwrap(),
+      vec!["JAVA_HOME=/usr/lib/jvm", "LANG=C.UTF-8"]
+    );
+    let limits = details.limits.unwrap();
+    assert_eq!(
+      limits[1],
+      Limit {
+        name: "Max open files".to_string(),
+        soft: "1024".to_string(),
+        hard: "524288".to_string(),
+        units: "files".to_string(),
+      }
+    );
+    assert_eq!(limits[0].units, "seconds");
+    assert_eq!(
+      details.namespaces.unwrap(),
+      vec![("net".to_string(), "net:[4026531840]".to_string())]
+    );
+    assert_eq!(details.open_files, Some(3));
+    assert_eq!(details.io.unwrap()[4], ("read_bytes".to_string(), 1_048_576));
+
+    // missing or unreadable files are None
+    let kworker = read_process_details(&fixture("basic"), 201).unwrap();
+    assert!(kworker.environment.is_none() && kworker.limits.is_none() && kworker.open_files.is_none());
+  }
+
+  #[test]
+  fn formats_elapsed_time() {
+    assert_eq!(format_elapsed(59), "00:00:59");
+    assert_eq!(format_elapsed(3 * 86400 + 4 * 3600 + 5 * 60 + 6), "3d 04:05:06");
+  }
+}
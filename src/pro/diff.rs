@@ -0,0 +1,283 @@
+//! What happened between two snapshots: processes that started or exited, changed noticeably, and
+//! used the most CPU. Processes are matched by pid and start time, so a reused pid shows up as one
+//! process exiting and another starting.
+
+use super::{record, ProcessInfo, Snapshot};
+use humansize::{format_size, BINARY};
+use libc::pid_t;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+/// How much a process has to change to be reported.
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+  /// Change of the resident set size in kB.
+  pub memory_kb: u64,
+  pub thread_count: u64,
+  pub priority: i32,
+  /// Report every state change. Otherwise going back and forth between running, sleeping and idle
+  /// is ignored, which is what most processes do all day.
+  pub all_states: bool,
+}
+
+impl Default for Thresholds {
+  fn default() -> Self {
+    Thresholds {
+      memory_kb: 10 * 1024,
+      thread_count: 1,
+      priority: 1,
+      all_states: false,
+    }
+  }
+}
+
+/// A process that changed beyond the thresholds, with the before and after of each such field.
+#[derive(Debug, Serialize)]
+pub struct Changed {
+  pub pid: pid_t,
+  pub name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub memory_kb: Option<(u64, u64)>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub thread_count: Option<(u64, u64)>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub priority: Option<(i32, i32)>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub state: Option<(char, char)>,
+}
+
+/// CPU used during the interval, for processes that ran in both snapshots or started in between.
+#[derive(Debug, Serialize)]
+pub struct CpuUsage {
+  pub pid: pid_t,
+  pub name: String,
+  pub cpu_seconds: f64,
+  /// Relative to one core, like `ProcessInfo::cpu_percent`.
+  pub cpu_percent: f64,
+}
+
+#[derive(Serialize)]
+pub struct Diff {
+  pub from: f64,
+  pub to: f64,
+  pub started: Vec<ProcessInfo>,
+  pub exited: Vec<ProcessInfo>,
+  pub changed: Vec<Changed>,
+  /// Busiest first.
+  pub top_cpu: Vec<CpuUsage>,
+}
+
+fn changed<T: PartialEq + Copy>(before: T, after: T, beyond: bool) -> Option<(T, T)> {
+  (before != after && beyond).then_some((before, after))
+}
+
+// Running, sleeping and idle
+fn is_routine(state: char) -> bool {
+  matches!(state, 'R' | 'S' | 'I')
+}
+
+/// Compares `before` with `after`, keeping the `top` processes by CPU usage.
+pub fn diff(before: &Snapshot, after: &Snapshot, thresholds: &Thresholds, top: usize) -> Diff {
+  let key = |p: &ProcessInfo| (p.pid, p.start_time);
+  let old: HashMap<_, &ProcessInfo> = before.processes.iter().map(|p| (key(p), p)).collect();
+  let new: HashMap<_, &ProcessInfo> = after.processes.iter().map(|p| (key(p), p)).collect();
+
+  let mut started = Vec::new();
+  let mut changes = Vec::new();
+  let mut top_cpu = Vec::new();
+  let interval = after.timestamp - before.timestamp;
+  let ticks = after.clock_ticks_per_second.max(1) as f64;
+  for process in &after.processes {
+    let previous = old.get(&key(process));
+    let used = process.user_time + process.system_time;
+    let cpu_seconds = match previous {
+      Some(p) => used.saturating_sub(p.user_time + p.system_time),
+      None => used,
+    } as f64
+      / ticks;
+    if cpu_seconds > 0.0 {
+      top_cpu.push(CpuUsage {
+        pid: process.pid,
+        name: process.name.clone(),
+        cpu_seconds,
+        cpu_percent: if interval > 0.0 {
+          cpu_seconds / interval * 100.0
+        } else {
+          0.0
+        },
+      });
+    }
+
+    let Some(previous) = previous else {
+      started.push(process.clone());
+      continue;
+    };
+    let change = Changed {
+      pid: process.pid,
+      name: process.name.clone(),
+      memory_kb: changed(
+        previous.memory,
+        process.memory,
+        previous.memory.abs_diff(process.memory) >= thresholds.memory_kb,
+      ),
+      thread_count: changed(
+        previous.thread_count,
+        process.thread_count,
+        previous.thread_count.abs_diff(process.thread_count) >= thresholds.thread_count,
+      ),
+      priority: changed(
+        previous.priority,
+        process.priority,
+        previous.priority.abs_diff(process.priority) >= thresholds.priority.unsigned_abs(),
+      ),
+      state: changed(
+        previous.state,
+        process.state,
+        thresholds.all_states || !is_routine(previous.state) || !is_routine(process.state),
+      ),
+    };
+    if change.memory_kb.is_some()
+      || change.thread_count.is_some()
+      || change.priority.is_some()
+      || change.state.is_some()
+    {
+      changes.push(change);
+    }
+  }
+
+  let mut exited: Vec<ProcessInfo> = before
+    .processes
+    .iter()
+    .filter(|p| !new.contains_key(&key(p)))
+    .cloned()
+    .collect();
+  started.sort_by_key(|p| p.pid);
+  exited.sort_by_key(|p| p.pid);
+  changes.sort_by_key(|c| c.pid);
+  top_cpu.sort_by(|a, b| b.cpu_seconds.total_cmp(&a.cpu_seconds).then(a.pid.cmp(&b.pid)));
+  top_cpu.truncate(top);
+
+  Diff {
+    from: before.timestamp,
+    to: after.timestamp,
+    started,
+    exited,
+    changed: changes,
+    top_cpu,
+  }
+}
+
+fn format_kb(kb: u64) -> String {
+  format_size(kb * 1024, BINARY)
+}
+
+impl Display for Diff {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    writeln!(
+      f,
+      "From {} to {} ({:.1}s)",
+      record::format_timestamp(self.from),
+      record::format_timestamp(self.to),
+      self.to - self.from
+    )?;
+    for (title, processes) in [("Started", &self.started), ("Exited", &self.exited)] {
+      writeln!(f, "\n{} ({}):", title, processes.len())?;
+      for p in processes {
+        writeln!(f, "  {:<7} {:<16} {:<10} {}", p.pid, p.name, p.user, p.command)?;
+      }
+    }
+
+    writeln!(f, "\nChanged ({}):", self.changed.len())?;
+    for change in &self.changed {
+      let mut fields = Vec::new();
+      if let Some((before, after)) = change.memory_kb {
+        let sign = if after > before { '+' } else { '-' };
+        fields.push(format!(
+          "memory {} -> {} ({}{})",
+          format_kb(before),
+          format_kb(after),
+          sign,
+          format_kb(before.abs_diff(after))
+        ));
+      }
+      if let Some((before, after)) = change.thread_count {
+        fields.push(format!("threads {} -> {}", before, after));
+      }
+      if let Some((before, after)) = change.priority {
+        fields.push(format!("priority {} -> {}", before, after));
+      }
+      if let Some((before, after)) = change.state {
+        fields.push(format!("state {} -> {}", before, after));
+      }
+      writeln!(f, "  {:<7} {:<16} {}", change.pid, change.name, fields.join(", "))?;
+    }
+
+    writeln!(f, "\nTop CPU:")?;
+    for usage in &self.top_cpu {
+      writeln!(
+        f,
+        "  {:<7} {:<16} {:>6.1}% {:>8.2}s",
+        usage.pid, usage.name, usage.cpu_percent, usage.cpu_seconds
+      )?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::fixture_snapshot as snapshot;
+  use super::*;
+
+  #[test]
+  fn reports_started_exited_and_changed() {
+    let before = snapshot(0.0);
+    let mut after = snapshot(10.0);
+    after.processes.retain(|p| p.pid != 200);
+    let java = after.processes.iter_mut().find(|p| p.pid == 42).unwrap();
+    java.memory += 20 * 1024;
+    java.thread_count += 3;
+    java.user_time += 500;
+    java.state = 'D';
+    let mut new = java.clone();
+    new.pid = 300;
+    new.system_time = 0;
+    new.user_time = 100;
+    after.processes.push(new);
+    // the pid was reused
+    let init = after.processes.iter_mut().find(|p| p.pid == 1).unwrap();
+    init.start_time += 1;
+
+    let diff = diff(&before, &after, &Thresholds::default(), 2);
+    let pids = |processes: &[ProcessInfo]| processes.iter().map(|p| p.pid).collect::<Vec<_>>();
+    assert_eq!(pids(&diff.started), vec![1, 300]);
+    assert_eq!(pids(&diff.exited), vec![1, 200]);
+    assert_eq!(diff.changed.len(), 1);
+    let change = &diff.changed[0];
+    assert_eq!(change.pid, 42);
+    assert_eq!(change.thread_count.map(|(b, a)| a - b), Some(3));
+    assert_eq!(change.state.map(|(_, a)| a), Some('D'));
+    assert!(change.memory_kb.is_some() && change.priority.is_none());
+    let top: Vec<_> = diff.top_cpu.iter().map(|u| (u.pid, u.cpu_percent)).collect();
+    assert_eq!(top[0], (42, 50.0));
+    assert_eq!(top.len(), 2);
+  }
+
+  #[test]
+  fn small_changes_stay_below_thresholds() {
+    let before = snapshot(0.0);
+    let mut after = snapshot(1.0);
+    let java = after.processes.iter_mut().find(|p| p.pid == 42).unwrap();
+    java.memory += 1024;
+    java.state = if java.state == 'R' { 'S' } else { 'R' };
+
+    assert!(diff(&before, &after, &Thresholds::default(), 5).changed.is_empty());
+    let thresholds = Thresholds {
+      memory_kb: 1024,
+      all_states: true,
+      ..Thresholds::default()
+    };
+    assert_eq!(diff(&before, &after, &thresholds, 5).changed.len(), 1);
+  }
+}
@@ -0,0 +1,284 @@
+//! Everything we collect in the OpenMetrics text format, for Prometheus to scrape. Counters are
+//! exported as the kernel reports them, only converted to seconds and bytes, and left to PromQL's
+//! `rate()`.
+
+use super::{
+  clock_ticks_per_second, get_cpu_times, get_disk_stats, get_network_stats, read_processes, DiskStats, Filter,
+  NetworkStats, ProcSource, ProcessInfo, Result, SystemSummary, CPU_MODES,
+};
+use std::fmt::{Display, Write};
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+const PREFIX: &str = "procstat_";
+
+struct Writer {
+  text: String,
+}
+
+impl Writer {
+  // `name` without the prefix, and without `_total` for counters
+  fn family(&mut self, name: &str, kind: &str, unit: &str, help: &str) {
+    let _ = writeln!(self.text, "# TYPE {}{} {}", PREFIX, name, kind);
+    if !unit.is_empty() {
+      let _ = writeln!(self.text, "# UNIT {}{} {}", PREFIX, name, unit);
+    }
+    let _ = writeln!(self.text, "# HELP {}{} {}", PREFIX, name, help);
+  }
+
+  fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+    let _ = write!(self.text, "{}{}", PREFIX, name);
+    if !labels.is_empty() {
+      let labels: Vec<String> = labels
+        .iter()
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+        .collect();
+      let _ = write!(self.text, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(self.text, " {}", value);
+  }
+
+  fn gauge(&mut self, name: &str, unit: &str, help: &str, value: impl Display) {
+    self.family(name, "gauge", unit, help);
+    self.sample(name, &[], value);
+  }
+}
+
+// Name, unit, help and value of a metric of each `T`
+type Metric<T, V> = (&'static str, &'static str, &'static str, fn(&T) -> V);
+
+fn escape(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Renders the system metrics, and those of the processes matching `processes` if given.
+pub fn render(source: &ProcSource, processes: Option<&Filter>) -> Result<String> {
+  let mut w = Writer { text: String::new() };
+  let ticks = clock_ticks_per_second() as f64;
+
+  let system = SystemSummary::read(source)?;
+  w.gauge(
+    "memory_total_bytes",
+    "bytes",
+    "Total usable RAM.",
+    system.total_ram_bytes,
+  );
+  w.gauge("memory_free_bytes", "bytes", "Unused RAM.", system.free_ram_bytes);
+  w.gauge(
+    "memory_shared_bytes",
+    "bytes",
+    "Shared memory.",
+    system.shared_ram_bytes,
+  );
+  w.gauge(
+    "memory_buffer_bytes",
+    "bytes",
+    "Memory used by buffers.",
+    system.buffer_ram_bytes,
+  );
+  w.gauge(
+    "swap_total_bytes",
+    "bytes",
+    "Total swap space.",
+    system.total_swap_bytes,
+  );
+  w.gauge("swap_free_bytes", "bytes", "Unused swap space.", system.free_swap_bytes);
+  w.gauge(
+    "uptime_seconds",
+    "seconds",
+    "Seconds since boot.",
+    system.uptime_seconds,
+  );
+  w.family("load_average", "gauge", "", "Load average over the period.");
+  for (period, load) in ["1m", "5m", "15m"].iter().zip(system.load_average) {
+    w.sample("load_average", &[("period", period)], load);
+  }
+
+  w.family("cpu_seconds", "counter", "seconds", "Time each cpu spent in each mode.");
+  for times in get_cpu_times(source)? {
+    // the sum of all cpus is left to the query
+    if times.cpu == "cpu" {
+      continue;
+    }
+    for (mode, ticks_in_mode) in CPU_MODES.iter().zip(times.ticks) {
+      w.sample(
+        "cpu_seconds_total",
+        &[("cpu", &times.cpu), ("mode", mode)],
+        ticks_in_mode as f64 / ticks,
+      );
+    }
+  }
+
+  let disks = get_disk_stats(source)?;
+  let disk_counters: [Metric<DiskStats, f64>; 9] = [
+    ("disk_reads_completed", "", "Reads completed.", |d| {
+      d.reads_completed as f64
+    }),
+    ("disk_reads_merged", "", "Adjacent reads merged.", |d| {
+      d.reads_merged as f64
+    }),
+    ("disk_read_bytes", "bytes", "Bytes read.", |d| {
+      (d.sectors_read * 512) as f64
+    }),
+    ("disk_read_time_seconds", "seconds", "Time spent reading.", |d| {
+      d.time_reading as f64 / 1000.0
+    }),
+    ("disk_writes_completed", "", "Writes completed.", |d| {
+      d.writes_completed as f64
+    }),
+    ("disk_writes_merged", "", "Adjacent writes merged.", |d| {
+      d.writes_merged as f64
+    }),
+    ("disk_written_bytes", "bytes", "Bytes written.", |d| {
+      (d.sectors_written * 512) as f64
+    }),
+    ("disk_write_time_seconds", "seconds", "Time spent writing.", |d| {
+      d.time_writing as f64 / 1000.0
+    }),
+    ("disk_io_time_seconds", "seconds", "Time spent doing I/O.", |d| {
+      d.time_io as f64 / 1000.0
+    }),
+  ];
+  for (name, unit, help, value) in disk_counters {
+    w.family(name, "counter", unit, help);
+    for disk in &disks {
+      w.sample(&format!("{}_total", name), &[("device", &disk.device)], value(disk));
+    }
+  }
+  w.family(
+    "disk_io_weighted_time_seconds",
+    "counter",
+    "seconds",
+    "Time spent doing I/O, weighted by the queue length.",
+  );
+  for disk in &disks {
+    w.sample(
+      "disk_io_weighted_time_seconds_total",
+      &[("device", &disk.device)],
+      disk.weighted_time_io as f64 / 1000.0,
+    );
+  }
+  w.family("disk_io_in_progress", "gauge", "", "I/O requests in flight.");
+  for disk in &disks {
+    w.sample("disk_io_in_progress", &[("device", &disk.device)], disk.io_in_progress);
+  }
+
+  let networks = get_network_stats(source)?;
+  let network_counters: [Metric<NetworkStats, u64>; 8] = [
+    ("network_receive_bytes", "bytes", "Bytes received.", |n| {
+      n.bytes_received
+    }),
+    ("network_receive_packets", "", "Packets received.", |n| {
+      n.packets_received
+    }),
+    ("network_receive_errors", "", "Receive errors.", |n| n.errors_received),
+    ("network_receive_drops", "", "Received packets dropped.", |n| {
+      n.drops_received
+    }),
+    ("network_transmit_bytes", "bytes", "Bytes transmitted.", |n| {
+      n.bytes_transmitted
+    }),
+    ("network_transmit_packets", "", "Packets transmitted.", |n| {
+      n.packets_transmitted
+    }),
+    ("network_transmit_errors", "", "Transmit errors.", |n| {
+      n.errors_transmitted
+    }),
+    ("network_transmit_drops", "", "Transmitted packets dropped.", |n| {
+      n.drops_transmitted
+    }),
+  ];
+  for (name, unit, help, value) in network_counters {
+    w.family(name, "counter", unit, help);
+    for network in &networks {
+      w.sample(
+        &format!("{}_total", name),
+        &[("interface", &network.interface)],
+        value(network),
+      );
+    }
+  }
+
+  if let Some(filter) = processes {
+    let processes: Vec<_> = read_processes(source)?
+      .into_iter()
+      .filter(|p| filter.matches(p))
+      .collect();
+    w.family(
+      "process_cpu_seconds",
+      "counter",
+      "seconds",
+      "Time the process spent in each mode.",
+    );
+    for p in &processes {
+      let pid = p.pid.to_string();
+      for (mode, used) in [("user", p.user_time), ("system", p.system_time)] {
+        w.sample(
+          "process_cpu_seconds_total",
+          &[("pid", &pid), ("name", &p.name), ("mode", mode)],
+          used as f64 / ticks,
+        );
+      }
+    }
+    let gauges: [Metric<ProcessInfo, i64>; 4] = [
+      ("process_resident_memory_bytes", "bytes", "Resident set size.", |p| {
+        p.memory as i64 * 1024
+      }),
+      ("process_virtual_memory_bytes", "bytes", "Virtual memory size.", |p| {
+        p.virtual_memory as i64 * 1024
+      }),
+      ("process_threads", "", "Number of threads.", |p| p.thread_count as i64),
+      ("process_priority", "", "Scheduling priority.", |p| p.priority as i64),
+    ];
+    for (name, unit, help, value) in gauges {
+      w.family(name, "gauge", unit, help);
+      for p in &processes {
+        w.sample(name, &[("pid", &p.pid.to_string()), ("name", &p.name)], value(p));
+      }
+    }
+  }
+
+  w.text.push_str("# EOF\n");
+  Ok(w.text)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::fixture;
+  use super::*;
+
+  #[test]
+  fn exports_raw_counters() {
+    let text = render(&fixture("basic"), None).unwrap();
+    let ticks = clock_ticks_per_second() as f64;
+    assert!(text.contains("# TYPE procstat_cpu_seconds counter\n# UNIT procstat_cpu_seconds seconds\n"));
+    assert!(text.contains(&format!(
+      "procstat_cpu_seconds_total{{cpu=\"cpu1\",mode=\"user\"}} {}\n",
+      5000.0 / ticks
+    )));
+    assert!(!text.contains("cpu=\"cpu\""));
+    // the gauges are of the fixture too, not of the machine running the test
+    assert!(text.contains("procstat_memory_total_bytes 8192000000\n"));
+    assert!(text.contains("procstat_uptime_seconds 90061\n"));
+    assert!(text.contains("procstat_load_average{period=\"15m\"} 0.3\n"));
+    assert!(text.contains("procstat_disk_read_bytes_total{device=\"sda\"} 452818944\n"));
+    assert!(text.contains("procstat_disk_io_in_progress{device=\"nvme0n1\"} 2\n"));
+    assert!(text.contains("procstat_network_receive_bytes_total{interface=\"eth0\"} 98765432\n"));
+    assert!(!text.contains("process_"));
+    assert!(text.ends_with("\n# EOF\n"));
+  }
+
+  #[test]
+  fn exports_matching_processes() {
+    let filter = Filter::new("name", "java", true).unwrap();
+    let text = render(&fixture("basic"), Some(&filter)).unwrap();
+    assert!(text.contains("procstat_process_resident_memory_bytes{pid=\"42\",name=\"java\"} 831488000\n"));
+    assert!(text.contains("procstat_process_threads{pid=\"42\",name=\"java\"} "));
+    assert!(!text.contains("pid=\"1\""));
+  }
+
+  #[test]
+  fn escapes_label_values() {
+    assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+  }
+}
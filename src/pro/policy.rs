@@ -0,0 +1,458 @@
+//! Watchdog policies that act on processes on their own, e.g. in a policies file:
+//!
+//! ```toml
+//! [[policy]]
+//! name = "tame batch jobs"
+//! where = 'name~"^batch" and cpu>80'
+//! for = "5m"
+//! action = "renice"
+//! priority = 10
+//!
+//! [[policy]]
+//! name = "leaks"
+//! where = "name=leaky and mem>2G"
+//! action = "kill"
+//! signal = "TERM"
+//! escalate = "KILL"
+//! grace = "30s"
+//!
+//! [[policy]]
+//! name = "pin postgres"
+//! where = "name=postgres"
+//! action = "affinity"
+//! cpus = [2, 3]
+//! ```
+//!
+//! A policy acts once on every process that matched `where` for `for` (right away without it). A
+//! kill with `escalate` sends that signal too if the process is still around after `grace`. Every
+//! action, or what would have been done in a dry run, goes to the audit log.
+
+use super::query::{parse_duration, Query};
+use super::{
+  bind_to_cpu_set, check_cpus, filter_processes, parse_signal, record, set_priority, signal_name, toml_from_str, Error,
+  Filter, OutputMessage, ProcessHandle, ProcessInfo, Result,
+};
+use libc::pid_t;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+  Renice(i32),
+  /// The signal, and the one to escalate to after the grace period in seconds.
+  Kill(i32, Option<(i32, f64)>),
+  Affinity(Vec<usize>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+  pub name: String,
+  pub query: Query,
+  /// Seconds a process has to match before acting on it.
+  pub duration: f64,
+  pub action: Action,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Value {
+  Number(f64),
+  Text(String),
+}
+
+impl Value {
+  fn seconds(&self) -> std::result::Result<f64, String> {
+    match self {
+      Value::Number(seconds) => Ok(*seconds),
+      Value::Text(text) => parse_duration(text.trim()).ok_or(format!("invalid duration '{}'", text)),
+    }
+  }
+
+  fn signal(&self) -> std::result::Result<i32, String> {
+    let signal = match self {
+      Value::Number(number) => parse_signal(&number.to_string()),
+      Value::Text(text) => parse_signal(text),
+    };
+    signal.map_err(|e| e.to_string())
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPolicy {
+  name: String,
+  #[serde(rename = "where")]
+  query: String,
+  #[serde(rename = "for")]
+  duration: Option<Value>,
+  action: String,
+  priority: Option<i32>,
+  signal: Option<Value>,
+  escalate: Option<Value>,
+  grace: Option<Value>,
+  cpus: Option<Vec<usize>>,
+}
+
+/// The `[[policy]]` tables of a policies file.
+#[derive(Deserialize, Default)]
+pub struct Policies {
+  #[serde(default, rename = "policy")]
+  raw: Vec<RawPolicy>,
+}
+
+impl TryFrom<RawPolicy> for Policy {
+  type Error = String;
+
+  fn try_from(raw: RawPolicy) -> std::result::Result<Self, String> {
+    let unexpected = |option: &str, present: bool| match present {
+      true => Err(format!("'{}' doesn't apply to action '{}'", option, raw.action)),
+      false => Ok(()),
+    };
+    let action = match raw.action.as_str() {
+      "renice" => {
+        unexpected("signal", raw.signal.is_some())?;
+        unexpected("escalate", raw.escalate.is_some())?;
+        unexpected("grace", raw.grace.is_some())?;
+        unexpected("cpus", raw.cpus.is_some())?;
+        Action::Renice(raw.priority.ok_or("renice needs 'priority'")?)
+      }
+      "kill" => {
+        unexpected("priority", raw.priority.is_some())?;
+        unexpected("cpus", raw.cpus.is_some())?;
+        let signal = match &raw.signal {
+          Some(signal) => signal.signal()?,
+          None => libc::SIGTERM,
+        };
+        let escalate = match (&raw.escalate, &raw.grace) {
+          (Some(escalate), grace) => Some((escalate.signal()?, grace.as_ref().map_or(Ok(10.0), Value::seconds)?)),
+          (None, Some(_)) => return Err("'grace' needs 'escalate'".to_string()),
+          (None, None) => None,
+        };
+        Action::Kill(signal, escalate)
+      }
+      "affinity" => {
+        unexpected("priority", raw.priority.is_some())?;
+        unexpected("signal", raw.signal.is_some())?;
+        unexpected("escalate", raw.escalate.is_some())?;
+        unexpected("grace", raw.grace.is_some())?;
+        let cpus = raw
+          .cpus
+          .clone()
+          .filter(|cpus| !cpus.is_empty())
+          .ok_or("affinity needs 'cpus'")?;
+        check_cpus(&cpus).map_err(|e| e.to_string())?;
+        Action::Affinity(cpus)
+      }
+      action => return Err(format!("unknown action '{}'", action)),
+    };
+    Ok(Policy {
+      name: raw.name,
+      query: raw.query.parse().map_err(|e: Error| e.to_string())?,
+      duration: raw.duration.as_ref().map_or(Ok(0.0), Value::seconds)?,
+      action,
+    })
+  }
+}
+
+impl Policies {
+  /// Validates the policies, `path` is where they were read from, for errors.
+  pub fn into_policies(self, path: &Path) -> Result<Vec<Policy>> {
+    self
+      .raw
+      .into_iter()
+      .map(|raw| {
+        let name = raw.name.clone();
+        Policy::try_from(raw).map_err(|e| Error::parse(path, None, format!("policy '{}': {}", name, e)))
+      })
+      .collect()
+  }
+}
+
+/// Reads the `[[policy]]` tables of the TOML file at `path`.
+pub fn load_policies(path: &Path) -> Result<Vec<Policy>> {
+  let text = fs::read_to_string(path).map_err(|e| Error::from_read(e, path, None))?;
+  toml_from_str::<Policies>(&text, path)?.into_policies(path)
+}
+
+/// One action taken, or that would have been in a dry run.
+#[derive(Debug, Clone)]
+pub struct Record {
+  pub timestamp: f64,
+  pub policy: String,
+  pub pid: pid_t,
+  pub name: String,
+  pub action: String,
+  pub dry_run: bool,
+  /// Why the action failed.
+  pub error: Option<String>,
+}
+
+impl std::fmt::Display for Record {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(
+      f,
+      "{} {}policy '{}': {} ({}): {}",
+      record::format_timestamp(self.timestamp),
+      if self.dry_run { "[dry run] " } else { "" },
+      self.policy,
+      self.pid,
+      self.name,
+      self.action,
+    )?;
+    match &self.error {
+      Some(error) => write!(f, ": failed, {}", error),
+      None => Ok(()),
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+struct Tracked {
+  // when the process started to match
+  since: f64,
+  // when we acted on it
+  acted: Option<f64>,
+  escalated: bool,
+}
+
+/// `/var/log/procstat-audit.log` for root, otherwise `$XDG_STATE_HOME/procstat/audit.log` falling back
+/// to `~/.local/state`, so other users can't plant the file.
+pub fn default_audit_log() -> PathBuf {
+  if unsafe { libc::geteuid() } == 0 {
+    return PathBuf::from("/var/log/procstat-audit.log");
+  }
+  let dir = match std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+    Some(dir) => PathBuf::from(dir),
+    None => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".local/state"),
+  };
+  dir.join("procstat").join("audit.log")
+}
+
+/// Applies policies to consecutive process lists.
+pub struct Watchdog {
+  policies: Vec<Policy>,
+  // per policy, by pid and start time so a reused pid starts over
+  tracked: Vec<HashMap<(pid_t, u64), Tracked>>,
+  dry_run: bool,
+  audit_log: Option<PathBuf>,
+}
+
+impl Watchdog {
+  pub fn new(policies: Vec<Policy>, dry_run: bool, audit_log: Option<PathBuf>) -> Self {
+    let tracked = policies.iter().map(|_| HashMap::new()).collect();
+    Watchdog {
+      policies,
+      tracked,
+      dry_run,
+      audit_log,
+    }
+  }
+
+  /// Acts on `processes` as the policies say, returning what was done. Fails only if the audit
+  /// log can't be written.
+  pub fn update(&mut self, processes: &[ProcessInfo], timestamp: f64) -> Result<Vec<Record>> {
+    let own_pid = std::process::id() as pid_t;
+    let mut records = Vec::new();
+    for (policy, tracked) in self.policies.iter().zip(&mut self.tracked) {
+      let key = |p: &ProcessInfo| (p.pid, p.start_time);
+      let present: HashMap<_, &ProcessInfo> = processes.iter().map(|p| (key(p), p)).collect();
+      let matching = filter_processes(processes.to_vec(), &Filter::with_query(policy.query.clone()));
+
+      // a process that stopped matching starts over, unless we are waiting to escalate
+      tracked.retain(|k, t| present.contains_key(k) && (t.acted.is_some() || matching.iter().any(|p| key(p) == *k)));
+      for process in matching.iter().filter(|p| p.pid != own_pid) {
+        let entry = tracked.entry(key(process)).or_insert(Tracked {
+          since: timestamp,
+          acted: None,
+          escalated: false,
+        });
+        if entry.acted.is_none() && timestamp - entry.since >= policy.duration {
+          entry.acted = Some(timestamp);
+          records.push(act(policy, &policy.action, process, timestamp, self.dry_run));
+        }
+      }
+
+      if let Action::Kill(_, Some((signal, grace))) = policy.action {
+        let escalation = Action::Kill(signal, None);
+        for (k, t) in tracked.iter_mut() {
+          let due = t.acted.is_some_and(|acted| timestamp - acted >= grace);
+          if due && !t.escalated {
+            t.escalated = true;
+            records.push(act(policy, &escalation, present[k], timestamp, self.dry_run));
+          }
+        }
+      }
+    }
+
+    if let Some(path) = &self.audit_log {
+      if !records.is_empty() {
+        // the watchdog often runs as root, so a symlink planted at the path must not redirect it
+        let mut file = fs::OpenOptions::new()
+          .create(true)
+          .append(true)
+          .custom_flags(libc::O_NOFOLLOW)
+          .open(path)
+          .map_err(|e| Error::from_read(e, path, None))?;
+        for record in &records {
+          writeln!(file, "{}", record)?;
+        }
+      }
+    }
+    Ok(records)
+  }
+}
+
+// Acts through a handle on the process that matched, so a process that took over its pid since is
+// left alone: signals go to the handle, and the other actions check that it still runs afterwards
+fn act(policy: &Policy, action: &Action, process: &ProcessInfo, timestamp: f64, dry_run: bool) -> Record {
+  let pid = process.pid;
+  let (description, result) = match action {
+    Action::Renice(priority) => (
+      format!("renice to {}", priority),
+      (!dry_run).then(|| {
+        run(process, |handle, tx| {
+          set_priority(pid, *priority, Some(tx))?;
+          handle.check_running()
+        })
+      }),
+    ),
+    Action::Kill(signal, _) => (
+      format!("send {}", signal_name(*signal)),
+      (!dry_run).then(|| run(process, |handle, tx| handle.send_signal(*signal, Some(tx)))),
+    ),
+    Action::Affinity(cpus) => (
+      format!("pin to cpus {:?}", cpus),
+      (!dry_run).then(|| {
+        run(process, |handle, tx| {
+          bind_to_cpu_set(pid, cpus, Some(tx))?;
+          handle.check_running()
+        })
+      }),
+    ),
+  };
+  Record {
+    timestamp,
+    policy: policy.name.clone(),
+    pid,
+    name: process.name.clone(),
+    action: description,
+    dry_run,
+    error: result.and_then(|r| r.err()).map(|e| e.to_string()),
+  }
+}
+
+// The actions report through a channel, so they don't print anything themselves
+fn run(
+  process: &ProcessInfo,
+  action: impl FnOnce(&ProcessHandle, &mpsc::Sender<OutputMessage>) -> Result<()>,
+) -> Result<()> {
+  let handle = ProcessHandle::open_process(process)?;
+  let (tx, _rx) = mpsc::channel();
+  action(&handle, &tx)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::{fixture, read_processes};
+  use super::*;
+
+  fn policies(text: &str) -> Result<Vec<Policy>> {
+    toml_from_str::<Policies>(text, Path::new("policies.toml"))?.into_policies(Path::new("policies.toml"))
+  }
+
+  fn processes() -> Vec<ProcessInfo> {
+    read_processes(&fixture("basic")).unwrap()
+  }
+
+  #[test]
+  fn acts_once_after_duration() {
+    let text = "[[policy]]\nname = \"tame\"\nwhere = \"user=app\"\nfor = \"5m\"\naction = \"renice\"\npriority = 10";
+    let mut watchdog = Watchdog::new(policies(text).unwrap(), true, None);
+    let processes = processes();
+    assert!(watchdog.update(&processes, 0.0).unwrap().is_empty());
+    assert!(watchdog.update(&processes, 200.0).unwrap().is_empty());
+    let records = watchdog.update(&processes, 300.0).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].pid, records[0].action.as_str()), (42, "renice to 10"));
+    assert!(records[0].dry_run && records[0].error.is_none());
+    assert!(records[0]
+      .to_string()
+      .ends_with("[dry run] policy 'tame': 42 (java): renice to 10"));
+    assert!(watchdog.update(&processes, 400.0).unwrap().is_empty());
+
+    // not matching for a while starts the duration over
+    let others: Vec<_> = processes.iter().filter(|p| p.pid != 42).cloned().collect();
+    watchdog.update(&others, 500.0).unwrap();
+    assert!(watchdog.update(&processes, 600.0).unwrap().is_empty());
+    assert_eq!(watchdog.update(&processes, 900.0).unwrap().len(), 1);
+  }
+
+  #[test]
+  fn escalates_kills_after_grace() {
+    let text = r#"
+      [[policy]]
+      name = "leaks"
+      where = "name=java and mem>500M"
+      action = "kill"
+      escalate = "KILL"
+      grace = "30s"
+    "#;
+    let dir = std::env::temp_dir().join(format!("procstat-audit-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let audit = dir.join("audit.log");
+    let mut watchdog = Watchdog::new(policies(text).unwrap(), true, Some(audit.clone()));
+    let mut processes = processes();
+
+    let records = watchdog.update(&processes, 0.0).unwrap();
+    assert_eq!(
+      records.iter().map(|r| r.action.as_str()).collect::<Vec<_>>(),
+      vec!["send SIGTERM"]
+    );
+    // shrinking doesn't save it from the escalation
+    processes.iter_mut().find(|p| p.pid == 42).unwrap().memory = 1000;
+    assert!(watchdog.update(&processes, 10.0).unwrap().is_empty());
+    let records = watchdog.update(&processes, 30.0).unwrap();
+    assert_eq!(
+      records.iter().map(|r| r.action.as_str()).collect::<Vec<_>>(),
+      vec!["send SIGKILL"]
+    );
+    assert!(watchdog.update(&processes, 60.0).unwrap().is_empty());
+
+    let log = fs::read_to_string(&audit).unwrap();
+    assert_eq!(log.lines().count(), 2);
+    assert!(log.lines().all(|line| line.contains("[dry run] policy 'leaks'")));
+
+    // a symlink in place of the log is refused rather than followed
+    let link = dir.join("link.log");
+    std::os::unix::fs::symlink(&audit, &link).unwrap();
+    let mut watchdog = Watchdog::new(policies(text).unwrap(), true, Some(link));
+    assert!(watchdog.update(&self::processes(), 0.0).is_err());
+    assert_eq!(fs::read_to_string(&audit).unwrap(), log);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn rejects_bad_policies() {
+    let error = |text: &str| policies(text).unwrap_err().to_string();
+    assert!(error("[[policy]]\nname = \"x\"\nwhere = \"cpu>1\"\naction = \"renice\"").contains("needs 'priority'"));
+    assert!(error("[[policy]]\nname = \"x\"\naction = \"kill\"").contains("missing field `where`"));
+    assert!(
+      error("[[policy]]\nname = \"x\"\nwhere = \"cpu>1\"\naction = \"kill\"\nsignal = \"NOPE\"")
+        .contains("unknown signal")
+    );
+    assert!(
+      error("[[policy]]\nname = \"x\"\nwhere = \"cpu>1\"\naction = \"renice\"\npriority = 1\ncpus = [1]")
+        .contains("'cpus' doesn't apply")
+    );
+    assert!(
+      error("[[policy]]\nname = \"x\"\nwhere = \"cpu>1\"\naction = \"affinity\"\ncpus = [1, 5000]")
+        .contains("invalid CPU '5000'")
+    );
+    assert!(error("[[policy]]\nname = \"x\"\nwhere = \"cpu>1\"\naction = \"stop\"").contains("unknown action 'stop'"));
+  }
+}
@@ -0,0 +1,506 @@
+//! A small filter language over process fields, e.g.
+//! `user=postgres and mem>500M and (state=R or threads>=32) and name~"^java"`.
+//!
+//! Comparisons are `field op value` with `=`, `!=`, `<`, `<=`, `>`, `>=`, `~` (regex match) and `!~`.
+//! They combine with `and`, `or`, `not` and parentheses. Values of size fields (`memory`, `vmsize`, and
+//! the rates `io_read`, `io_write`) are bytes and take `K`, `M`, `G` or `T` suffixes (powers of 1024),
+//! time fields (`utime`, `stime`) are seconds and take `ms`, `s`, `m`, `h` or `d`, `cpu` may end in
+//! `%`, and `start` is seconds since the Unix epoch.
+
+use super::{clock_ticks_per_second, Error, Field, ProcessInfo, Result};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Match,
+  NotMatch,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+  Text(String),
+  Number(f64),
+  Regex(Regex),
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+  Compare { field: Field, op: Op, value: Value },
+  Not(Box<Expr>),
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+}
+
+/// A parsed filter expression.
+#[derive(Debug, Clone)]
+pub struct Query {
+  source: String,
+  expr: Expr,
+}
+
+impl Query {
+  pub fn parse(input: &str) -> Result<Query> {
+    let mut parser = Parser { input, pos: 0 };
+    let expr = parser.parse_or(0)?;
+    parser.skip_whitespace();
+    if parser.pos < input.len() {
+      return Err(parser.error("unexpected input"));
+    }
+    Ok(Query {
+      source: input.to_string(),
+      expr,
+    })
+  }
+
+  pub fn expr(&self) -> &Expr {
+    &self.expr
+  }
+
+  pub fn matches(&self, process: &ProcessInfo) -> bool {
+    self.expr.matches(process)
+  }
+}
+
+impl FromStr for Query {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self> {
+    Query::parse(s)
+  }
+}
+
+impl fmt::Display for Query {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.source)
+  }
+}
+
+impl Expr {
+  pub fn matches(&self, process: &ProcessInfo) -> bool {
+    match self {
+      Expr::Compare { field, op, value } => compare(*field, *op, value, process),
+      Expr::Not(expr) => !expr.matches(process),
+      Expr::And(left, right) => left.matches(process) && right.matches(process),
+      Expr::Or(left, right) => left.matches(process) || right.matches(process),
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+  Text,
+  Number,
+  Size,
+  Duration,
+  Percent,
+}
+
+fn kind(field: Field) -> Kind {
+  match field {
+    Field::User | Field::State | Field::Name | Field::Command | Field::Exe | Field::Cwd | Field::Cgroup => Kind::Text,
+    Field::Pid | Field::Ppid | Field::Threads | Field::Priority | Field::StartTime => Kind::Number,
+    Field::Memory | Field::VirtualMemory | Field::IoRead | Field::IoWrite => Kind::Size,
+    Field::UserTime | Field::SystemTime => Kind::Duration,
+    Field::Cpu => Kind::Percent,
+  }
+}
+
+/// The value of a numeric field in the units used by queries: bytes, seconds or percent.
+fn number(field: Field, process: &ProcessInfo) -> Option<f64> {
+  let ticks = clock_ticks_per_second() as f64;
+  Some(match field {
+    Field::Pid => process.pid as f64,
+    Field::Ppid => process.ppid as f64,
+    Field::Threads => process.thread_count as f64,
+    Field::Priority => process.priority as f64,
+    Field::Memory => process.memory as f64 * 1024.0,
+    Field::VirtualMemory => process.virtual_memory as f64 * 1024.0,
+    Field::UserTime => process.user_time as f64 / ticks,
+    Field::SystemTime => process.system_time as f64 / ticks,
+    Field::Cpu => process.cpu_percent,
+    Field::StartTime => process.started as f64,
+    Field::IoRead => process.io_read_rate,
+    Field::IoWrite => process.io_write_rate,
+    Field::User | Field::State | Field::Name | Field::Command | Field::Exe | Field::Cwd | Field::Cgroup => return None,
+  })
+}
+
+fn compare(field: Field, op: Op, value: &Value, process: &ProcessInfo) -> bool {
+  let ordering = match value {
+    Value::Regex(regex) => {
+      let found = regex.is_match(&field.value(process));
+      return if op == Op::NotMatch { !found } else { found };
+    }
+    Value::Number(expected) => match number(field, process) {
+      Some(actual) => actual.total_cmp(expected),
+      None => return false,
+    },
+    Value::Text(expected) => field.value(process).as_str().cmp(expected.as_str()),
+  };
+  match op {
+    Op::Eq => ordering == Ordering::Equal,
+    Op::Ne => ordering != Ordering::Equal,
+    Op::Lt => ordering == Ordering::Less,
+    Op::Le => ordering != Ordering::Greater,
+    Op::Gt => ordering == Ordering::Greater,
+    Op::Ge => ordering != Ordering::Less,
+    Op::Match | Op::NotMatch => unreachable!("regex values are handled above"),
+  }
+}
+
+/// Splits "1.5G" into (1.5, "G").
+fn split_unit(text: &str) -> Option<(f64, &str)> {
+  let end = text
+    .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+    .unwrap_or(text.len());
+  Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+/// Parses sizes like "500M" into bytes, with powers of 1024.
+pub fn parse_size(text: &str) -> Option<f64> {
+  let (number, unit) = split_unit(text)?;
+  let unit = unit.to_ascii_uppercase();
+  let unit = unit.trim_end_matches("IB").trim_end_matches('B');
+  let multiplier = match unit {
+    "" => 1.0,
+    "K" => 1024.0,
+    "M" => 1024.0 * 1024.0,
+    "G" => 1024.0 * 1024.0 * 1024.0,
+    "T" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+    _ => return None,
+  };
+  Some(number * multiplier)
+}
+
+/// Parses durations like "90s" or "5m" into seconds, plain numbers are seconds.
+pub fn parse_duration(text: &str) -> Option<f64> {
+  let (number, unit) = split_unit(text)?;
+  let multiplier = match unit {
+    "ms" => 0.001,
+    "" | "s" => 1.0,
+    "m" => 60.0,
+    "h" => 3600.0,
+    "d" => 86400.0,
+    _ => return None,
+  };
+  Some(number * multiplier)
+}
+
+fn parse_value(field: Field, op: Op, text: &str) -> std::result::Result<Value, String> {
+  if matches!(op, Op::Match | Op::NotMatch) {
+    return Regex::new(text).map(Value::Regex).map_err(|e| e.to_string());
+  }
+  let number = match kind(field) {
+    Kind::Text => return Ok(Value::Text(text.to_string())),
+    Kind::Number => text.parse().ok(),
+    Kind::Size => parse_size(text),
+    Kind::Duration => parse_duration(text),
+    Kind::Percent => text.strip_suffix('%').unwrap_or(text).parse().ok(),
+  };
+  number
+    .map(Value::Number)
+    .ok_or_else(|| format!("invalid value '{}' for {}", text, field))
+}
+
+// Parentheses and nots deeper than this are refused rather than recursed into, a filter can come
+// from any client of the daemon
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+  input: &'a str,
+  pos: usize,
+}
+
+fn nested(depth: usize) -> Result<usize> {
+  if depth >= MAX_DEPTH {
+    return Err(Error::InvalidArgument("filter nested too deeply".to_string()));
+  }
+  Ok(depth + 1)
+}
+
+impl Parser<'_> {
+  fn error(&self, message: &str) -> Error {
+    Error::InvalidArgument(format!(
+      "invalid filter at column {}: {}: '{}'",
+      self.pos + 1,
+      message,
+      self.input
+    ))
+  }
+
+  fn rest(&self) -> &str {
+    &self.input[self.pos..]
+  }
+
+  fn skip_whitespace(&mut self) {
+    let rest = self.rest();
+    self.pos += rest.len() - rest.trim_start().len();
+  }
+
+  /// Consumes `token` if it comes next.
+  fn eat(&mut self, token: &str) -> bool {
+    self.skip_whitespace();
+    if self.rest().starts_with(token) {
+      self.pos += token.len();
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Consumes the keyword `word` if it comes next as a whole word, case-insensitively.
+  fn eat_keyword(&mut self, word: &str) -> bool {
+    self.skip_whitespace();
+    let rest = self.rest();
+    let end = rest
+      .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+      .unwrap_or(rest.len());
+    if rest[..end].eq_ignore_ascii_case(word) {
+      self.pos += end;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn parse_or(&mut self, depth: usize) -> Result<Expr> {
+    let mut left = self.parse_and(depth)?;
+    while self.eat_keyword("or") || self.eat("||") {
+      let right = self.parse_and(depth)?;
+      left = Expr::Or(Box::new(left), Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn parse_and(&mut self, depth: usize) -> Result<Expr> {
+    let mut left = self.parse_not(depth)?;
+    while self.eat_keyword("and") || self.eat("&&") {
+      let right = self.parse_not(depth)?;
+      left = Expr::And(Box::new(left), Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn parse_not(&mut self, depth: usize) -> Result<Expr> {
+    if self.eat_keyword("not") {
+      return Ok(Expr::Not(Box::new(self.parse_not(nested(depth)?)?)));
+    }
+    self.parse_primary(depth)
+  }
+
+  fn parse_primary(&mut self, depth: usize) -> Result<Expr> {
+    if self.eat("(") {
+      let expr = self.parse_or(nested(depth)?)?;
+      if !self.eat(")") {
+        return Err(self.error("expected ')'"));
+      }
+      return Ok(expr);
+    }
+    self.parse_comparison()
+  }
+
+  fn parse_comparison(&mut self) -> Result<Expr> {
+    self.skip_whitespace();
+    let rest = self.rest();
+    let end = rest
+      .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+      .unwrap_or(rest.len());
+    if end == 0 {
+      return Err(self.error("expected a field name"));
+    }
+    let field: Field = rest[..end].to_ascii_lowercase().parse()?;
+    self.pos += end;
+
+    // longest operators first
+    let op = [
+      ("!=", Op::Ne),
+      ("!~", Op::NotMatch),
+      ("<=", Op::Le),
+      (">=", Op::Ge),
+      ("==", Op::Eq),
+      ("=", Op::Eq),
+      ("<", Op::Lt),
+      (">", Op::Gt),
+      ("~", Op::Match),
+    ]
+    .into_iter()
+    .find(|(token, _)| self.eat(token))
+    .map(|(_, op)| op)
+    .ok_or_else(|| self.error("expected an operator"))?;
+
+    let start = self.pos;
+    let text = self.parse_text()?;
+    parse_value(field, op, &text)
+      .map_err(|message| {
+        self.pos = start;
+        self.error(&message)
+      })
+      .map(|value| Expr::Compare { field, op, value })
+  }
+
+  /// A double-quoted string with `\` escapes, or a bare word up to whitespace or a parenthesis.
+  fn parse_text(&mut self) -> Result<String> {
+    self.skip_whitespace();
+    let mut chars = self.rest().char_indices();
+    match chars.next() {
+      Some((_, '"')) => {
+        let mut text = String::new();
+        let mut escaped = false;
+        for (index, c) in chars {
+          if escaped {
+            // keep the backslash unless it escapes a quote, so regex escapes survive
+            if c != '"' {
+              text.push('\\');
+            }
+            text.push(c);
+            escaped = false;
+          } else if c == '\\' {
+            escaped = true;
+          } else if c == '"' {
+            self.pos += index + 1;
+            return Ok(text);
+          } else {
+            text.push(c);
+          }
+        }
+        Err(self.error("unterminated string"))
+      }
+      Some(_) => {
+        let rest = self.rest();
+        let end = rest
+          .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+          .unwrap_or(rest.len());
+        let text = rest[..end].to_string();
+        self.pos += end;
+        Ok(text)
+      }
+      None => Err(self.error("expected a value")),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn process() -> ProcessInfo {
+    ProcessInfo {
+      user: "postgres".to_string(),
+      pid: 100,
+      ppid: 1,
+      name: "java".to_string(),
+      state: 'R',
+      memory: 600 * 1024,
+      thread_count: 40,
+      virtual_memory: 4 * 1024 * 1024,
+      user_time: 90 * clock_ticks_per_second(),
+      system_time: 0,
+      priority: 5,
+      start_time: 0,
+      cpu_percent: 12.5,
+      command: "/usr/bin/java -Xmx2g -jar app.jar".to_string(),
+      exe: "/usr/lib/jvm/bin/java".to_string(),
+      cwd: "/srv/app".to_string(),
+      started: 1_700_000_000,
+      io_read: 0,
+      io_write: 0,
+      io_read_rate: 2.0 * 1024.0 * 1024.0,
+      io_write_rate: 0.0,
+      cgroup: "/system.slice/app.service".to_string(),
+    }
+  }
+
+  fn matches(query: &str) -> bool {
+    Query::parse(query).unwrap().matches(&process())
+  }
+
+  #[test]
+  fn combined_example() {
+    assert!(matches(
+      r#"user=postgres and mem>500M and (state=R or threads>=32) and name~"^java""#
+    ));
+    assert!(!matches(r#"user=postgres and mem>700M"#));
+  }
+
+  #[test]
+  fn comparison_operators() {
+    assert!(matches("pid=100"));
+    assert!(matches("pid==100"));
+    assert!(matches("pid!=101"));
+    assert!(matches("ppid<2 and ppid<=1 and threads>39 and threads>=40"));
+    assert!(!matches("priority<5"));
+    assert!(matches("name!~^py"));
+  }
+
+  #[test]
+  fn units() {
+    assert!(matches("memory>=600M and memory<=600MiB and rss>599.5MB"));
+    assert!(matches("vmsize=4G"));
+    assert!(matches("utime>1m and utime<=90s and utime<2h"));
+    assert!(matches("cpu>10% and cpu<13"));
+  }
+
+  #[test]
+  fn boolean_precedence() {
+    // and binds tighter than or
+    assert!(matches("pid=1 or pid=100 and state=R"));
+    assert!(!matches("(pid=1 or pid=100) and state=S"));
+    assert!(matches("not state=S"));
+    assert!(matches("NOT (user=root || name=bash) && threads>1"));
+  }
+
+  #[test]
+  fn limits_nesting() {
+    let nested = |depth: usize| format!("{}pid=100{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(matches(&nested(MAX_DEPTH)));
+    assert!(matches(&format!("{}pid=100", "not not ".repeat(MAX_DEPTH / 2))));
+    for deep in [nested(MAX_DEPTH + 1), "(".repeat(60000), "not ".repeat(60000) + "pid=1"] {
+      match Query::parse(&deep) {
+        Err(Error::InvalidArgument(message)) => assert_eq!(message, "filter nested too deeply"),
+        _ => panic!("accepted a filter nested too deeply"),
+      }
+    }
+  }
+
+  #[test]
+  fn command_fields() {
+    assert!(matches(r#"cmd~"-jar app\.jar" and exe~/jvm/ and cwd=/srv/app"#));
+  }
+
+  #[test]
+  fn quoted_values() {
+    assert!(matches(r#"user="postgres""#));
+    assert!(matches(r#"name~"j\w+a""#));
+    assert!(!matches(r#"name="java ""#));
+  }
+
+  #[test]
+  fn errors() {
+    assert!(matches!(Query::parse("colour=red"), Err(Error::UnknownField(_))));
+    for bad in [
+      "",
+      "pid",
+      "pid=",
+      "pid=abc",
+      "mem>5X",
+      "(pid=1",
+      "pid=1 pid=2",
+      "name~\"(\"",
+      "name=\"open",
+    ] {
+      assert!(
+        matches!(Query::parse(bad), Err(Error::InvalidArgument(_))),
+        "{} should not parse",
+        bad
+      );
+    }
+  }
+}
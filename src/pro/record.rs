@@ -0,0 +1,193 @@
+//! Recordings of `Snapshot`s as JSON lines, one snapshot per line, so they can be replayed later or
+//! read by other tools. `Recorder` rotates the file like logrotate: `FILE` is renamed to `FILE.1`,
+//! `FILE.1` to `FILE.2` and so on, dropping the oldest.
+
+use super::{Error, Result, Snapshot};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub struct Recorder {
+  path: PathBuf,
+  max_bytes: u64,
+  keep: usize,
+  file: BufWriter<File>,
+  written: u64,
+}
+
+impl Recorder {
+  /// Appends to `path`, rotating once it grows past `max_bytes` and keeping `keep` old files.
+  pub fn new<P: Into<PathBuf>>(path: P, max_bytes: u64, keep: usize) -> Result<Self> {
+    let path = path.into();
+    let (file, written) = Self::open(&path)?;
+    Ok(Recorder {
+      path,
+      max_bytes,
+      keep,
+      file,
+      written,
+    })
+  }
+
+  fn open(path: &Path) -> Result<(BufWriter<File>, u64)> {
+    let file = fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .map_err(|e| Error::from_read(e, path, None))?;
+    let written = file.metadata()?.len();
+    Ok((BufWriter::new(file), written))
+  }
+
+  pub fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+    let mut line = serde_json::to_string(snapshot).map_err(io::Error::from)?;
+    line.push('\n');
+    if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+      self.rotate()?;
+    }
+    self.file.write_all(line.as_bytes())?;
+    // flush every record, a recording is most useful right after something went wrong
+    self.file.flush()?;
+    self.written += line.len() as u64;
+    Ok(())
+  }
+
+  fn rotate(&mut self) -> Result<()> {
+    self.file.flush()?;
+    if self.keep == 0 {
+      fs::remove_file(&self.path)?;
+    } else {
+      for index in (1..self.keep).rev() {
+        let from = rotated_path(&self.path, index);
+        if from.exists() {
+          fs::rename(&from, rotated_path(&self.path, index + 1))?;
+        }
+      }
+      fs::rename(&self.path, rotated_path(&self.path, 1))?;
+    }
+    (self.file, self.written) = Self::open(&self.path)?;
+    Ok(())
+  }
+}
+
+/// `FILE.index`, the name of an old recording.
+pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+  let mut name = path.as_os_str().to_os_string();
+  name.push(format!(".{}", index));
+  PathBuf::from(name)
+}
+
+/// Reads the snapshots of one or more recordings, ordered by time.
+pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<Snapshot>> {
+  let mut snapshots = Vec::new();
+  for path in paths {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| match Error::from_read(e, path, None) {
+      Error::Io(e) => io::Error::new(e.kind(), format!("{}: {}", path.display(), e)).into(),
+      other => other,
+    })?;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      let snapshot = serde_json::from_str(&line).map_err(|e| Error::parse(path, Some(index + 1), e))?;
+      snapshots.push(snapshot);
+    }
+  }
+  snapshots.sort_by(|a: &Snapshot, b: &Snapshot| a.timestamp.total_cmp(&b.timestamp));
+  Ok(snapshots)
+}
+
+/// Formats seconds since the epoch in local time, e.g. "2024-03-01 03:00:15".
+pub fn format_timestamp(timestamp: f64) -> String {
+  let time = timestamp as libc::time_t;
+  let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+  if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+    return format!("{:.0}", timestamp);
+  }
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+    tm.tm_year + 1900,
+    tm.tm_mon + 1,
+    tm.tm_mday,
+    tm.tm_hour,
+    tm.tm_min,
+    tm.tm_sec
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::{fixture, fixture_snapshot as snapshot, read_processes};
+  use super::*;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("procstat-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn round_trip() {
+    let dir = temp_dir("round-trip");
+    let path = dir.join("rec.ndjson");
+    let mut recorder = Recorder::new(&path, u64::MAX, 1).unwrap();
+    recorder.write(&snapshot(2.0)).unwrap();
+    recorder.write(&snapshot(1.0)).unwrap();
+
+    let snapshots = load(&[&path]).unwrap();
+    assert_eq!(
+      snapshots.iter().map(|s| s.timestamp).collect::<Vec<_>>(),
+      vec![1.0, 2.0]
+    );
+    let live = read_processes(&fixture("basic")).unwrap();
+    assert_eq!(snapshots[0].processes.len(), live.len());
+    let java = snapshots[0].processes.iter().find(|p| p.pid == 42).unwrap();
+    assert_eq!(
+      (java.memory, java.command.as_str()),
+      (812000, "/usr/lib/jvm/bin/java -Xmx2g -jar /srv/app/app.jar")
+    );
+    assert_eq!(snapshots[0].disks.len(), 3);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn rotates_and_keeps_old_files() {
+    let dir = temp_dir("rotate");
+    let path = dir.join("rec.ndjson");
+    // small enough that every snapshot starts a new file
+    let mut recorder = Recorder::new(&path, 10, 2).unwrap();
+    for timestamp in 1..=4 {
+      recorder.write(&snapshot(timestamp as f64)).unwrap();
+    }
+
+    let timestamps = |path: &Path| load(&[path]).unwrap().iter().map(|s| s.timestamp).collect::<Vec<_>>();
+    assert_eq!(timestamps(&path), vec![4.0]);
+    assert_eq!(timestamps(&rotated_path(&path, 1)), vec![3.0]);
+    assert_eq!(timestamps(&rotated_path(&path, 2)), vec![2.0]);
+    assert!(!rotated_path(&path, 3).exists());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn bad_lines_report_their_position() {
+    let dir = temp_dir("bad-line");
+    let path = dir.join("rec.ndjson");
+    let mut recorder = Recorder::new(&path, u64::MAX, 1).unwrap();
+    recorder.write(&snapshot(1.0)).unwrap();
+    fs::OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .unwrap()
+      .write_all(b"{not json\n")
+      .unwrap();
+
+    match load(&[&path]) {
+      Err(Error::Parse { line, .. }) => assert_eq!(line, Some(2)),
+      other => panic!("expected a parse error, got {:?}", other.map(|s| s.len())),
+    }
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,209 @@
+//! Samples the system on one background thread and publishes every sample to any number of
+//! subscribers. The sampler owns the `Counters` the usage and rates are computed from, so the
+//! frontends share one set of deltas instead of each polling and skewing the others'.
+
+use super::{
+  get_disk_rates, get_network_rates, Counters, DiskStats, Field, NetworkStats, ProcSource, Result, Snapshot,
+};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// One sample of the system, shared by the subscribers and never changed after it is published.
+pub struct Sample {
+  /// All processes, sorted by pid.
+  pub snapshot: Snapshot,
+  /// Seconds since the previous sample, 0 for the first.
+  pub elapsed_seconds: f64,
+  /// (device, read bytes/s, written bytes/s), empty for the first sample.
+  pub disk_rates: Vec<(String, f64, f64)>,
+  /// (interface, received bytes/s, transmitted bytes/s), empty for the first sample.
+  pub network_rates: Vec<(String, f64, f64)>,
+}
+
+impl fmt::Debug for Sample {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Sample")
+      .field("timestamp", &self.snapshot.timestamp)
+      .field("processes", &self.snapshot.processes.len())
+      .field("elapsed_seconds", &self.elapsed_seconds)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Takes the samples of a `Sampler`, for callers that sample on their own schedule like the CLI
+/// loops.
+pub struct Collector {
+  source: ProcSource,
+  counters: Counters,
+  // timestamp, disks and networks of the previous sample
+  previous: Option<(f64, Vec<DiskStats>, Vec<NetworkStats>)>,
+}
+
+impl Collector {
+  pub fn new(source: ProcSource) -> Self {
+    Collector {
+      source,
+      counters: Counters::default(),
+      previous: None,
+    }
+  }
+
+  pub fn collect(&mut self) -> Result<Sample> {
+    let snapshot = self
+      .counters
+      .take_snapshot(&self.source, usize::MAX, Field::Pid, false, None)?;
+    let (elapsed_seconds, disk_rates, network_rates) = match &self.previous {
+      Some((timestamp, disks, networks)) if snapshot.timestamp > *timestamp => {
+        let elapsed = snapshot.timestamp - timestamp;
+        (
+          elapsed,
+          get_disk_rates(disks, &snapshot.disks, elapsed),
+          get_network_rates(networks, &snapshot.networks, elapsed),
+        )
+      }
+      _ => (0.0, Vec::new(), Vec::new()),
+    };
+    self.previous = Some((snapshot.timestamp, snapshot.disks.clone(), snapshot.networks.clone()));
+    Ok(Sample {
+      snapshot,
+      elapsed_seconds,
+      disk_rates,
+      network_rates,
+    })
+  }
+}
+
+#[derive(Default)]
+struct Subscribers {
+  latest: Option<Arc<Sample>>,
+  senders: Vec<Sender<Arc<Sample>>>,
+}
+
+/// Samples a `ProcSource` every interval until dropped. A round that fails to read, e.g. because the
+/// procfs went away, is skipped and subscribers keep the previous sample.
+pub struct Sampler {
+  subscribers: Arc<Mutex<Subscribers>>,
+  // a message samples right away, hanging up stops the thread
+  control: Option<Sender<()>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Sampler {
+  /// Starts sampling `source` now and then every `interval`.
+  pub fn spawn(source: ProcSource, interval: Duration) -> Self {
+    let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+    let (control, requests) = mpsc::channel();
+    let thread = thread::spawn({
+      let subscribers = Arc::clone(&subscribers);
+      move || {
+        let mut collector = Collector::new(source);
+        loop {
+          if let Ok(sample) = collector.collect() {
+            let sample = Arc::new(sample);
+            let mut subscribers = subscribers.lock().unwrap();
+            // gone subscribers drop their receivers
+            subscribers.senders.retain(|tx| tx.send(Arc::clone(&sample)).is_ok());
+            subscribers.latest = Some(sample);
+          }
+          match requests.recv_timeout(interval) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+          }
+        }
+      }
+    });
+    Sampler {
+      subscribers,
+      control: Some(control),
+      thread: Some(thread),
+    }
+  }
+
+  /// Receives every sample from now on, starting with the latest one if there is one already.
+  pub fn subscribe(&self) -> Receiver<Arc<Sample>> {
+    let (tx, rx) = mpsc::channel();
+    let mut subscribers = self.subscribers.lock().unwrap();
+    if let Some(sample) = &subscribers.latest {
+      let _ = tx.send(Arc::clone(sample));
+    }
+    subscribers.senders.push(tx);
+    rx
+  }
+
+  pub fn latest(&self) -> Option<Arc<Sample>> {
+    self.subscribers.lock().unwrap().latest.clone()
+  }
+
+  /// Takes the next sample right away rather than at the end of the interval.
+  pub fn refresh(&self) {
+    if let Some(control) = &self.control {
+      let _ = control.send(());
+    }
+  }
+}
+
+impl Drop for Sampler {
+  fn drop(&mut self) {
+    self.control = None;
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::fixture;
+  use super::*;
+  use std::time::Instant;
+
+  #[test]
+  fn collector_keeps_its_own_deltas() {
+    let mut collector = Collector::new(fixture("basic"));
+    let first = collector.collect().unwrap();
+    assert!(first.snapshot.cpu_percent.is_empty() && first.disk_rates.is_empty());
+    assert_eq!(first.elapsed_seconds, 0.0);
+
+    // another poller in between doesn't touch the collector's previous sample
+    Counters::default().cpu_usage(&fixture("basic")).unwrap();
+    thread::sleep(Duration::from_millis(10));
+    let second = collector.collect().unwrap();
+    assert!(second.elapsed_seconds > 0.0);
+    // the fixture doesn't change, so everything idles
+    assert_eq!(second.snapshot.cpu_percent, vec![0.0; 3]);
+    assert_eq!(second.network_rates.len(), second.snapshot.networks.len());
+    assert!(second
+      .disk_rates
+      .iter()
+      .all(|&(_, read, write)| read == 0.0 && write == 0.0));
+  }
+
+  #[test]
+  fn subscribers_share_samples() {
+    let sampler = Sampler::spawn(fixture("basic"), Duration::from_millis(10));
+    let first = sampler.subscribe();
+    let second = sampler.subscribe();
+    let received: Vec<_> = first.iter().take(5).collect();
+    let shared = second.recv().unwrap();
+    assert!(received.iter().any(|sample| Arc::ptr_eq(sample, &shared)));
+    assert!(received
+      .windows(2)
+      .all(|w| w[1].snapshot.timestamp > w[0].snapshot.timestamp));
+
+    // dropping the sampler stops the thread and hangs up on the subscribers
+    drop(sampler);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+      match second.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(_) => continue,
+        Err(e) => {
+          assert_eq!(e, RecvTimeoutError::Disconnected);
+          break;
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,135 @@
+//! A minimal HTTP server for `pro::metrics`, just enough for Prometheus to scrape /metrics. One
+//! request per connection, each on a thread of its own.
+
+use crate::pro::{self, metrics};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+const TEXT: &str = "text/plain; charset=utf-8";
+const INDEX: &str = "<html><body><a href=\"/metrics\">Metrics</a></body></html>\n";
+
+// A scraper that stops talking is dropped after this
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Longest request line and headers together, nothing we serve needs more
+const MAX_REQUEST: u64 = 8 * 1024;
+
+pub fn run(listen: &str, source: pro::ProcSource, processes: Option<pro::Filter>) -> pro::Result<()> {
+  let listener = TcpListener::bind(listen)?;
+  eprintln!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+  let shared = Arc::new((source, processes));
+  for stream in listener.incoming() {
+    let stream = match stream {
+      Ok(stream) => stream,
+      Err(e) => {
+        eprintln!("error: {}", e);
+        continue;
+      }
+    };
+    let shared = Arc::clone(&shared);
+    std::thread::spawn(move || {
+      // a failed request is the client's problem, the others are served anyway
+      if let Err(e) = handle(stream, &shared.0, shared.1.as_ref()) {
+        eprintln!("error: {}", e);
+      }
+    });
+  }
+  Ok(())
+}
+
+fn handle(stream: TcpStream, source: &pro::ProcSource, processes: Option<&pro::Filter>) -> pro::Result<()> {
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  stream.set_write_timeout(Some(TIMEOUT))?;
+  let mut reader = BufReader::new((&stream).take(MAX_REQUEST));
+  let mut request = String::new();
+  reader.read_line(&mut request)?;
+  // skip the headers, nothing we serve depends on them
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+    header.clear();
+  }
+
+  let (status, content_type, body) = if request.ends_with('\n') {
+    respond(&request, source, processes)
+  } else {
+    // cut off by the limit, or the client hung up mid-line
+    ("400 Bad Request", TEXT, "Bad request\n".to_string())
+  };
+  let head = request.starts_with("HEAD ");
+  let mut stream = &stream;
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    content_type,
+    body.len(),
+    if head { "" } else { &body }
+  )?;
+  stream.flush()?;
+  Ok(())
+}
+
+// The status, content type and body answering `request`, e.g. "GET /metrics HTTP/1.1"
+fn respond(
+  request: &str,
+  source: &pro::ProcSource,
+  processes: Option<&pro::Filter>,
+) -> (&'static str, &'static str, String) {
+  let mut parts = request.split_whitespace();
+  let method = parts.next().unwrap_or_default();
+  let path = parts.next().unwrap_or_default();
+  let path = path.split('?').next().unwrap_or_default();
+
+  match (method, path) {
+    ("GET" | "HEAD", "/metrics") => match metrics::render(source, processes) {
+      Ok(text) => ("200 OK", metrics::CONTENT_TYPE, text),
+      Err(e) => ("500 Internal Server Error", TEXT, format!("{}\n", e)),
+    },
+    ("GET" | "HEAD", "/") => ("200 OK", "text/html; charset=utf-8", INDEX.to_string()),
+    (_, "/metrics" | "/") => ("405 Method Not Allowed", TEXT, "Method not allowed\n".to_string()),
+    _ => ("404 Not Found", TEXT, "Not found\n".to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn routes_requests() {
+    let source = pro::fixture("basic");
+    let status = |request: &str| respond(request, &source, None).0;
+    assert_eq!(status("GET /metrics HTTP/1.1\r\n"), "200 OK");
+    assert_eq!(status("GET /metrics?name[]=x HTTP/1.1\r\n"), "200 OK");
+    assert_eq!(status("GET / HTTP/1.0\r\n"), "200 OK");
+    assert_eq!(status("POST /metrics HTTP/1.1\r\n"), "405 Method Not Allowed");
+    assert_eq!(status("GET /favicon.ico HTTP/1.1\r\n"), "404 Not Found");
+    assert_eq!(status(""), "404 Not Found");
+
+    let missing = pro::ProcSource::new("/nonexistent");
+    assert_eq!(
+      respond("GET /metrics HTTP/1.1", &missing, None).0,
+      "500 Internal Server Error"
+    );
+  }
+
+  #[test]
+  fn refuses_overlong_requests() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+      let (stream, _) = listener.accept().unwrap();
+      handle(stream, &pro::fixture("basic"), None)
+    });
+    let mut client = TcpStream::connect(address).unwrap();
+    // exactly the limit without a line end, more would be unread when the server hangs up, and
+    // reset the connection before the response is read
+    write!(client, "GET /{}", "x".repeat(MAX_REQUEST as usize - 5)).unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    server.join().unwrap().unwrap();
+  }
+}
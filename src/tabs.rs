@@ -0,0 +1,33 @@
+//! The views the TUI and the GUI both switch between.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tab {
+  Processes,
+  Cpu,
+  System,
+  Disk,
+  Network,
+}
+
+impl Tab {
+  /// Every tab, in the order they are shown.
+  pub const ALL: [Tab; 5] = [Tab::Processes, Tab::Cpu, Tab::System, Tab::Disk, Tab::Network];
+
+  /// The tab to the right, wrapping around to the first.
+  pub fn next(self) -> Tab {
+    Tab::ALL[(self as usize + 1) % Tab::ALL.len()]
+  }
+
+  /// The tab to the left, wrapping around to the last.
+  pub fn previous(self) -> Tab {
+    Tab::ALL[(self as usize + Tab::ALL.len() - 1) % Tab::ALL.len()]
+  }
+}
+
+impl fmt::Display for Tab {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
@@ -1,90 +1,144 @@
+use crate::config::{Colors, Config, Keys};
 use crate::pro::{self, list_processes, OutputMessage};
-use core::fmt;
+use crate::tabs::Tab;
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
 use humansize::{format_size, BINARY};
-use libc::sysinfo;
-use plotters_iced::sample::lttb::LttbSource;
+use libc::{pid_t, sysinfo};
 use ratatui::{
+  layout::Flex,
   layout::{Constraint, Layout},
   prelude::Backend,
-  style::{Color, Style, Stylize},
+  style::{Style, Stylize},
   symbols::{self, Marker},
-  widgets::{Block, Chart, Dataset, Gauge, GraphType, Paragraph, Row, Table, TableState, Tabs},
+  widgets::{
+    Block, Chart, Clear, Dataset, Gauge, GraphType, List, ListState, Paragraph, Row, Sparkline, Table, TableState,
+    Tabs, Wrap,
+  },
   Frame, Terminal,
 };
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
   fmt::Debug,
   io,
+  path::PathBuf,
   sync::mpsc::{self, Receiver, Sender},
-  thread, time,
+  sync::Arc,
+  thread,
 };
 
+// Starts a filter expression instead of a plain search, e.g. "/:user=root and cpu>5"
+const QUERY_PREFIX: char = ':';
+
+// (name, read/rx points, write/tx points)
+type RateSeries = (String, Vec<(f64, f64)>, Vec<(f64, f64)>);
+
 #[derive(Debug)]
 enum InputMessage {
   KeyPress(KeyEvent),
-  SearchInput(char),
-  ModifySort(String),
-  ClearSearch,
-  Backspace,
-  SearchEnd,
-  ModifySearch,
-  Quit,
+  Sample(Arc<pro::sampler::Sample>),
 }
 
 #[derive(PartialEq)]
 enum Mode {
   Search,
+  // the sort key was pressed, the next key picks the field
+  Sort,
   Normal,
 }
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum CurrentTab {
-  Processes,
-  Cpu,
-  System,
-  Disk,
-  Network,
-  Max,
+// Opened with 'k': pick a signal from pro::SIGNALS, then confirm sending it
+struct SignalPicker {
+  pid: pid_t,
+  name: String,
+  list_state: ListState,
+  confirming: bool,
 }
 
-impl TryFrom<u8> for CurrentTab {
-  type Error = ();
-  fn try_from(value: u8) -> Result<Self, Self::Error> {
-    match value {
-      0 => Ok(Self::Processes),
-      1 => Ok(Self::Cpu),
-      2 => Ok(Self::System),
-      3 => Ok(Self::Disk),
-      4 => Ok(Self::Network),
-      _ => Err(()),
+impl SignalPicker {
+  fn new(pid: pid_t, name: String) -> Self {
+    let term = pro::SIGNALS.iter().position(|&(_, number)| number == libc::SIGTERM);
+    SignalPicker {
+      pid,
+      name,
+      list_state: ListState::default().with_selected(term),
+      confirming: false,
     }
   }
-}
 
-impl fmt::Display for CurrentTab {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{:?}", self)
+  fn signal(&self) -> i32 {
+    // the list only clamps the selection when it is drawn
+    let index = self
+      .list_state
+      .selected()
+      .unwrap_or_default()
+      .min(pro::SIGNALS.len() - 1);
+    pro::SIGNALS[index].1
   }
 }
 
+// Opened with F2: show, hide, reorder and resize the process table columns
+struct ColumnSetup {
+  list_state: ListState,
+}
+
+// Opened with Enter: everything about one process, refreshed with the list
+struct DetailView {
+  pid: pid_t,
+  details: Option<pro::detail::ProcessDetails>,
+  // why the last refresh failed, e.g. the process exited
+  error: Option<String>,
+  // sampled while the view is open, cpu in tenths of a percent and rss in kB
+  cpu_history: Vec<u64>,
+  memory_history: Vec<u64>,
+  scroll: u16,
+}
+
+// Drives the App from a recording instead of /proc, see pro::record
+struct Replay {
+  snapshots: Vec<pro::Snapshot>,
+  // the snapshot on screen
+  position: usize,
+  paused: bool,
+}
+
+// How far '<' and '>' jump in a replay
+const SEEK_SECONDS: f64 = 60.0;
+
 pub struct App {
+  source: pro::ProcSource,
   processes: Vec<pro::ProcessInfo>,
   accessible_processes: Vec<pro::ProcessInfo>,
   from: usize,
   nprocs: usize,
-  sort_by: String,
+  sort_by: pro::Field,
   ascending: bool,
-  filter_by: String,
-  pattern: String,
-  exact_match: bool,
+  filter: pro::Filter,
+  search: String,
+  search_error: Option<String>,
+  show_command: bool,
+  // pid whose threads are shown instead of the process list
+  threads_of: Option<pid_t>,
+  threads: Vec<pro::ThreadInfo>,
+  tree_mode: bool,
+  // one per row of accessible_processes in tree mode
+  tree_lines: Vec<pro::TreeLine>,
+  collapsed: HashSet<pid_t>,
+  signal_picker: Option<SignalPicker>,
+  column_setup: Option<ColumnSetup>,
+  detail: Option<DetailView>,
+  replay: Option<Replay>,
+  alerts: pro::alert::Alerts,
+  // None when replaying
+  sampler: Option<pro::sampler::Sampler>,
+  // the thread view polls its pid on its own
+  thread_counters: pro::Counters,
   exit: bool,
   refresh_rate: std::time::Duration,
   time: std::time::Instant,
-  current_tab: CurrentTab,
+  current_tab: Tab,
   mode: Mode,
   table_state: TableState,
+  input_tx: Sender<InputMessage>,
   input_rx: Receiver<InputMessage>,
   sysinfo: Option<sysinfo>,
   load_history: Vec<(f64, f64)>,
@@ -92,165 +146,134 @@ pub struct App {
   cpu_history: Vec<(f64, f64)>,
   cpu_usage: Vec<f64>,
   disk_stats: Vec<pro::DiskStats>,
-  prev_disk_stats: Vec<pro::DiskStats>,
   disk_history: HashMap<String, Vec<(f64, f64, f64)>>,
   network_stats: Vec<pro::NetworkStats>,
-  prev_network_stats: Vec<pro::NetworkStats>,
   network_history: HashMap<String, Vec<(f64, f64, f64)>>,
   status_message: Option<String>,
   status_message_error: bool,
   status_message_time: Option<std::time::Instant>,
   output_tx: Sender<OutputMessage>,
   output_rx: Receiver<OutputMessage>,
+  config: Config,
+  // where the save key writes the layout
+  config_path: Option<PathBuf>,
 }
 
+// Key presses go to the app as they are, it knows which popup or mode they are meant for
 fn spawn_input_handler(tx: Sender<InputMessage>) {
   thread::spawn(move || loop {
-    if let Ok(event) = event::read() {
-      match event {
-        Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-          let msg = match key_event.code {
-            KeyCode::Char('s') => {
-              let mut sort_by = "pid";
-              if let Ok(Event::Key(key_event)) = event::read() {
-                sort_by = match key_event.code {
-                  KeyCode::Char('n') => "name",
-                  KeyCode::Char('p') => "pid",
-                  KeyCode::Char('u') => "user",
-                  KeyCode::Char('m') => "memory",
-                  _ => "pid",
-                };
-              }
-              InputMessage::ModifySort(sort_by.to_string())
-            }
-            KeyCode::Char('q') => InputMessage::Quit,
-            KeyCode::Char('/') => {
-              let _ = tx.send(InputMessage::KeyPress(key_event));
-              loop {
-                if let Ok(Event::Key(key_event)) = event::read() {
-                  if key_event.kind == KeyEventKind::Press {
-                    match key_event.code {
-                      KeyCode::Enter => {
-                        break;
-                      }
-                      KeyCode::Esc => {
-                        let _ = tx.send(InputMessage::ClearSearch);
-                        break;
-                      }
-                      KeyCode::Char(c) => {
-                        let _ = tx.send(InputMessage::SearchInput(c));
-                      }
-                      KeyCode::Backspace => {
-                        let _ = tx.send(InputMessage::Backspace);
-                      }
-                      _ => {}
-                    }
-                    let _ = tx.send(InputMessage::ModifySearch);
-                  }
-                }
-              }
-              InputMessage::SearchEnd
-            }
-            _ => InputMessage::KeyPress(key_event),
-          };
-          if tx.send(msg).is_err() {
-            break;
-          }
-        }
-        _ => {}
+    if let Ok(Event::Key(key_event)) = event::read() {
+      if key_event.kind == KeyEventKind::Press && tx.send(InputMessage::KeyPress(key_event)).is_err() {
+        break;
       }
     }
   });
 }
 
 impl App {
-  pub fn new() -> Self {
+  pub fn new(source: pro::ProcSource, config: Config) -> Self {
     let (tx, rx) = mpsc::channel();
     let (output_tx, output_rx) = mpsc::channel();
-    spawn_input_handler(tx);
+    spawn_input_handler(tx.clone());
 
-    Self {
-      processes: pro::read_processes().unwrap(),
+    let mut app = Self {
+      processes: Vec::new(),
+      source,
       accessible_processes: Vec::new(),
       from: 0,
       nprocs: usize::MAX,
-      sort_by: String::from("pid"),
-      ascending: true,
-      filter_by: String::from("any"),
-      pattern: String::from(""),
-      exact_match: false,
+      sort_by: config.sort_by,
+      ascending: !config.descending,
+      filter: pro::Filter::default(),
+      search: String::new(),
+      search_error: None,
+      show_command: false,
+      threads_of: None,
+      threads: Vec::new(),
+      tree_mode: false,
+      tree_lines: Vec::new(),
+      collapsed: HashSet::new(),
+      signal_picker: None,
+      column_setup: None,
+      detail: None,
+      replay: None,
+      alerts: pro::alert::Alerts::new(Vec::new()),
+      sampler: None,
+      thread_counters: pro::Counters::default(),
       exit: false,
-      refresh_rate: std::time::Duration::from_secs(1),
-      time: std::time::Instant::now() - std::time::Duration::from_secs(1),
-      current_tab: CurrentTab::Processes,
+      refresh_rate: std::time::Duration::from_secs(config.refresh_rate),
+      time: std::time::Instant::now() - std::time::Duration::from_secs(config.refresh_rate),
+      current_tab: Tab::Processes,
       table_state: TableState::default(),
+      input_tx: tx,
       input_rx: rx,
       mode: Mode::Normal,
       sysinfo: None,
-      load_history: Vec::with_capacity(100),
-      memory_history: Vec::with_capacity(100),
-      cpu_history: Vec::with_capacity(100),
+      load_history: Vec::with_capacity(config.history),
+      memory_history: Vec::with_capacity(config.history),
+      cpu_history: Vec::with_capacity(config.history),
       cpu_usage: Vec::new(),
       disk_stats: Vec::new(),
-      prev_disk_stats: Vec::new(),
       disk_history: HashMap::new(),
       network_stats: Vec::new(),
-      prev_network_stats: Vec::new(),
       network_history: HashMap::new(),
       status_message: None,
       status_message_error: false,
       status_message_time: None,
       output_tx,
       output_rx,
+      config,
+      config_path: None,
+    };
+    // the default filter shows up as a search, to be edited or cleared
+    if let Some(filter) = &app.config.filter {
+      app.search = format!("{}{}", QUERY_PREFIX, filter);
+      app.apply_search();
     }
+    app
   }
 
-  fn update_network_info(&mut self) {
-    if let Ok(stats) = pro::get_network_stats() {
-      let rates = pro::get_network_rates(&self.prev_network_stats, &stats, self.refresh_rate.as_secs_f64());
+  fn push_network_stats(&mut self, stats: Vec<pro::NetworkStats>, elapsed_seconds: f64) {
+    self.push_network_rates(pro::get_network_rates(&self.network_stats, &stats, elapsed_seconds));
+    self.network_stats = stats;
+  }
 
-      for (interface, rx_rate, tx_rate) in rates {
-        let history = self.network_history.entry(interface).or_default();
-        if history.len() >= 100 {
-          history.remove(0);
-        }
-        history.push((history.len() as f64, rx_rate, tx_rate));
+  fn push_network_rates(&mut self, rates: Vec<(String, f64, f64)>) {
+    for (interface, rx_rate, tx_rate) in rates {
+      let history = self.network_history.entry(interface).or_default();
+      if history.len() >= self.config.history {
+        history.remove(0);
       }
-
-      self.prev_network_stats = self.network_stats.clone();
-      self.network_stats = stats;
+      history.push((history.len() as f64, rx_rate, tx_rate));
     }
   }
 
-  fn update_cpu_info(&mut self) {
-    if let Ok(usage) = pro::get_cpu_usage() {
-      if usage.is_empty() {
-        return
-      }
-      self.cpu_usage = usage[1..].to_vec();
-
-      if self.cpu_history.len() >= 100 {
-        self.cpu_history.remove(0);
-      }
+  // total usage first, then one entry per core
+  fn push_cpu_usage(&mut self, usage: Vec<f64>) {
+    if usage.is_empty() {
+      return;
+    }
+    self.cpu_usage = usage[1..].to_vec();
 
-        self.cpu_history.push((self.cpu_history.len() as f64, usage[0]));
+    if self.cpu_history.len() >= self.config.history {
+      self.cpu_history.remove(0);
     }
+
+    self.cpu_history.push((self.cpu_history.len() as f64, usage[0]));
   }
 
-  fn update_disk_info(&mut self) {
-    if let Ok(stats) = pro::get_disk_stats() {
-      let rates = pro::get_disk_rates(&self.prev_disk_stats, &stats, self.refresh_rate.as_secs_f64());
+  fn push_disk_stats(&mut self, stats: Vec<pro::DiskStats>, elapsed_seconds: f64) {
+    self.push_disk_rates(pro::get_disk_rates(&self.disk_stats, &stats, elapsed_seconds));
+    self.disk_stats = stats;
+  }
 
-      for (device, read_rate, write_rate) in rates {
-        let history = self.disk_history.entry(device).or_default();
-        if history.len() >= 100 {
-          history.remove(0);
-        }
-        history.push((history.len() as f64, read_rate, write_rate));
+  fn push_disk_rates(&mut self, rates: Vec<(String, f64, f64)>) {
+    for (device, read_rate, write_rate) in rates {
+      let history = self.disk_history.entry(device).or_default();
+      if history.len() >= self.config.history {
+        history.remove(0);
       }
-
-      self.prev_disk_stats = self.disk_stats.clone();
-      self.disk_stats = stats;
+      history.push((history.len() as f64, read_rate, write_rate));
     }
   }
 
@@ -262,17 +285,41 @@ impl App {
     format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
   }
 
+  // Samples the live system on a background thread, handing every sample to the loop of `run`
+  fn start_sampling(&mut self) {
+    let sampler = pro::sampler::Sampler::spawn(self.source.clone(), self.refresh_rate);
+    let samples = sampler.subscribe();
+    let tx = self.input_tx.clone();
+    thread::spawn(move || {
+      for sample in samples {
+        if tx.send(InputMessage::Sample(sample)).is_err() {
+          break;
+        }
+      }
+    });
+    self.sampler = Some(sampler);
+  }
+
+  fn show_sample(&mut self, sample: &pro::sampler::Sample) {
+    let snapshot = &sample.snapshot;
+    self.processes = snapshot.processes.clone();
+    self.push_sysinfo(snapshot.system.to_sysinfo());
+    self.push_cpu_usage(snapshot.cpu_percent.clone());
+    self.push_disk_rates(sample.disk_rates.clone());
+    self.disk_stats = snapshot.disks.clone();
+    self.push_network_rates(sample.network_rates.clone());
+    self.network_stats = snapshot.networks.clone();
+    self.update_threads();
+    self.update_detail();
+    self.evaluate_alerts(snapshot);
+  }
+
   pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
     while !self.exit {
-      let time = time::Instant::now();
-      if (time - self.time) > self.refresh_rate {
-        self.update_processes();
-        self.update_sysinfo();
-        self.update_cpu_info();
-        self.update_disk_info();
-        self.update_network_info();
+      if self.time.elapsed() >= self.refresh_rate {
+        self.advance_replay();
         self.clear_status_after_delay();
-        self.time = time;
+        self.time = std::time::Instant::now();
       }
 
       // Check for output messages
@@ -285,13 +332,28 @@ impl App {
       let _ = self.list_processes();
       terminal.draw(|frame| self.draw(frame))?;
 
-      if let Ok(message) = self.input_rx.try_recv() {
+      // sleeps until a key, a sample or the next replay step
+      let timeout = self.refresh_rate.saturating_sub(self.time.elapsed());
+      if let Ok(message) = self.input_rx.recv_timeout(timeout) {
         self.handle_input_message(message);
       }
     }
     Ok(())
   }
 
+  fn evaluate_alerts(&mut self, snapshot: &pro::Snapshot) {
+    for event in self.alerts.update(&pro::alert::Sample::from(snapshot)) {
+      let (message, is_error) = match self.alerts.notify(&event) {
+        Err(e) => (format!("{}: {}", event.rule, e), true),
+        Ok(()) if event.firing => (format!("ALERT {}", event.message), true),
+        Ok(()) => (event.message, false),
+      };
+      self.status_message = Some(message);
+      self.status_message_error = is_error;
+      self.status_message_time = Some(std::time::Instant::now());
+    }
+  }
+
   fn clear_status_after_delay(&mut self) {
     if let Some(time) = self.status_message_time {
       if time.elapsed() > std::time::Duration::from_secs(3) {
@@ -301,19 +363,366 @@ impl App {
     }
   }
 
-  fn update_processes(&mut self) {
-    self.processes = pro::read_processes().unwrap();
+  /// An App showing `snapshots` one per `refresh_rate`, starting paused at the first one.
+  pub fn replay(snapshots: Vec<pro::Snapshot>, refresh_rate: std::time::Duration, config: Config) -> Self {
+    let mut app = App::new(pro::ProcSource::default(), config);
+    app.refresh_rate = refresh_rate;
+    app.time = std::time::Instant::now();
+    app.replay = Some(Replay {
+      snapshots,
+      position: 0,
+      paused: true,
+    });
+    app.show_snapshot(0);
+    app
+  }
+
+  fn advance_replay(&mut self) {
+    let Some(replay) = &mut self.replay else {
+      return;
+    };
+    if replay.paused {
+      return;
+    }
+    if replay.position + 1 < replay.snapshots.len() {
+      let position = replay.position + 1;
+      self.show_snapshot(position);
+    } else {
+      replay.paused = true;
+    }
+  }
+
+  // Shows the snapshot at `position`, with the histories of the ones before it
+  fn show_snapshot(&mut self, position: usize) {
+    let Some(replay) = self.replay.take() else {
+      return;
+    };
+    let position = position.min(replay.snapshots.len().saturating_sub(1));
+
+    self.load_history.clear();
+    self.memory_history.clear();
+    self.cpu_history.clear();
+    self.cpu_usage.clear();
+    self.disk_stats.clear();
+    self.disk_history.clear();
+    self.network_stats.clear();
+    self.network_history.clear();
+    let start = position.saturating_sub(self.config.history - 1);
+    let mut previous: Option<&pro::Snapshot> = None;
+    for snapshot in replay.snapshots.get(start..=position).unwrap_or_default() {
+      let elapsed = previous.map_or(self.refresh_rate.as_secs_f64(), |p| snapshot.timestamp - p.timestamp);
+      self.push_sysinfo(snapshot.system.to_sysinfo());
+      self.push_cpu_usage(snapshot.cpu_percent.clone());
+      self.push_disk_stats(snapshot.disks.clone(), elapsed);
+      self.push_network_stats(snapshot.networks.clone(), elapsed);
+      previous = Some(snapshot);
+    }
+    if let Some(snapshot) = replay.snapshots.get(position) {
+      self.processes = snapshot.processes.clone();
+    }
+
+    self.replay = Some(Replay { position, ..replay });
+  }
+
+  fn handle_replay_key(&mut self, key_event: KeyEvent) -> bool {
+    let Some(replay) = &mut self.replay else {
+      return false;
+    };
+    let keys = self.config.keys;
+    let timestamp = replay.snapshots.get(replay.position).map_or(0.0, |s| s.timestamp);
+    // first snapshot at or after `timestamp`
+    let seek = |timestamp: f64| replay.snapshots.partition_point(|s| s.timestamp < timestamp);
+    let KeyCode::Char(key) = key_event.code else {
+      return false;
+    };
+    let position = match key {
+      _ if key == keys.pause => {
+        replay.paused = !replay.paused;
+        return true;
+      }
+      _ if key == keys.step => replay.position + 1,
+      _ if key == keys.step_back => replay.position.saturating_sub(1),
+      _ if key == keys.seek => seek(timestamp + SEEK_SECONDS),
+      _ if key == keys.seek_back => seek(timestamp - SEEK_SECONDS),
+      // acting on the pids of a recording would hit whatever runs under them now
+      _ if [keys.kill, keys.nice, keys.unnice, keys.threads].contains(&key) => {
+        self.status_message = Some("Not available when replaying a recording".to_string());
+        self.status_message_error = true;
+        self.status_message_time = Some(std::time::Instant::now());
+        return true;
+      }
+      _ => return false,
+    };
+    replay.paused = true;
+    self.show_snapshot(position);
+    true
+  }
+
+  fn open_detail(&mut self) {
+    if self.replay.is_some() {
+      self.status_message = Some("Not available when replaying a recording".to_string());
+      self.status_message_error = true;
+      self.status_message_time = Some(std::time::Instant::now());
+      return;
+    }
+    let Some(pid) = self.selected_process().map(|p| p.pid) else {
+      return;
+    };
+    self.detail = Some(DetailView {
+      pid,
+      details: None,
+      error: None,
+      cpu_history: Vec::new(),
+      memory_history: Vec::new(),
+      scroll: 0,
+    });
+    self.update_detail();
+  }
+
+  fn update_detail(&mut self) {
+    let Some(detail) = &mut self.detail else {
+      return;
+    };
+    match pro::detail::read_process_details(&self.source, detail.pid) {
+      Ok(details) => {
+        // the sample has the cpu usage since the previous one
+        let cpu_percent = self
+          .processes
+          .iter()
+          .find(|p| p.pid == detail.pid && p.start_time == details.info.start_time)
+          .map_or(0.0, |p| p.cpu_percent);
+        for (history, value) in [
+          (&mut detail.cpu_history, (cpu_percent * 10.0) as u64),
+          (&mut detail.memory_history, details.info.memory),
+        ] {
+          if history.len() >= self.config.history {
+            history.remove(0);
+          }
+          history.push(value);
+        }
+        detail.details = Some(details);
+        detail.error = None;
+      }
+      // keep showing what we had
+      Err(e) => detail.error = Some(e.to_string()),
+    }
+  }
+
+  fn handle_detail_key(&mut self, key_event: KeyEvent) {
+    let Some(detail) = &mut self.detail else {
+      return;
+    };
+    let keys = self.config.keys;
+    match key_event.code {
+      KeyCode::Esc | KeyCode::Enter => self.detail = None,
+      KeyCode::Up => detail.scroll = detail.scroll.saturating_sub(1),
+      KeyCode::Down => detail.scroll = detail.scroll.saturating_add(1),
+      KeyCode::PageUp => detail.scroll = detail.scroll.saturating_sub(10),
+      KeyCode::PageDown => detail.scroll = detail.scroll.saturating_add(10),
+      KeyCode::Char(key) if key == keys.help || key == keys.quit => self.handle_action_key(key, keys),
+      // these act on the shown process, not on whatever the list selects by now
+      KeyCode::Char(key) if [keys.kill, keys.nice, keys.unnice].contains(&key) => {
+        let Some(process) = self.detail_process() else {
+          return;
+        };
+        match key {
+          _ if key == keys.kill => self.signal_picker = Some(SignalPicker::new(process.pid, process.name)),
+          _ if key == keys.nice => {
+            let _ = pro::set_priority(process.pid, process.priority + 1, Some(&self.output_tx));
+          }
+          _ => {
+            let _ = pro::set_priority(process.pid, process.priority - 1, Some(&self.output_tx));
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+
+  // The process of the detail view as it is now, if its pid wasn't taken over by another one
+  fn detail_process(&mut self) -> Option<pro::ProcessInfo> {
+    let shown = &self.detail.as_ref()?.details.as_ref()?.info;
+    let (pid, start_time) = (shown.pid, shown.start_time);
+    match pro::read_process_info(&self.source, pid) {
+      Ok(process) if process.start_time == start_time => Some(process),
+      _ => {
+        self.status_message = Some(format!("Process {} no longer exists", pid));
+        self.status_message_error = true;
+        self.status_message_time = Some(std::time::Instant::now());
+        None
+      }
+    }
+  }
+
+  fn draw_detail(frame: &mut Frame, area: ratatui::layout::Rect, detail: &mut DetailView, colors: &Colors) {
+    let Some(details) = &detail.details else {
+      let message = detail.error.clone().unwrap_or_default();
+      frame.render_widget(
+        Paragraph::new(message)
+          .style(Style::default().fg(colors.error))
+          .block(Block::bordered().title(format!("Process {} - [esc] back", detail.pid))),
+        area,
+      );
+      return;
+    };
+    let info = &details.info;
+    let title = match &detail.error {
+      Some(error) => format!("Process {} ({}) - {} - [esc] back", info.pid, info.name, error),
+      None => format!("Process {} ({}) - [esc] back, ↑/↓ scroll", info.pid, info.name),
+    };
+    let block = Block::bordered().title(title);
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let [charts_area, body_area] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(inner);
+    let [cpu_area, memory_area] =
+      Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(charts_area);
+    let [overview_area, files_area] =
+      Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body_area);
+
+    let cpu = detail.cpu_history.last().copied().unwrap_or_default() as f64 / 10.0;
+    frame.render_widget(
+      Sparkline::default()
+        .block(Block::bordered().title(format!("CPU {:.1}%", cpu)))
+        .style(Style::default().fg(colors.chart))
+        .data(&detail.cpu_history),
+      cpu_area,
+    );
+    frame.render_widget(
+      Sparkline::default()
+        .block(Block::bordered().title(format!("RSS {}", format_size(info.memory * 1024, BINARY))))
+        .style(Style::default().fg(colors.memory_chart))
+        .data(&detail.memory_history),
+      memory_area,
+    );
+
+    let now = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_secs();
+    let mut overview: Vec<String> = details
+      .overview(now)
+      .into_iter()
+      .map(|(label, value)| format!("{}: {}", label, value))
+      .collect();
+    overview.extend([String::new(), "Memory:".to_string()]);
+    for (key, kb) in &details.memory {
+      overview.push(format!("  {:<10}{:>12}", key, format_size(kb * 1024, BINARY)));
+    }
+    overview.push(String::new());
+    match &details.io {
+      Some(counters) => {
+        overview.push(format!(
+          "I/O: read {}, write {}",
+          pro::format_rate(info.io_read_rate),
+          pro::format_rate(info.io_write_rate)
+        ));
+        for (key, value) in counters {
+          // syscr and syscw count calls, the others bytes
+          let value = if key.starts_with("sysc") {
+            value.to_string()
+          } else {
+            format_size(*value, BINARY)
+          };
+          overview.push(format!("  {:<24}{:>12}", key, value));
+        }
+      }
+      None => overview.push(format!("I/O: {}", pro::UNREADABLE)),
+    }
+    frame.render_widget(
+      Paragraph::new(overview.join("\n"))
+        .style(Style::default().fg(colors.text))
+        .wrap(Wrap { trim: false }),
+      overview_area,
+    );
+
+    let mut lines = vec!["Status:".to_string()];
+    lines.extend(
+      details
+        .status
+        .iter()
+        .map(|(key, value)| format!("  {}: {}", key, value)),
+    );
+    lines.push(String::new());
+    match &details.limits {
+      Some(limits) => {
+        lines.push("Limits:".to_string());
+        lines.extend(
+          limits
+            .iter()
+            .map(|l| format!("  {:<26}{:<12}{:<12}{}", l.name, l.soft, l.hard, l.units)),
+        );
+      }
+      None => lines.push(format!("Limits: {}", pro::UNREADABLE)),
+    }
+    lines.push(String::new());
+    match &details.namespaces {
+      Some(namespaces) => {
+        lines.push("Namespaces:".to_string());
+        lines.extend(
+          namespaces
+            .iter()
+            .map(|(name, target)| format!("  {:<18}{}", name, target)),
+        );
+      }
+      None => lines.push(format!("Namespaces: {}", pro::UNREADABLE)),
+    }
+    lines.push(String::new());
+    match &details.environment {
+      Some(environment) => {
+        lines.push("Environment:".to_string());
+        lines.extend(environment.iter().map(|entry| format!("  {}", entry)));
+      }
+      None => lines.push(format!("Environment: {}", pro::UNREADABLE)),
+    }
+    // don't scroll past the end
+    detail.scroll = detail.scroll.min(lines.len().saturating_sub(1) as u16);
+    frame.render_widget(
+      Paragraph::new(lines.join("\n"))
+        .style(Style::default().fg(colors.text))
+        .scroll((detail.scroll, 0)),
+      files_area,
+    );
+  }
+
+  fn update_threads(&mut self) {
+    let Some(pid) = self.threads_of else {
+      return;
+    };
+    match self.thread_counters.read_threads(&self.source, pid) {
+      Ok(mut threads) => {
+        // busiest first, that's usually the one we are looking for
+        threads.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent).then(a.tid.cmp(&b.tid)));
+        self.threads = threads;
+      }
+      Err(e) => {
+        self.threads_of = None;
+        self.threads.clear();
+        self.status_message = Some(e.to_string());
+        self.status_message_error = true;
+        self.status_message_time = Some(std::time::Instant::now());
+      }
+    }
+  }
+
+  fn toggle_threads(&mut self) {
+    if self.threads_of.take().is_none() {
+      if let Some(selection) = self.table_state.selected() {
+        self.threads_of = self.accessible_processes.get(selection).map(|p| p.pid);
+      }
+    }
+    self.threads.clear();
+    self.table_state.select(Some(0));
+    self.update_threads();
   }
 
-  fn update_sysinfo(&mut self) {
-    let info = pro::get_sysinfo();
-    if self.load_history.len() >= 100 {
+  fn push_sysinfo(&mut self, info: sysinfo) {
+    if self.load_history.len() >= self.config.history {
       self.load_history.remove(0);
     }
     let load_avg = info.loads[0] as f64 / 65536.0;
     self.load_history.push((self.load_history.len() as f64, load_avg));
 
-    if self.memory_history.len() >= 100 {
+    if self.memory_history.len() >= self.config.history {
       self.memory_history.remove(0);
     }
     let used_mem = (info.totalram - info.freeram) as f64 / info.totalram as f64 * 100.0;
@@ -324,29 +733,62 @@ impl App {
 
   fn handle_input_message(&mut self, message: InputMessage) {
     match message {
-      InputMessage::ModifySort(sort_by) => {
-        self.status_message = Some(format!("Sorting by {}", sort_by));
-        self.sort_by = sort_by;
-        self.ascending = true;
-      }
-      InputMessage::ClearSearch => {
-        self.pattern = String::new();
+      InputMessage::KeyPress(key_event) => self.handle_key_event(key_event),
+      InputMessage::Sample(sample) => self.show_sample(&sample),
+    }
+  }
+
+  // Typing a search: Enter keeps it, Esc clears it
+  fn handle_search_key(&mut self, key_event: KeyEvent) {
+    match key_event.code {
+      KeyCode::Enter => self.mode = Mode::Normal,
+      KeyCode::Esc => {
+        self.search.clear();
+        self.apply_search();
         self.mode = Mode::Normal;
       }
-      InputMessage::KeyPress(key_event) => self.handle_key_event(key_event),
-      InputMessage::SearchInput(c) => {
-        if self.mode == Mode::Normal {
-          self.pattern = String::new();
-          self.mode = Mode::Search;
-        }
-        self.pattern.push(c);
+      KeyCode::Char(c) => {
+        self.search.push(c);
+        self.apply_search();
       }
-      InputMessage::Backspace => {
-        self.pattern.pop();
+      KeyCode::Backspace => {
+        self.search.pop();
+        self.apply_search();
+      }
+      _ => {}
+    }
+  }
+
+  // The key after the sort key names the field, anything else sorts by pid
+  fn handle_sort_key(&mut self, key_event: KeyEvent) {
+    let sort_by = match key_event.code {
+      KeyCode::Char(c) => pro::Field::from_key(c).unwrap_or(pro::Field::Pid),
+      _ => pro::Field::Pid,
+    };
+    self.status_message = Some(format!("Sorting by {}", sort_by));
+    self.sort_by = sort_by;
+    self.ascending = true;
+    self.mode = Mode::Normal;
+  }
+
+  /// Searches by substring, or by filter expression when the search starts with QUERY_PREFIX.
+  /// An expression that does not parse yet keeps the previous filter.
+  fn apply_search(&mut self) {
+    match self.search.strip_prefix(QUERY_PREFIX) {
+      Some(expr) => match expr.parse() {
+        Ok(query) => {
+          self.filter = pro::Filter::with_query(query);
+          self.search_error = None;
+        }
+        Err(e) => self.search_error = Some(e.to_string()),
+      },
+      None => {
+        self.filter = pro::Filter {
+          pattern: self.search.clone(),
+          ..Default::default()
+        };
+        self.search_error = None;
       }
-      InputMessage::SearchEnd => self.mode = Mode::Normal,
-      InputMessage::ModifySearch => self.filter_by = "any".to_string(),
-      InputMessage::Quit => self.exit = true,
     }
   }
 
@@ -356,52 +798,113 @@ impl App {
     let vertical = Layout::vertical([Length(1), Min(0)]);
     let [title_area, main_area] = vertical.areas(frame.area());
     let tab_area = Layout::horizontal([Percentage(15), Percentage(85)]).split(title_area);
-    let main_area = Layout::vertical([Percentage(100), Min(1)]).split(main_area);
+    let firing = self.alerts.firing();
+    let alerts_height = if firing.is_empty() {
+      0
+    } else {
+      firing.len().min(5) as u16 + 2
+    };
+    let main_area = Layout::vertical([Percentage(100), Length(alerts_height), Min(1)]).split(main_area);
 
     frame.render_widget(Block::bordered().title("AMR KADI Pro"), tab_area[0]);
-    let mut tabs: Vec<String> = Vec::new();
-    for i in 0..CurrentTab::Max as usize {
-      tabs.push(CurrentTab::try_from(i as u8).expect("").to_string());
-    }
+    let tabs: Vec<String> = Tab::ALL.iter().map(|tab| tab.to_string()).collect();
     frame.render_widget(
       Tabs::new(tabs)
         .select(self.current_tab as usize)
-        .style(Style::default().white())
-        .highlight_style(Style::default().yellow())
+        .style(Style::default().fg(self.config.colors.tabs))
+        .highlight_style(Style::default().fg(self.config.colors.active_tab))
         .divider(symbols::DOT),
       tab_area[1],
     );
 
     match self.current_tab {
-      CurrentTab::Processes => {
+      Tab::Processes if self.detail.is_some() => {
+        if let Some(detail) = &mut self.detail {
+          Self::draw_detail(frame, main_area[0], detail, &self.config.colors);
+        }
+      }
+      Tab::Processes if self.threads_of.is_some() => {
+        let pid = self.threads_of.unwrap_or_default();
+        let name = self
+          .processes
+          .iter()
+          .find(|p| p.pid == pid)
+          .map(|p| p.name.as_str())
+          .unwrap_or("");
         let header = Row::new([
-          "UID",
-          "PID",
-          "PPID",
+          "TID",
+          "Name",
           "STATE",
-          "MEM(MB)",
-          "THREADS",
-          "VIRT_MEM(MB)",
+          "CPU%",
           "USER_TIME",
           "SYS_TIME",
+          "CPU",
           "Priority",
-          "Name",
         ]);
+        let rows = self.threads.iter().map(|t| {
+          Row::new([
+            t.tid.to_string(),
+            t.name.clone(),
+            t.state.to_string(),
+            format!("{:.1}", t.cpu_percent),
+            t.user_time.to_string(),
+            t.system_time.to_string(),
+            t.processor.to_string(),
+            t.priority.to_string(),
+          ])
+        });
+
+        frame.render_stateful_widget(
+          Table::new(rows, [8, 16, 6, 6, 10, 10, 4, 9])
+            .block(Block::bordered().title(format!("Threads of {} ({}) - [H] back", pid, name)))
+            .highlight_symbol(">>")
+            .row_highlight_style(Style::default().bg(self.config.colors.selection))
+            .header(header),
+          main_area[0],
+          &mut self.table_state,
+        );
+      }
+      Tab::Processes => {
+        let columns = self.columns();
+        let header = Row::new(columns.iter().map(|field| field.title()));
+        let widths: Vec<Constraint> = columns
+          .iter()
+          .map(|&field| match (is_text_column(field), self.column_width(field)) {
+            (true, width) => Constraint::Min(width),
+            (false, width) => Constraint::Length(width),
+          })
+          .collect();
+        // tree guides go in front of the name
+        let name_column = columns
+          .iter()
+          .position(|field| matches!(field, pro::Field::Name | pro::Field::Command))
+          .unwrap_or(columns.len() - 1);
 
         frame.render_stateful_widget(
           Table::new(
-            self.accessible_processes.iter().map(|f| Row::new(f.clone())),
-            [6, 6, 6, 5, 7, 7, 12, 9, 9, 9, 30],
+            self.accessible_processes.iter().enumerate().map(|(i, p)| {
+              let mut cells = p.cells(&columns);
+              if let Some(line) = self.tree_lines.get(i) {
+                let marker = match (line.has_children, line.collapsed) {
+                  (false, _) => "",
+                  (true, false) => "▾ ",
+                  (true, true) => "▸ ",
+                };
+                cells[name_column] = format!("{}{}{}", line.guides, marker, cells[name_column]);
+              }
+              Row::new(cells)
+            }),
+            widths,
           )
-          .block(Block::bordered().title("Processes"))
+          .block(Block::bordered().title(if self.tree_mode { "Process tree" } else { "Processes" }))
           .highlight_symbol(">>")
-          .row_highlight_style(Style::default().bg(Color::DarkGray))
+          .row_highlight_style(Style::default().bg(self.config.colors.selection))
           .header(header),
           main_area[0],
           &mut self.table_state,
         );
       }
-      CurrentTab::System => {
+      Tab::System => {
         if let Some(info) = &self.sysinfo {
           let chunks = Layout::vertical([
             Constraint::Percentage(25),
@@ -420,7 +923,7 @@ impl App {
           frame.render_widget(
             Gauge::default()
               .block(Block::bordered().title("RAM Usage"))
-              .gauge_style(Style::default().fg(Color::Green))
+              .gauge_style(Style::default().fg(self.config.colors.gauge))
               .percent(ram_used as u16)
               .label(format!(
                 "{}/{} ({:.1}%)",
@@ -434,7 +937,7 @@ impl App {
           frame.render_widget(
             Gauge::default()
               .block(Block::bordered().title("Swap Usage"))
-              .gauge_style(Style::default().fg(Color::Yellow))
+              .gauge_style(Style::default().fg(self.config.colors.swap))
               .percent(swap_used as u16)
               .label(format!(
                 "{}/{} ({:.1}%)",
@@ -449,7 +952,7 @@ impl App {
             .name("Load Average")
             .marker(Marker::Braille)
             .graph_type(GraphType::Line)
-            .style(Style::default().fg(Color::Cyan))
+            .style(Style::default().fg(self.config.colors.chart))
             .data(&self.load_history)];
 
           let max_load = self.load_history.iter().map(|(_, y)| *y).fold(1.0, f64::max);
@@ -458,7 +961,7 @@ impl App {
               .block(Block::bordered().title("Load Average History"))
               .x_axis(
                 ratatui::widgets::Axis::default()
-                  .bounds([0.0, 100.0])
+                  .bounds([0.0, self.config.history as f64])
                   .labels(vec![].into_iter().collect::<Vec<String>>()),
               )
               .y_axis(
@@ -478,7 +981,7 @@ impl App {
             .name("Memory Usage %")
             .marker(Marker::Braille)
             .graph_type(GraphType::Line)
-            .style(Style::default().fg(Color::Green))
+            .style(Style::default().fg(self.config.colors.memory_chart))
             .data(&self.memory_history)];
 
           frame.render_widget(
@@ -486,7 +989,7 @@ impl App {
               .block(Block::bordered().title("Memory Usage History"))
               .x_axis(
                 ratatui::widgets::Axis::default()
-                  .bounds([0.0, 100.0])
+                  .bounds([0.0, self.config.history as f64])
                   .labels(vec![].into_iter().collect::<Vec<String>>()),
               )
               .y_axis(
@@ -535,7 +1038,7 @@ impl App {
           frame.render_widget(
             Paragraph::new(system_info.join("\n"))
               .block(Block::bordered().title("System Information"))
-              .style(Style::default().fg(Color::White)),
+              .style(Style::default().fg(self.config.colors.text)),
             chunks[3],
           );
         } else {
@@ -545,10 +1048,10 @@ impl App {
           );
         }
       }
-      CurrentTab::Disk => {
+      Tab::Disk => {
         let chunks = Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).split(main_area[0]);
 
-        let disk_data: Vec<(String, Vec<(f64, f64)>, Vec<(f64, f64)>)> = self
+        let disk_data: Vec<RateSeries> = self
           .disk_history
           .iter()
           .map(|(device, history)| {
@@ -567,7 +1070,7 @@ impl App {
               .name(format!("{} Read", device))
               .marker(Marker::Braille)
               .graph_type(GraphType::Line)
-              .style(Style::default().fg(Color::Green))
+              .style(Style::default().fg(self.config.colors.read))
               .data(read_data),
           );
 
@@ -576,7 +1079,7 @@ impl App {
               .name(format!("{} Write", device))
               .marker(Marker::Braille)
               .graph_type(GraphType::Line)
-              .style(Style::default().fg(Color::Red))
+              .style(Style::default().fg(self.config.colors.write))
               .data(write_data),
           );
         }
@@ -592,7 +1095,7 @@ impl App {
             .block(Block::bordered().title("Disk I/O Rates"))
             .x_axis(
               ratatui::widgets::Axis::default()
-                .bounds([0.0, 100.0])
+                .bounds([0.0, self.config.history as f64])
                 .labels(vec![].into_iter().collect::<Vec<String>>()),
             )
             .y_axis(
@@ -625,14 +1128,14 @@ impl App {
         frame.render_widget(
           Paragraph::new(rates_text.join("\n"))
             .block(Block::bordered().title("Current I/O Rates"))
-            .style(Style::default().fg(Color::White)),
+            .style(Style::default().fg(self.config.colors.text)),
           chunks[1],
         );
       }
-      CurrentTab::Network => {
+      Tab::Network => {
         let chunks = Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).split(main_area[0]);
 
-        let network_data: Vec<(String, Vec<(f64, f64)>, Vec<(f64, f64)>)> = self
+        let network_data: Vec<RateSeries> = self
           .network_history
           .iter()
           .map(|(interface, history)| {
@@ -651,7 +1154,7 @@ impl App {
               .name(format!("{} RX", interface))
               .marker(Marker::Braille)
               .graph_type(GraphType::Line)
-              .style(Style::default().fg(Color::Green))
+              .style(Style::default().fg(self.config.colors.read))
               .data(rx_data),
           );
 
@@ -660,7 +1163,7 @@ impl App {
               .name(format!("{} TX", interface))
               .marker(Marker::Braille)
               .graph_type(GraphType::Line)
-              .style(Style::default().fg(Color::Red))
+              .style(Style::default().fg(self.config.colors.write))
               .data(tx_data),
           );
         }
@@ -676,7 +1179,7 @@ impl App {
             .block(Block::bordered().title("Network Traffic"))
             .x_axis(
               ratatui::widgets::Axis::default()
-                .bounds([0.0, 100.0])
+                .bounds([0.0, self.config.history as f64])
                 .labels(vec![].into_iter().collect::<Vec<String>>()),
             )
             .y_axis(
@@ -709,11 +1212,11 @@ impl App {
         frame.render_widget(
           Paragraph::new(rates_text.join("\n"))
             .block(Block::bordered().title("Current Network Rates"))
-            .style(Style::default().fg(Color::White)),
+            .style(Style::default().fg(self.config.colors.text)),
           chunks[1],
         );
       }
-      CurrentTab::Cpu => {
+      Tab::Cpu => {
         if self.cpu_usage.is_empty() {
           frame.render_widget(
             Paragraph::new("Loading CPU information...").block(Block::bordered().title("CPU")),
@@ -727,7 +1230,7 @@ impl App {
           .name("CPU Usage")
           .marker(Marker::Braille)
           .graph_type(GraphType::Line)
-          .style(Style::default().fg(Color::Cyan))
+          .style(Style::default().fg(self.config.colors.chart))
           .data(&self.cpu_history)];
 
         frame.render_widget(
@@ -735,7 +1238,7 @@ impl App {
             .block(Block::bordered().title("CPU Usage History"))
             .x_axis(
               ratatui::widgets::Axis::default()
-                .bounds([0.0, 100.0])
+                .bounds([0.0, self.config.history as f64])
                 .labels(vec![].into_iter().collect::<Vec<String>>()),
             )
             .y_axis(
@@ -753,7 +1256,7 @@ impl App {
         );
 
         let num_cores = self.cpu_usage.len();
-        let num_rows = (num_cores + 3) / 4;
+        let num_rows = num_cores.div_ceil(4);
         let gauge_constraints = vec![Constraint::Percentage(25); 4];
 
         let row_constraints = vec![Constraint::Percentage((100 / num_rows) as u16); num_rows];
@@ -769,7 +1272,7 @@ impl App {
                 frame.render_widget(
                   Gauge::default()
                     .block(Block::bordered().title(format!("CPU {}", core_idx)))
-                    .gauge_style(Style::default().fg(Color::Green))
+                    .gauge_style(Style::default().fg(self.config.colors.gauge))
                     .percent(usage as u16)
                     .label(format!("{:>5.1}%", usage)),
                   cores_in_row[col],
@@ -779,24 +1282,45 @@ impl App {
           }
         }
       }
-      _ => {}
     }
 
     let status_text = if self.status_message.is_some() {
       self.status_message.clone().unwrap_or_default()
-    } else if self.current_tab == CurrentTab::Processes && self.mode == Mode::Search {
-      self.pattern.clone()
+    } else if self.current_tab == Tab::Processes && self.mode == Mode::Search {
+      match &self.search_error {
+        Some(error) => format!("{}    ({})", self.search, error),
+        None => self.search.clone(),
+      }
     } else {
-      let info = self.sysinfo.as_ref().unwrap();
+      // nothing until the first sample is in
+      let used_ram = match &self.sysinfo {
+        Some(info) => format_size((info.totalram - info.freeram) * info.mem_unit as u64, BINARY),
+        None => "Loading...".to_string(),
+      };
+      let replay = match &self.replay {
+        Some(replay) => format!(
+          "[{} {}/{} {}] ",
+          if replay.paused { "paused" } else { "replay" },
+          replay.position + 1,
+          replay.snapshots.len(),
+          replay
+            .snapshots
+            .get(replay.position)
+            .map(|s| pro::record::format_timestamp(s.timestamp))
+            .unwrap_or_default()
+        ),
+        None => String::new(),
+      };
       format!(
-        "Processes: {}, CPU: {}, Free Ram: {}\t\t Press (?) for help",
+        "{}Processes: {}, CPU: {}, Free Ram: {}\t\t Press (?) for help",
+        replay,
         self.processes.len(),
         if self.cpu_usage.is_empty() {
           "Loading...".to_string()
         } else {
           format!("{:.1}%", self.cpu_usage[0])
         },
-        format_size((info.totalram - info.freeram) * info.mem_unit as u64, BINARY),
+        used_ram,
       )
     };
 
@@ -805,66 +1329,370 @@ impl App {
         .style(if self.status_message.is_none() {
           Style::default()
         } else if self.status_message_error {
-          Style::default().fg(Color::Red)
+          Style::default().fg(self.config.colors.error)
         } else {
-          Style::default().fg(Color::Green)
+          Style::default().fg(self.config.colors.ok)
         })
         .block(Block::default()),
-      main_area[1],
+      main_area[2],
     );
+
+    if !firing.is_empty() {
+      let lines: Vec<String> = firing
+        .iter()
+        .map(|alert| {
+          format!(
+            "{}  since {}",
+            alert.message,
+            pro::record::format_timestamp(alert.since)
+          )
+        })
+        .collect();
+      frame.render_widget(
+        Paragraph::new(lines.join("\n"))
+          .style(Style::default().fg(self.config.colors.error))
+          .block(Block::bordered().title(format!("Alerts ({})", firing.len()))),
+        main_area[1],
+      );
+    }
+
+    if let Some(picker) = &mut self.signal_picker {
+      Self::draw_signal_picker(frame, main_area[0], picker, &self.config.colors);
+    }
+    if self.column_setup.is_some() {
+      self.draw_column_setup(frame, main_area[0]);
+    }
+  }
+
+  // Shown columns in order, then the hidden ones
+  fn setup_entries(&self) -> Vec<(pro::Field, bool)> {
+    let shown = self.config.columns.iter().map(|&field| (field, true));
+    let hidden = pro::Field::ALL
+      .into_iter()
+      .filter(|field| !self.config.columns.contains(field))
+      .map(|field| (field, false));
+    shown.chain(hidden).collect()
+  }
+
+  fn draw_column_setup(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+    let entries = self.setup_entries();
+    let items: Vec<String> = entries
+      .iter()
+      .map(|&(field, shown)| {
+        format!(
+          "[{}] {:<10} {:<10} {:>3}",
+          if shown { 'x' } else { ' ' },
+          field.title(),
+          field.name(),
+          self.column_width(field)
+        )
+      })
+      .collect();
+    let title = "Columns: [space] show/hide, [/] move, ←/→ width, [esc] close";
+    let [area] = Layout::horizontal([Constraint::Length(title.chars().count() as u16 + 4)])
+      .flex(Flex::Center)
+      .areas(area);
+    let [area] = Layout::vertical([Constraint::Max(entries.len() as u16 + 2)])
+      .flex(Flex::Center)
+      .areas(area);
+    let Some(setup) = &mut self.column_setup else {
+      return;
+    };
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(
+      List::new(items)
+        .block(Block::bordered().title(title))
+        .highlight_symbol(">>")
+        .highlight_style(Style::default().bg(self.config.colors.selection)),
+      area,
+      &mut setup.list_state,
+    );
+  }
+
+  fn handle_column_setup_key(&mut self, key_event: KeyEvent) {
+    let Some(selected) = self.column_setup.as_ref().map(|setup| setup.list_state.selected()) else {
+      return;
+    };
+    let entries = self.setup_entries();
+    let (field, shown) = entries[selected.unwrap_or_default().min(entries.len() - 1)];
+    let width = self.column_width(field);
+    let Some(setup) = &mut self.column_setup else {
+      return;
+    };
+    let columns = &mut self.config.columns;
+    let position = columns.iter().position(|&f| f == field);
+    match key_event.code {
+      KeyCode::Up => setup.list_state.select_previous(),
+      KeyCode::Down => setup.list_state.select_next(),
+      KeyCode::Esc | KeyCode::F(2) => self.column_setup = None,
+      KeyCode::Char(c) if c == self.config.keys.setup => self.column_setup = None,
+      // keep at least one column
+      KeyCode::Char(' ') | KeyCode::Enter if shown && columns.len() > 1 => {
+        columns.retain(|&f| f != field);
+        // follow the field down into the hidden ones
+        let row = self.setup_entries().iter().position(|&(f, _)| f == field);
+        if let Some(setup) = &mut self.column_setup {
+          setup.list_state.select(row);
+        }
+      }
+      KeyCode::Char(' ') | KeyCode::Enter if !shown => {
+        columns.push(field);
+        setup.list_state.select(Some(columns.len() - 1));
+      }
+      KeyCode::Char('[') => {
+        if let Some(position) = position.filter(|&p| p > 0) {
+          columns.swap(position, position - 1);
+          setup.list_state.select(Some(position - 1));
+        }
+      }
+      KeyCode::Char(']') => {
+        if let Some(position) = position.filter(|&p| p + 1 < columns.len()) {
+          columns.swap(position, position + 1);
+          setup.list_state.select(Some(position + 1));
+        }
+      }
+      KeyCode::Left | KeyCode::Right => {
+        let width = match key_event.code {
+          KeyCode::Left => width.saturating_sub(1).max(MIN_COLUMN_WIDTH),
+          _ => width.saturating_add(1),
+        };
+        // only widths that differ from the default end up in the config
+        if width == default_column_width(field) {
+          self.config.widths.remove(&field);
+        } else {
+          self.config.widths.insert(field, width);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn column_width(&self, field: pro::Field) -> u16 {
+    self
+      .config
+      .widths
+      .get(&field)
+      .copied()
+      .unwrap_or_else(|| default_column_width(field))
+  }
+
+  fn draw_signal_picker(frame: &mut Frame, area: ratatui::layout::Rect, picker: &mut SignalPicker, colors: &Colors) {
+    let title = format!("Signal for {} ({})", picker.name, picker.pid);
+    let [area] = Layout::horizontal([Constraint::Length(title.len().max(36) as u16 + 4)])
+      .flex(Flex::Center)
+      .areas(area);
+
+    if picker.confirming {
+      let [area] = Layout::vertical([Constraint::Length(3)]).flex(Flex::Center).areas(area);
+      frame.render_widget(Clear, area);
+      frame.render_widget(
+        Paragraph::new(format!(
+          "Send {} to {} ({})? [y/N]",
+          pro::signal_name(picker.signal()),
+          picker.name,
+          picker.pid
+        ))
+        .block(Block::bordered().title("Confirm").fg(colors.error)),
+        area,
+      );
+    } else {
+      let [area] = Layout::vertical([Constraint::Max(pro::SIGNALS.len() as u16 + 2)])
+        .flex(Flex::Center)
+        .areas(area);
+      let items = pro::SIGNALS.map(|(name, number)| format!("{:>2} SIG{}", number, name));
+      frame.render_widget(Clear, area);
+      frame.render_stateful_widget(
+        List::new(items)
+          .block(Block::bordered().title(title))
+          .highlight_symbol(">>")
+          .highlight_style(Style::default().bg(colors.selection)),
+        area,
+        &mut picker.list_state,
+      );
+    }
+  }
+
+  fn handle_signal_picker_key(&mut self, key_event: KeyEvent) {
+    let Some(picker) = &mut self.signal_picker else {
+      return;
+    };
+    if picker.confirming {
+      if let KeyCode::Char('y' | 'Y') = key_event.code {
+        let _ = pro::kill_process(picker.pid, picker.signal(), Some(&self.output_tx));
+      }
+      self.signal_picker = None;
+      return;
+    }
+    match key_event.code {
+      KeyCode::Up => picker.list_state.select_previous(),
+      KeyCode::Down => picker.list_state.select_next(),
+      KeyCode::Enter => picker.confirming = true,
+      KeyCode::Esc => self.signal_picker = None,
+      _ => {}
+    }
   }
 
   fn handle_key_event(&mut self, key_event: KeyEvent) {
+    match self.mode {
+      Mode::Search => return self.handle_search_key(key_event),
+      Mode::Sort => return self.handle_sort_key(key_event),
+      Mode::Normal => {}
+    }
+    if self.signal_picker.is_some() {
+      self.handle_signal_picker_key(key_event);
+      return;
+    }
+    if self.column_setup.is_some() {
+      self.handle_column_setup_key(key_event);
+      return;
+    }
+    if self.detail.is_some() && self.current_tab == Tab::Processes {
+      self.handle_detail_key(key_event);
+      return;
+    }
+    if self.handle_replay_key(key_event) {
+      return;
+    }
+    let keys = self.config.keys;
     match key_event.code {
       KeyCode::Up => self.decrement_list(),
       KeyCode::Down => self.increment_list(),
       KeyCode::Left => self.prev_tab(),
       KeyCode::Right => self.next_tab(),
-      KeyCode::Char('?') => {
-        self.status_message = Some(
-          "[s]ort by: [n]ame, [p]id, [u]ser, [m]em; [/] search; flip [a]scending; [G]oto bottom; [k]ill; [q]uit, [n/N]ice+/-"
-            .to_string(),
-        );
+      KeyCode::Esc if self.threads_of.is_some() => self.toggle_threads(),
+      KeyCode::Enter if self.current_tab == Tab::Processes && self.threads_of.is_none() => self.open_detail(),
+      KeyCode::F(2) => self.open_column_setup(),
+      KeyCode::Char(key) => self.handle_action_key(key, keys),
+      _ => {}
+    }
+  }
+
+  fn handle_action_key(&mut self, key: char, keys: Keys) {
+    match key {
+      _ if key == keys.quit => self.exit = true,
+      _ if key == keys.search => {
+        // typing replaces the search, Esc clears it
+        self.search.clear();
+        self.apply_search();
+        self.mode = Mode::Search;
+      }
+      _ if key == keys.sort => self.mode = Mode::Sort,
+      _ if key == keys.help => {
+        let sort_keys: Vec<String> = pro::Field::ALL
+          .iter()
+          .map(|field| format!("{}={}", field.key(), field))
+          .collect();
+        self.status_message = Some(format!(
+          "[{}] sort by: {}; [{}] search ([{}:] filter expression); [{}] flip ascending; [{}] command line; [{}] tree ([{}/{}] collapse/expand); [{}] threads; [{}] goto bottom; [enter] details; [{}] kill (pick a signal); [{}/{}] nice+/-; [{}/F2] columns; [{}] save layout; [{}] quit; replay: [{}] pause, [{}/{}] step, [{}/{}] seek 1m",
+          keys.sort,
+          sort_keys.join(" "),
+          keys.search,
+          keys.search,
+          keys.ascending,
+          keys.command,
+          keys.tree,
+          keys.collapse,
+          keys.expand,
+          keys.threads,
+          keys.bottom,
+          keys.kill,
+          keys.nice,
+          keys.unnice,
+          keys.setup,
+          keys.save,
+          keys.quit,
+          keys.pause,
+          keys.step_back,
+          keys.step,
+          keys.seek_back,
+          keys.seek,
+        ));
       }
-      KeyCode::Char('G') => {
-        self.table_state.select(Some(self.accessible_processes.len() - 1));
+      _ if key == keys.bottom => {
+        let len = if self.threads_of.is_some() {
+          self.threads.len()
+        } else {
+          self.accessible_processes.len()
+        };
+        self.table_state.select(Some(len.saturating_sub(1)));
       }
-      KeyCode::Char('n') => {
+      _ if key == keys.threads => self.toggle_threads(),
+      _ if key == keys.tree => self.tree_mode = !self.tree_mode,
+      _ if key == keys.collapse && self.tree_mode => self.set_collapsed(true),
+      _ if key == keys.expand && self.tree_mode => self.set_collapsed(false),
+      _ if key == keys.save => self.save_layout(),
+      _ if key == keys.setup => self.open_column_setup(),
+      // the actions below work on the process list
+      _ if self.threads_of.is_some() && [keys.nice, keys.unnice, keys.kill].contains(&key) => {}
+      _ if key == keys.nice => {
         if let Some(selection) = self.table_state.selected() {
           let sel = &self.accessible_processes[selection];
-          pro::set_priority(sel.pid, sel.priority + 1, Some(&self.output_tx));
+          let _ = pro::set_priority(sel.pid, sel.priority + 1, Some(&self.output_tx));
         }
       }
-      KeyCode::Char('N') => {
+      _ if key == keys.unnice => {
         if let Some(selection) = self.table_state.selected() {
           let sel = &self.accessible_processes[selection];
-          pro::set_priority(sel.pid, sel.priority - 1, Some(&self.output_tx));
+          let _ = pro::set_priority(sel.pid, sel.priority - 1, Some(&self.output_tx));
         }
       }
-      KeyCode::Char('k') => {
-        if let Some(selection) = self.table_state.selected() {
-          let pid = self.accessible_processes[selection].pid;
-          pro::kill_process(pid, 9, Some(&self.output_tx));
+      _ if key == keys.kill => {
+        if let Some(process) = self.selected_process() {
+          self.signal_picker = Some(SignalPicker::new(process.pid, process.name.clone()));
         }
       }
-      KeyCode::Char('a') => {
+      _ if key == keys.ascending => {
         self.ascending = !self.ascending;
       }
+      _ if key == keys.command => {
+        self.show_command = !self.show_command;
+      }
       _ => {}
     }
   }
 
+  // Writes the sort order and columns on screen to the config file
+  fn save_layout(&mut self) {
+    let Some(path) = self.config_path.clone() else {
+      return;
+    };
+    // the columns and widths are edited in place by the column setup
+    self.config.sort_by = self.sort_by;
+    self.config.descending = !self.ascending;
+    let (message, is_error) = match self.config.save_layout(&path) {
+      Ok(()) => (format!("Saved the layout to {}", path.display()), false),
+      Err(e) => (format!("Failed to save the layout: {}", e), true),
+    };
+    self.status_message = Some(message);
+    self.status_message_error = is_error;
+    self.status_message_time = Some(std::time::Instant::now());
+  }
+
+  fn open_column_setup(&mut self) {
+    self.column_setup = Some(ColumnSetup {
+      list_state: ListState::default().with_selected(Some(0)),
+    });
+  }
+
+  // The columns of the process table, with the command line instead of the name if toggled
+  fn columns(&self) -> Vec<pro::Field> {
+    self
+      .config
+      .columns
+      .iter()
+      .map(|&field| match field {
+        pro::Field::Name if self.show_command => pro::Field::Command,
+        field => field,
+      })
+      .collect()
+  }
+
   fn next_tab(&mut self) {
-    self.current_tab =
-      CurrentTab::try_from((self.current_tab as u8 + 1) % CurrentTab::Max as u8).expect("Failed to get next tab")
+    self.current_tab = self.current_tab.next();
   }
 
   fn prev_tab(&mut self) {
-    self.current_tab = if self.current_tab as u8 != 0 {
-      CurrentTab::try_from((self.current_tab as u8 - 1) % CurrentTab::Max as u8).expect("Failed to get previous tab")
-    } else {
-      CurrentTab::try_from(CurrentTab::Max as u8 - 1).expect("Failed to get last tab")
-    }
+    self.current_tab = self.current_tab.previous();
   }
 
   fn increment_list(&mut self) {
@@ -875,24 +1703,96 @@ impl App {
     self.table_state.select_previous();
   }
 
-  fn list_processes(&mut self) -> io::Result<()> {
-    self.accessible_processes = list_processes(
+  fn selected_process(&self) -> Option<&pro::ProcessInfo> {
+    self.accessible_processes.get(self.table_state.selected()?)
+  }
+
+  fn list_processes(&mut self) -> pro::Result<()> {
+    let selected_pid = self.selected_process().map(|p| p.pid);
+    let processes = list_processes(
       self.processes.clone(),
       self.from,
       self.nprocs,
-      &self.sort_by,
+      self.sort_by,
       self.ascending,
-      &self.filter_by,
-      &self.pattern,
-      self.exact_match,
+      Some(&self.filter),
     )?;
+
+    if self.tree_mode {
+      // siblings keep the sort order of the flat list
+      let lines = pro::build_tree(&processes, 0).lines(&self.collapsed);
+      let mut by_pid: HashMap<pid_t, pro::ProcessInfo> = processes.into_iter().map(|p| (p.pid, p)).collect();
+      self.accessible_processes = lines.iter().filter_map(|line| by_pid.remove(&line.pid)).collect();
+      self.tree_lines = lines;
+    } else {
+      self.accessible_processes = processes;
+      self.tree_lines.clear();
+    }
+
+    // follow the selected process when rows move around
+    if self.threads_of.is_none() {
+      if let Some(index) = selected_pid.and_then(|pid| self.accessible_processes.iter().position(|p| p.pid == pid)) {
+        self.table_state.select(Some(index));
+      }
+    }
     Ok(())
   }
+
+  fn set_collapsed(&mut self, collapsed: bool) {
+    if let Some(pid) = self.selected_process().map(|p| p.pid) {
+      if collapsed {
+        self.collapsed.insert(pid);
+      } else {
+        self.collapsed.remove(&pid);
+      }
+    }
+  }
+}
+
+const MIN_COLUMN_WIDTH: u16 = 3;
+
+// Width of a process table column unless configured, enough for the title and usual values
+fn default_column_width(field: pro::Field) -> u16 {
+  match field {
+    pro::Field::State | pro::Field::Priority => 5,
+    pro::Field::Cpu => 6,
+    pro::Field::Pid | pro::Field::Ppid | pro::Field::Threads => 7,
+    pro::Field::User | pro::Field::Memory => 8,
+    pro::Field::VirtualMemory | pro::Field::UserTime | pro::Field::SystemTime => 9,
+    pro::Field::IoRead | pro::Field::IoWrite => 11,
+    pro::Field::StartTime => 16,
+    pro::Field::Name | pro::Field::Command | pro::Field::Exe | pro::Field::Cwd | pro::Field::Cgroup => 30,
+  }
+}
+
+// Text columns take up the space left, at least their width
+fn is_text_column(field: pro::Field) -> bool {
+  matches!(
+    field,
+    pro::Field::Name | pro::Field::Command | pro::Field::Exe | pro::Field::Cwd | pro::Field::Cgroup
+  )
+}
+
+/// Runs the TUI, saving the layout to `config_path` when asked to.
+pub fn run(
+  source: pro::ProcSource,
+  rules: Vec<pro::alert::Rule>,
+  config: Config,
+  config_path: PathBuf,
+) -> io::Result<()> {
+  let mut terminal = ratatui::init();
+  let mut app = App::new(source, config);
+  app.config_path = Some(config_path);
+  app.alerts = pro::alert::Alerts::new(rules);
+  app.start_sampling();
+  let app_result = app.run(&mut terminal);
+  ratatui::restore();
+  app_result
 }
 
-pub fn run() -> io::Result<()> {
+pub fn replay(snapshots: Vec<pro::Snapshot>, refresh_rate: std::time::Duration, config: Config) -> io::Result<()> {
   let mut terminal = ratatui::init();
-  let app_result = App::new().run(&mut terminal);
+  let app_result = App::replay(snapshots, refresh_rate, config).run(&mut terminal);
   ratatui::restore();
   app_result
 }